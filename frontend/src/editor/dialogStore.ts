@@ -5,7 +5,7 @@ export interface DialogConfig {
   placeholder: string;
   defaultValue: string;
   label?: string;
-  type?: "text" | "number" | "textarea";
+  type?: "text" | "number" | "textarea" | "password";
   min?: number;
   max?: number;
   multiline?: boolean; // For textarea support
@@ -0,0 +1,46 @@
+import { invoke, convertFileSrc } from "@tauri-apps/api/core";
+
+export interface AssetInfo {
+  name: string;
+  size: number;
+  mimeType: string;
+}
+
+/**
+ * Copy a file from disk into the assets/ folder of a .flm archive
+ */
+export async function addAsset(filePath: string, sourcePath: string): Promise<AssetInfo> {
+  return await invoke<AssetInfo>("add_asset", {
+    path: filePath,
+    sourcePath,
+  });
+}
+
+/**
+ * Store raw bytes (e.g. a pasted image) in the assets/ folder of a .flm archive
+ */
+export async function addAssetBytes(
+  filePath: string,
+  fileName: string,
+  bytes: Uint8Array
+): Promise<AssetInfo> {
+  return await invoke<AssetInfo>("add_asset_bytes", {
+    path: filePath,
+    fileName,
+    bytes: Array.from(bytes),
+  });
+}
+
+/**
+ * List assets stored in a .flm archive
+ */
+export async function listAssets(filePath: string): Promise<AssetInfo[]> {
+  return await invoke<AssetInfo[]>("list_assets", { path: filePath });
+}
+
+/**
+ * Webview URL for an asset reference (e.g. "assets/images/<hash>.png")
+ */
+export function assetUrl(filePath: string, name: string): string {
+  return `${convertFileSrc(name, "flmasset")}?archive=${encodeURIComponent(filePath)}`;
+}
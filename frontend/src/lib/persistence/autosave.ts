@@ -1,6 +1,8 @@
 import type { EditorState } from "prosemirror-state";
 import { fileStore } from "./fileStore";
 import { saveToFile } from "./fileIO";
+import { scheduleJournal } from "./recovery";
+import { recordWriting } from "./goals";
 import { get } from "svelte/store";
 
 let autosaveTimer: ReturnType<typeof setTimeout> | null = null;
@@ -10,6 +12,9 @@ const AUTOSAVE_DELAY = 2000; // 2 seconds
  * Schedule autosave (debounced)
  */
 export function scheduleAutosave(state: EditorState): void {
+  // Untitled documents are only covered by the recovery journal
+  scheduleJournal(state);
+
   // Only autosave if file path exists
   const currentState = get(fileStore);
   const currentPath = currentState.path;
@@ -30,6 +35,7 @@ export function scheduleAutosave(state: EditorState): void {
     try {
       await saveToFile(currentPath, state);
       console.log("Autosave completed");
+      await recordWriting(currentPath, state);
     } catch (error) {
       console.error("Autosave failed:", error);
       // Don't throw - autosave failures should be silent
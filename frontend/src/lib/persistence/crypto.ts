@@ -0,0 +1,40 @@
+import { invoke } from "@tauri-apps/api/core";
+import { errorCode } from "./errors";
+
+/**
+ * Whether loading failed because the file is encrypted; ask for the
+ * password and pass it to loadFromFile
+ */
+export function isPasswordRequired(error: unknown): boolean {
+  return errorCode(error) === "PASSWORD_REQUIRED";
+}
+
+export function isWrongPassword(error: unknown): boolean {
+  return errorCode(error) === "WRONG_PASSWORD";
+}
+
+export async function isEncrypted(filePath: string): Promise<boolean> {
+  return await invoke<boolean>("is_flm_encrypted", { path: filePath });
+}
+
+/**
+ * Set, change or (with newPassword null) remove the password of a .flm file
+ */
+export async function setPassword(
+  filePath: string,
+  currentPassword: string | null,
+  newPassword: string | null
+): Promise<void> {
+  await invoke("set_flm_password", {
+    path: filePath,
+    currentPassword,
+    newPassword,
+  });
+}
+
+/**
+ * Forget the key of an encrypted file, e.g. after closing it
+ */
+export async function lockFile(filePath: string): Promise<void> {
+  await invoke("lock_flm", { path: filePath });
+}
@@ -0,0 +1,65 @@
+import { invoke } from "@tauri-apps/api/core";
+
+export type ChangeKind = "added" | "removed" | "modified" | "renumbered";
+
+export interface Segment {
+  kind: "unchanged" | "added" | "removed";
+  text: string;
+}
+
+export interface BlockChange {
+  kind: ChangeKind;
+  oldIndex: number | null;
+  newIndex: number | null;
+  blockType: string;
+  before: string | null;
+  after: string | null;
+  words: Segment[];
+}
+
+export interface NoteChange {
+  kind: ChangeKind;
+  noteId: string;
+  oldNumber: number | null;
+  newNumber: number | null;
+  before: string | null;
+  after: string | null;
+  words: Segment[];
+}
+
+export interface DiffSummary {
+  blocksAdded: number;
+  blocksRemoved: number;
+  blocksModified: number;
+  wordsAdded: number;
+  wordsRemoved: number;
+  notesChanged: number;
+}
+
+export interface DocumentDiff {
+  changes: BlockChange[];
+  notes: NoteChange[];
+  summary: DiffSummary;
+  /** Redline of the new version, with its own <style> */
+  html: string;
+}
+
+/** A .flm file, or one of the revisions in its history */
+export interface DiffSource {
+  path: string;
+  revision?: string;
+}
+
+/**
+ * Compare two documents block by block and word by word
+ */
+export async function diffDocuments(
+  oldSource: DiffSource,
+  newSource: DiffSource
+): Promise<DocumentDiff> {
+  const source = (s: DiffSource) => ({ path: s.path, revision: s.revision ?? null });
+  return await invoke<DocumentDiff>("diff_documents", {
+    old: source(oldSource),
+    new: source(newSource),
+  });
+}
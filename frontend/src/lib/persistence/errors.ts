@@ -0,0 +1,89 @@
+/**
+ * Stable identifiers of the errors returned by backend commands
+ */
+export type FlowErrorCode =
+  | "IO"
+  | "NOT_FOUND"
+  | "PERMISSION_DENIED"
+  | "CORRUPT_ARCHIVE"
+  | "MISSING_DOCUMENT"
+  | "MISSING_ENTRY"
+  | "INVALID_JSON"
+  | "WRONG_FORMAT"
+  | "UNSUPPORTED_VERSION"
+  | "INVALID_SCHEMA"
+  | "TOO_MANY_ENTRIES"
+  | "ENTRY_TOO_LARGE"
+  | "ARCHIVE_TOO_LARGE"
+  | "COMPRESSION_RATIO_TOO_HIGH"
+  | "NESTING_TOO_DEEP"
+  | "UNSAFE_ENTRY_NAME"
+  | "PASSWORD_REQUIRED"
+  | "WRONG_PASSWORD"
+  | "EXTERNAL_CHANGE"
+  | "INVALID_ARGUMENT"
+  | "OTHER";
+
+/**
+ * Part of a document that doesn't match the editor schema
+ */
+export interface SchemaViolation {
+  /** JSON path in document.json, e.g. "$.content.doc.content[2]" */
+  path: string;
+  kind:
+    | "unknownNode"
+    | "unknownMark"
+    | "invalidContent"
+    | "invalidMark"
+    | "missingContent"
+    | "invalidText"
+    | "invalidAttribute";
+  message: string;
+}
+
+/**
+ * Error rejected by every backend command
+ */
+export interface FlowError {
+  code: FlowErrorCode;
+  /** Human-readable description */
+  message: string;
+  /** File the error is about */
+  path?: string;
+  /** Archive entry, e.g. for MISSING_ENTRY and INVALID_JSON */
+  entry?: string;
+  /** Versions, for UNSUPPORTED_VERSION */
+  found?: string;
+  supported?: string;
+  /** Value found and the archive limit it exceeds */
+  actual?: number;
+  limit?: number;
+  /** Problems found, for INVALID_SCHEMA */
+  violations?: SchemaViolation[];
+}
+
+export function isFlowError(error: unknown): error is FlowError {
+  return (
+    typeof error === "object" &&
+    error !== null &&
+    typeof (error as FlowError).code === "string" &&
+    typeof (error as FlowError).message === "string"
+  );
+}
+
+/**
+ * Code of a backend error, or null for anything else
+ */
+export function errorCode(error: unknown): FlowErrorCode | null {
+  return isFlowError(error) ? error.code : null;
+}
+
+/**
+ * Message to show for any caught error
+ */
+export function errorMessage(error: unknown): string {
+  if (isFlowError(error) || error instanceof Error) {
+    return error.message;
+  }
+  return String(error);
+}
@@ -0,0 +1,154 @@
+import { save } from "@tauri-apps/plugin-dialog";
+import { invoke } from "@tauri-apps/api/core";
+
+/**
+ * Ask for a destination and export a saved .flm file as Markdown
+ */
+export async function exportMarkdown(filePath: string): Promise<string | null> {
+  const outputPath = await save({
+    defaultPath: filePath.replace(/\.flm$/i, ".md"),
+    filters: [
+      {
+        name: "Markdown",
+        extensions: ["md"],
+      },
+    ],
+  });
+
+  if (!outputPath) {
+    return null; // User cancelled
+  }
+
+  await invoke("export_markdown", {
+    path: filePath,
+    outputPath,
+  });
+  return outputPath;
+}
+
+export interface HtmlExportOptions {
+  title?: string;
+  embedTheme?: boolean;
+  css?: string;
+  inlineImages?: boolean;
+}
+
+/**
+ * Ask for a destination and export a saved .flm file as a standalone HTML page
+ */
+export async function exportHtml(
+  filePath: string,
+  options: HtmlExportOptions = {}
+): Promise<string | null> {
+  const outputPath = await save({
+    defaultPath: filePath.replace(/\.flm$/i, ".html"),
+    filters: [
+      {
+        name: "HTML",
+        extensions: ["html", "htm"],
+      },
+    ],
+  });
+
+  if (!outputPath) {
+    return null; // User cancelled
+  }
+
+  await invoke("export_html", {
+    path: filePath,
+    outputPath,
+    options,
+  });
+  return outputPath;
+}
+
+export interface EpubExportOptions {
+  title?: string;
+  author?: string;
+  language?: string;
+  embedTheme?: boolean;
+}
+
+/**
+ * Ask for a destination and export a saved .flm file as an EPUB 3 book
+ */
+export async function exportEpub(
+  filePath: string,
+  options: EpubExportOptions = {}
+): Promise<string | null> {
+  const outputPath = await save({
+    defaultPath: filePath.replace(/\.flm$/i, ".epub"),
+    filters: [
+      {
+        name: "EPUB",
+        extensions: ["epub"],
+      },
+    ],
+  });
+
+  if (!outputPath) {
+    return null; // User cancelled
+  }
+
+  await invoke("export_epub", {
+    path: filePath,
+    outputPath,
+    options,
+  });
+  return outputPath;
+}
+
+export type PdfPageSize =
+  | "a4"
+  | "a5"
+  | "letter"
+  | "legal"
+  | { custom: { width: number; height: number } };
+
+export interface PdfExportOptions {
+  title?: string;
+  author?: string;
+  pageSize?: PdfPageSize;
+  /** Margins in millimetres */
+  margins?: { top?: number; right?: number; bottom?: number; left?: number };
+  /** Body text size in points */
+  fontSize?: number;
+  fonts?: {
+    family?: "serif" | "sans";
+    regular?: string;
+    bold?: string;
+    italic?: string;
+    boldItalic?: string;
+    monospace?: string;
+  };
+  footnotes?: "page" | "endnotes";
+}
+
+/**
+ * Ask for a destination and export a saved .flm file as a PDF
+ */
+export async function exportPdf(
+  filePath: string,
+  options: PdfExportOptions = {}
+): Promise<string | null> {
+  const outputPath = await save({
+    defaultPath: filePath.replace(/\.flm$/i, ".pdf"),
+    filters: [
+      {
+        name: "PDF",
+        extensions: ["pdf"],
+      },
+    ],
+  });
+
+  if (!outputPath) {
+    return null; // User cancelled
+  }
+
+  await invoke("export_pdf", {
+    path: filePath,
+    outputPath,
+    options,
+  });
+  return outputPath;
+}
@@ -3,6 +3,10 @@ import { invoke } from "@tauri-apps/api/core";
 import type { EditorState } from "prosemirror-state";
 import { serializeDocument, deserializeDocument, type FlowmarkDocument } from "./serializer";
 import { fileStore } from "./fileStore";
+import { errorCode, type FlowError } from "./errors";
+import { isPasswordRequired, isWrongPassword } from "./crypto";
+import { dialogStore } from "../../editor/dialogStore";
+import type { NoteReport } from "./notes";
 import { get } from "svelte/store";
 
 /**
@@ -33,23 +37,27 @@ export async function saveAs(state: EditorState): Promise<string | null> {
 }
 
 /**
- * Save document to existing file path
+ * Save document to existing file path.
+ * Fails if the file was changed by another program since it was opened,
+ * unless `force` is set. Notes are renumbered and references to missing
+ * notes dropped on the way; notes nothing refers to are only removed with
+ * `removeOrphanedNotes`.
  */
-export async function saveToFile(filePath: string, state: EditorState): Promise<void> {
+export async function saveToFile(
+  filePath: string,
+  state: EditorState,
+  force = false,
+  removeOrphanedNotes = false
+): Promise<NoteReport> {
   try {
     // Get current file state to preserve createdAt
     const currentState = get(fileStore);
     const currentPath = currentState.path;
     let createdAt: string | undefined;
-    
-    // If saving to the same file, try to preserve createdAt
-    if (currentPath === filePath) {
-      try {
-        const existingDoc = await loadFromFile(filePath);
-        createdAt = existingDoc.createdAt;
-      } catch {
-        // File doesn't exist or can't be read, use new timestamp
-      }
+
+    // If saving to the same file, preserve createdAt
+    if (currentPath === filePath && currentState.createdAt) {
+      createdAt = currentState.createdAt;
     }
 
     // Serialize document to JSON (no ZIP handling here)
@@ -57,13 +65,19 @@ export async function saveToFile(filePath: string, state: EditorState): Promise<
     const documentJson = JSON.stringify(doc, null, 2);
 
     // Call Rust backend to create .flm ZIP archive
-    await invoke("save_flm", {
+    const report = await invoke<NoteReport>("save_flm", {
       path: filePath,
       documentJson: documentJson,
+      force,
+      removeOrphanedNotes,
+      // Saving an encrypted document under a new path keeps it encrypted
+      sourcePath: currentPath,
     });
 
     fileStore.setPath(filePath);
+    fileStore.setCreatedAt(doc.createdAt);
     fileStore.setDirty(false);
+    return report;
   } catch (error) {
     console.error("Error writing file:", error);
     throw error;
@@ -71,13 +85,23 @@ export async function saveToFile(filePath: string, state: EditorState): Promise<
 }
 
 /**
- * Load document from .flm file
+ * Load document from .flm file. Encrypted files need their password the
+ * first time they are opened (see isPasswordRequired). Small departures from
+ * the editor schema are fixed on the way; documents that would need their
+ * structure changed are rejected unless fixSchema is set, in which case they
+ * are fixed and marked as modified (see isInvalidSchema).
  */
-export async function loadFromFile(filePath: string): Promise<FlowmarkDocument> {
+export async function loadFromFile(
+  filePath: string,
+  password?: string,
+  fixSchema = false
+): Promise<FlowmarkDocument> {
   try {
     // Call Rust backend to extract document.json from .flm ZIP
     const documentJson = await invoke<string>("load_flm", {
       path: filePath,
+      password: password ?? null,
+      fixSchema,
     });
 
     // Parse JSON
@@ -85,7 +109,8 @@ export async function loadFromFile(filePath: string): Promise<FlowmarkDocument>
 
     // Update file store
     fileStore.setPath(filePath);
-    fileStore.setDirty(false);
+    fileStore.setCreatedAt(doc.createdAt);
+    fileStore.setDirty(fixSchema);
 
     return doc;
   } catch (error) {
@@ -95,7 +120,8 @@ export async function loadFromFile(filePath: string): Promise<FlowmarkDocument>
 }
 
 /**
- * Open file dialog and load document
+ * Open file dialog and load document, asking for the password of
+ * encrypted files and whether to fix documents the editor can't load
  */
 export async function openFile(): Promise<FlowmarkDocument | null> {
   try {
@@ -113,13 +139,68 @@ export async function openFile(): Promise<FlowmarkDocument | null> {
       return null; // User cancelled
     }
 
-    return await loadFromFile(filePath);
+    return await loadWithPrompts(filePath);
   } catch (error) {
     console.error("Error opening file:", error);
     throw error;
   }
 }
 
+/**
+ * Load a file, asking for its password until the right one is entered and
+ * offering to fix it if it doesn't match the editor schema. Null if the
+ * user cancels.
+ */
+async function loadWithPrompts(filePath: string): Promise<FlowmarkDocument | null> {
+  let password: string | undefined;
+  let fixSchema = false;
+  for (;;) {
+    let label: string;
+    try {
+      return await loadFromFile(filePath, password, fixSchema);
+    } catch (error) {
+      if (isInvalidSchema(error) && !fixSchema) {
+        if (!confirmSchemaFix(error as FlowError)) {
+          return null;
+        }
+        fixSchema = true;
+        continue;
+      } else if (isPasswordRequired(error)) {
+        label = "This file is encrypted. Enter its password:";
+      } else if (isWrongPassword(error)) {
+        label = "Incorrect password. Try again:";
+      } else {
+        throw error;
+      }
+    }
+
+    const entered = await dialogStore.show({
+      title: "Password",
+      placeholder: "Password",
+      defaultValue: "",
+      label,
+      type: "password",
+    });
+    if (entered === null) {
+      return null; // User cancelled
+    }
+    password = entered;
+  }
+}
+
+function confirmSchemaFix(error: FlowError): boolean {
+  const violations = error.violations ?? [];
+  const listed = violations
+    .slice(0, 5)
+    .map((violation) => `- ${violation.message} (${violation.path})`)
+    .join("\n");
+  const more = violations.length > 5 ? `\n…and ${violations.length - 5} more` : "";
+  return confirm(
+    `This file contains content the editor can't display:\n\n${listed}${more}\n\n` +
+      "Open it anyway with the content fixed? The file itself only changes when you save."
+  );
+}
+
 /**
  * Save document (save as if no path, save to path if exists)
  */
@@ -137,3 +218,49 @@ export async function saveDocument(state: EditorState): Promise<string | null> {
   }
 }
 
+/**
+ * Open a Markdown file and convert it to a FlowMark document.
+ * With an output path the result is also written there as a .flm file.
+ */
+export async function importMarkdown(outputPath?: string): Promise<FlowmarkDocument | null> {
+  try {
+    const filePath = await open({
+      multiple: false,
+      filters: [
+        {
+          name: "Markdown",
+          extensions: ["md", "markdown"],
+        },
+      ],
+    });
+
+    if (!filePath || typeof filePath !== "string") {
+      return null; // User cancelled
+    }
+
+    const documentJson = await invoke<string>("import_markdown", {
+      path: filePath,
+      outputPath: outputPath ?? null,
+    });
+    const doc = JSON.parse(documentJson) as FlowmarkDocument;
+
+    if (outputPath) {
+      fileStore.setPath(outputPath);
+      fileStore.setCreatedAt(doc.createdAt);
+      fileStore.setDirty(false);
+    }
+    return doc;
+  } catch (error) {
+    console.error("Error importing Markdown:", error);
+    throw error;
+  }
+}
+
+/**
+ * Whether loading failed because the document doesn't match the editor
+ * schema; its violations list what is wrong, and loading again with
+ * fixSchema repairs it
+ */
+export function isInvalidSchema(error: unknown): boolean {
+  return errorCode(error) === "INVALID_SCHEMA";
+}
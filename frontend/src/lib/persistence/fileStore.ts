@@ -3,12 +3,15 @@ import { writable } from "svelte/store";
 export interface FileState {
   path: string | null;
   isDirty: boolean;
+  /** createdAt of the document at `path`, kept across saves */
+  createdAt: string | null;
 }
 
 function createFileStore() {
   const { subscribe, set, update } = writable<FileState>({
     path: null,
     isDirty: false,
+    createdAt: null,
   });
 
   return {
@@ -19,8 +22,11 @@ function createFileStore() {
     setDirty: (isDirty: boolean) => {
       update((state) => ({ ...state, isDirty }));
     },
+    setCreatedAt: (createdAt: string | null) => {
+      update((state) => ({ ...state, createdAt }));
+    },
     reset: () => {
-      set({ path: null, isDirty: false });
+      set({ path: null, isDirty: false, createdAt: null });
     },
   };
 }
@@ -0,0 +1,87 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { EditorState } from "prosemirror-state";
+import { serializeDocument } from "./serializer";
+
+export interface WritingGoal {
+  /** Length of the finished manuscript in words */
+  targetWords?: number | null;
+  /** YYYY-MM-DD */
+  deadline?: string | null;
+  dailyWords?: number | null;
+  weeklyWords?: number | null;
+}
+
+export interface WritingSession {
+  startedAt: string;
+  endedAt: string;
+  startWords: number;
+  endWords: number;
+  wordsAdded: number;
+  wordsRemoved: number;
+}
+
+export interface WritingPeriod {
+  wordsAdded: number;
+  wordsRemoved: number;
+  netWords: number;
+  minutes: number;
+  sessions: number;
+}
+
+export interface WritingDay extends WritingPeriod {
+  date: string;
+}
+
+export interface WritingProgress {
+  path: string;
+  words: number | null;
+  goal: WritingGoal;
+  today: WritingPeriod;
+  /** Since Monday */
+  thisWeek: WritingPeriod;
+  /** The last 30 days, oldest first */
+  days: WritingDay[];
+  /** Most recent sessions, newest first */
+  sessions: WritingSession[];
+  remainingWords: number | null;
+  daysLeft: number | null;
+  wordsPerDayNeeded: number | null;
+}
+
+/**
+ * Report the document's current state to the session tracker; loading a
+ * file sets the baseline it is compared with
+ */
+export async function recordWriting(filePath: string, state: EditorState): Promise<WritingProgress> {
+  const doc = serializeDocument(state);
+  return await invoke<WritingProgress>("record_writing", {
+    path: filePath,
+    documentJson: JSON.stringify(doc),
+  });
+}
+
+/**
+ * Forget the tracking baseline, e.g. after closing the document
+ */
+export async function endWritingSession(filePath: string): Promise<void> {
+  await invoke("end_writing_session", { path: filePath });
+}
+
+export async function setWritingGoal(filePath: string, goal: WritingGoal): Promise<WritingProgress> {
+  return await invoke<WritingProgress>("set_writing_goal", {
+    path: filePath,
+    goal: {
+      targetWords: goal.targetWords ?? null,
+      deadline: goal.deadline ?? null,
+      dailyWords: goal.dailyWords ?? null,
+      weeklyWords: goal.weeklyWords ?? null,
+    },
+  });
+}
+
+/**
+ * Daily and weekly progress of a document against its goal
+ */
+export async function writingProgress(filePath: string): Promise<WritingProgress> {
+  return await invoke<WritingProgress>("writing_progress", { path: filePath });
+}
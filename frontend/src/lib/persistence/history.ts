@@ -0,0 +1,49 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { FlowmarkDocument } from "./serializer";
+import { fileStore } from "./fileStore";
+import type { DocumentDiff } from "./diff";
+
+export interface Revision {
+  id: string;
+  savedAt: string;
+  size: number;
+}
+
+/**
+ * List the revisions kept in a .flm archive, newest first
+ */
+export async function listHistory(filePath: string): Promise<Revision[]> {
+  return await invoke<Revision[]>("list_history", { path: filePath });
+}
+
+/**
+ * Load a revision without touching the current document
+ */
+export async function previewHistory(filePath: string, id: string): Promise<FlowmarkDocument> {
+  const documentJson = await invoke<string>("preview_history", { path: filePath, id });
+  return JSON.parse(documentJson) as FlowmarkDocument;
+}
+
+/**
+ * Compare a revision with another one, or with the current document
+ */
+export async function diffHistory(
+  filePath: string,
+  id: string,
+  against?: string
+): Promise<DocumentDiff> {
+  return await invoke<DocumentDiff>("diff_history", {
+    path: filePath,
+    id,
+    against: against ?? null,
+  });
+}
+
+/**
+ * Make a revision the current document; the replaced one stays in the history
+ */
+export async function restoreHistory(filePath: string, id: string): Promise<FlowmarkDocument> {
+  const documentJson = await invoke<string>("restore_history", { path: filePath, id });
+  fileStore.setDirty(false);
+  return JSON.parse(documentJson) as FlowmarkDocument;
+}
@@ -1,6 +1,22 @@
 // Export all persistence functionality
+export * from "./errors";
 export * from "./fileStore";
 export * from "./serializer";
 export * from "./fileIO";
 export * from "./autosave";
+export * from "./assets";
+export * from "./export";
 
+export * from "./history";
+export * from "./recovery";
+export * from "./search";
+export * from "./workspace";
+export * from "./watch";
+export * from "./merge";
+export * from "./diff";
+export * from "./stats";
+export * from "./goals";
+export * from "./crypto";
+export * from "./repair";
+export * from "./limits";
+export * from "./notes";
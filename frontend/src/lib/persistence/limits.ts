@@ -0,0 +1,47 @@
+import { invoke } from "@tauri-apps/api/core";
+import { errorCode } from "./errors";
+
+/**
+ * Limits applied to every .flm archive that is opened
+ */
+export interface ArchiveLimits {
+  maxEntries: number;
+  /** Uncompressed size of a single entry, in bytes */
+  maxEntrySize: number;
+  /** Uncompressed size of all entries together, in bytes */
+  maxTotalSize: number;
+  /** Uncompressed to compressed size of an entry */
+  maxCompressionRatio: number;
+  /** Depth of nested objects and arrays in a JSON entry */
+  maxNesting: number;
+}
+
+export async function getArchiveLimits(): Promise<ArchiveLimits> {
+  return await invoke<ArchiveLimits>("archive_limits");
+}
+
+/**
+ * Change the limits for the rest of the session; omitted fields are reset
+ * to their defaults
+ */
+export async function setArchiveLimits(limits: Partial<ArchiveLimits>): Promise<void> {
+  await invoke("set_archive_limits", { limits });
+}
+
+/**
+ * Whether opening a file failed because it exceeds the archive limits or
+ * contains entries that would be written outside the archive
+ */
+export function isArchiveRejected(error: unknown): boolean {
+  switch (errorCode(error)) {
+    case "TOO_MANY_ENTRIES":
+    case "ENTRY_TOO_LARGE":
+    case "ARCHIVE_TOO_LARGE":
+    case "COMPRESSION_RATIO_TOO_HIGH":
+    case "NESTING_TOO_DEEP":
+    case "UNSAFE_ENTRY_NAME":
+      return true;
+    default:
+      return false;
+  }
+}
@@ -0,0 +1,43 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { FlowmarkDocument } from "./serializer";
+
+export interface BlockConflict {
+  /** Index of the opening "<<<<<<< ours" paragraph in the merged document */
+  index: number;
+  base: unknown[];
+  ours: unknown[];
+  theirs: unknown[];
+}
+
+export interface NoteConflict {
+  noteId: string;
+  base: string | null;
+  ours: string | null;
+  theirs: string | null;
+}
+
+export interface MergeResult {
+  document: FlowmarkDocument;
+  conflicts: BlockConflict[];
+  noteConflicts: NoteConflict[];
+  hasBase: boolean;
+}
+
+/**
+ * Three-way merge two versions of a document, e.g. a sync tool's
+ * "conflicted copy". Without a base path the common ancestor is taken from
+ * the revision history of the two files. With an output path the merged
+ * document is written there.
+ */
+export async function mergeFlm(
+  oursPath: string,
+  theirsPath: string,
+  options: { basePath?: string; outputPath?: string } = {}
+): Promise<MergeResult> {
+  return await invoke<MergeResult>("merge_flm", {
+    oursPath,
+    theirsPath,
+    basePath: options.basePath ?? null,
+    outputPath: options.outputPath ?? null,
+  });
+}
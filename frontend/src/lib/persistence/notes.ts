@@ -0,0 +1,34 @@
+import { invoke } from "@tauri-apps/api/core";
+
+/**
+ * How the notes of a document match the references to them. Documents are
+ * renumbered by the order of their references when loaded and saved.
+ */
+export interface NoteReport {
+  /** Note ids referenced in the document that have no note */
+  missing: string[];
+  /** Notes nothing in the document refers to */
+  orphaned: string[];
+  /** Note ids that occur more than once */
+  duplicates: string[];
+  /** Whether the numbers didn't follow the order of the references */
+  renumbered: boolean;
+  /** Whether the orphaned notes were removed */
+  orphansRemoved: boolean;
+}
+
+/**
+ * Check the notes of a .flm file without changing it
+ */
+export async function checkNotes(filePath: string): Promise<NoteReport> {
+  return await invoke<NoteReport>("check_notes", { path: filePath });
+}
+
+export function isNoteReportConsistent(report: NoteReport): boolean {
+  return (
+    report.missing.length === 0 &&
+    report.orphaned.length === 0 &&
+    report.duplicates.length === 0 &&
+    !report.renumbered
+  );
+}
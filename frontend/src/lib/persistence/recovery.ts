@@ -0,0 +1,84 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { EditorState } from "prosemirror-state";
+import { serializeDocument, type FlowmarkDocument } from "./serializer";
+import { fileStore } from "./fileStore";
+import { get } from "svelte/store";
+
+export interface RecoveredDocument {
+  id: string;
+  path: string | null;
+  title: string | null;
+  savedAt: string;
+}
+
+// One journal entry per window, replaced as the document changes
+const journalId = crypto.randomUUID();
+
+let journalTimer: ReturnType<typeof setTimeout> | null = null;
+const JOURNAL_DELAY = 1000; // 1 second
+
+/**
+ * Journal the document for crash recovery (debounced), saved or not
+ */
+export function scheduleJournal(state: EditorState): void {
+  if (journalTimer) {
+    clearTimeout(journalTimer);
+  }
+
+  journalTimer = setTimeout(async () => {
+    journalTimer = null;
+    try {
+      await journalDocument(state);
+    } catch (error) {
+      console.error("Recovery journal failed:", error);
+    }
+  }, JOURNAL_DELAY);
+}
+
+/**
+ * Journal the document immediately
+ */
+export async function journalDocument(state: EditorState): Promise<void> {
+  const doc = serializeDocument(state);
+  await invoke("journal_document", {
+    id: journalId,
+    path: get(fileStore).path ?? null,
+    documentJson: JSON.stringify(doc),
+  });
+}
+
+/**
+ * Drop this window's journal, e.g. when the document is closed on purpose
+ */
+export async function discardJournal(): Promise<void> {
+  if (journalTimer) {
+    clearTimeout(journalTimer);
+    journalTimer = null;
+  }
+  await invoke("discard_journal", { id: journalId });
+}
+
+/**
+ * Documents left behind by a session that did not shut down cleanly
+ */
+export async function listRecovery(): Promise<RecoveredDocument[]> {
+  return await invoke<RecoveredDocument[]>("list_recovery");
+}
+
+/**
+ * Take a recovered document out of the store. If it belonged to a file,
+ * that path is restored too, but the document stays dirty until saved.
+ */
+export async function restoreRecovery(recovered: RecoveredDocument): Promise<FlowmarkDocument> {
+  const documentJson = await invoke<string>("restore_recovery", { id: recovered.id });
+  fileStore.setPath(recovered.path);
+  fileStore.setDirty(true);
+  return JSON.parse(documentJson) as FlowmarkDocument;
+}
+
+/**
+ * Throw a recovered document away
+ */
+export async function discardRecovery(id: string): Promise<void> {
+  await invoke("discard_recovery", { id });
+}
@@ -0,0 +1,45 @@
+import { invoke } from "@tauri-apps/api/core";
+import { errorCode } from "./errors";
+
+export interface LostEntry {
+  name: string;
+  reason: string;
+}
+
+export interface RepairReport {
+  /** Path of the salvaged copy */
+  output: string;
+  /**
+   * "intact": document.json was undamaged; "partial": it was cut back to
+   * its complete blocks; "revision": the newest readable revision is used
+   */
+  document: "intact" | "partial" | "revision";
+  revision: string | null;
+  blocks: number;
+  notes: number;
+  /** Bytes dropped from the end of a partial document, if known */
+  lostBytes: number | null;
+  recovered: string[];
+  lost: LostEntry[];
+  /** Assets the document references that couldn't be recovered */
+  missingAssets: string[];
+}
+
+/**
+ * Whether loading failed because the archive is damaged; offer repairFlm
+ */
+export function isRepairable(error: unknown): boolean {
+  const code = errorCode(error);
+  return code === "CORRUPT_ARCHIVE" || code === "MISSING_DOCUMENT" || code === "INVALID_JSON";
+}
+
+/**
+ * Salvage what can be read from a damaged .flm file into a new one, by
+ * default "<name>.repaired.flm" next to it. The damaged file is left as is.
+ */
+export async function repairFlm(filePath: string, outputPath?: string): Promise<RepairReport> {
+  return await invoke<RepairReport>("repair_flm", {
+    path: filePath,
+    outputPath: outputPath ?? null,
+  });
+}
@@ -0,0 +1,38 @@
+import { invoke } from "@tauri-apps/api/core";
+
+export interface IndexStats {
+  files: number;
+  updated: number;
+  removed: number;
+}
+
+export interface SearchHit {
+  path: string;
+  title: string | null;
+  score: number;
+  /** HTML-escaped context with matches wrapped in <mark> */
+  snippet: string;
+  noteId: string | null;
+}
+
+/**
+ * Add a folder of .flm files to the search index
+ */
+export async function indexFolder(folder: string): Promise<IndexStats> {
+  return await invoke<IndexStats>("index_folder", { folder });
+}
+
+/**
+ * Remove a folder and its documents from the search index
+ */
+export async function unindexFolder(folder: string): Promise<void> {
+  await invoke("unindex_folder", { folder });
+}
+
+/**
+ * Search the indexed folders. Supports "quoted phrases" and prefix* terms;
+ * changed files are re-indexed first.
+ */
+export async function search(query: string, limit?: number): Promise<SearchHit[]> {
+  return await invoke<SearchHit[]>("search", { query, limit: limit ?? null });
+}
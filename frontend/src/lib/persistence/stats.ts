@@ -0,0 +1,37 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { EditorState } from "prosemirror-state";
+import { serializeDocument } from "./serializer";
+
+export interface Counts {
+  words: number;
+  characters: number;
+  charactersNoSpaces: number;
+  paragraphs: number;
+  /** Estimated reading time in seconds */
+  readingTime: number;
+}
+
+export interface SectionStats extends Counts {
+  /** Null for the text before the first heading */
+  heading: string | null;
+  level: number | null;
+  index: number;
+}
+
+export interface DocumentStats extends Counts {
+  headings: number;
+  notes: number;
+  noteWords: number;
+  sections: SectionStats[];
+}
+
+/**
+ * Count words, characters and paragraphs of the current document, overall
+ * and per heading. Notes are counted separately.
+ */
+export async function documentStats(state: EditorState): Promise<DocumentStats> {
+  const doc = serializeDocument(state);
+  return await invoke<DocumentStats>("document_stats", {
+    documentJson: JSON.stringify(doc),
+  });
+}
@@ -0,0 +1,31 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import { errorCode } from "./errors";
+
+export interface FileChange {
+  path: string;
+  kind: "modified" | "deleted";
+}
+
+/**
+ * Be notified when an open file is changed or deleted by another program.
+ * Until it is reloaded, saving to it fails unless forced.
+ */
+export async function onFileChanged(handler: (change: FileChange) => void): Promise<UnlistenFn> {
+  return await listen<FileChange>("file-changed", (event) => handler(event.payload));
+}
+
+/**
+ * Stop watching a file, e.g. after closing its document
+ */
+export async function unwatchFile(filePath: string): Promise<void> {
+  await invoke("unwatch_file", { path: filePath });
+}
+
+/**
+ * Whether saving failed because the file was changed by another program;
+ * reload it or save again with force
+ */
+export function isExternalChange(error: unknown): boolean {
+  return errorCode(error) === "EXTERNAL_CHANGE";
+}
@@ -0,0 +1,70 @@
+import { open } from "@tauri-apps/plugin-dialog";
+import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+
+export interface WorkspaceSettings {
+  name?: string;
+  /** Paths relative to the workspace root, in reading order */
+  order: string[];
+  [key: string]: unknown;
+}
+
+export type TreeEntry =
+  | { type: "folder"; name: string; path: string; children: TreeEntry[] }
+  | {
+      type: "file";
+      name: string;
+      path: string;
+      format: "flm" | "markdown";
+      title: string | null;
+    };
+
+export interface WorkspaceInfo {
+  root: string;
+  name: string;
+  settings: WorkspaceSettings;
+  tree: TreeEntry[];
+}
+
+export interface WorkspaceChange {
+  kind: "created" | "modified" | "removed" | "renamed";
+  paths: string[];
+}
+
+/**
+ * Open a folder as the workspace, asking for one if no path is given
+ */
+export async function openWorkspace(path?: string): Promise<WorkspaceInfo | null> {
+  const folder = path ?? (await open({ directory: true, multiple: false }));
+  if (!folder || typeof folder !== "string") {
+    return null; // User cancelled
+  }
+  return await invoke<WorkspaceInfo>("open_workspace", { path: folder });
+}
+
+/**
+ * Settings and file tree of the open workspace, re-read from disk
+ */
+export async function workspaceInfo(): Promise<WorkspaceInfo | null> {
+  return await invoke<WorkspaceInfo | null>("workspace_info");
+}
+
+/**
+ * Store settings in the workspace's .flowmarker/ directory
+ */
+export async function saveWorkspaceSettings(settings: WorkspaceSettings): Promise<void> {
+  await invoke("save_workspace_settings", { settings });
+}
+
+export async function closeWorkspace(): Promise<void> {
+  await invoke("close_workspace");
+}
+
+/**
+ * Be notified when documents in the workspace change on disk
+ */
+export async function onWorkspaceChanged(
+  handler: (change: WorkspaceChange) => void
+): Promise<UnlistenFn> {
+  return await listen<WorkspaceChange>("workspace-changed", (event) => handler(event.payload));
+}
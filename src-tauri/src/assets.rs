@@ -0,0 +1,191 @@
+//! Binary assets (images, attachments) stored under `assets/` in a .flm archive.
+//!
+//! Assets are named after the SHA-256 of their content, so adding the same
+//! file twice stores it once. Documents reference them by their entry name
+//! (e.g. `assets/images/<hash>.png`) in `image` nodes or `link` marks.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::document::{FlowmarkDocument, MarkKind, NodeKind};
+use crate::error::FlowError;
+use crate::files;
+use crate::flm;
+use crate::limits;
+
+pub const ASSETS_DIR: &str = "assets/";
+
+/// URI scheme used by the webview to display assets.
+pub const URI_SCHEME: &str = "flmasset";
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "svg", "avif", "bmp"];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetInfo {
+    /// Entry name inside the archive, used as the reference in the document.
+    pub name: String,
+    pub size: u64,
+    pub mime_type: String,
+}
+
+/// Entry name for `bytes` originally called `file_name`.
+pub fn entry_name(file_name: &str, bytes: &[u8]) -> String {
+    let hash = files::sha256_hex(bytes);
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .filter(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_alphanumeric()));
+    let folder = match &extension {
+        Some(ext) if IMAGE_EXTENSIONS.contains(&ext.as_str()) => "images",
+        _ => "files",
+    };
+    match extension {
+        Some(ext) => format!("{}{}/{}.{}", ASSETS_DIR, folder, hash, ext),
+        None => format!("{}{}/{}", ASSETS_DIR, folder, hash),
+    }
+}
+
+/// MIME type guessed from the entry's extension.
+pub fn mime_type(name: &str) -> &'static str {
+    let extension = Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Asset entries referenced by `image` src attributes or `link` hrefs.
+pub fn referenced(doc: &FlowmarkDocument) -> HashSet<String> {
+    let mut names = HashSet::new();
+    doc.content.doc.walk(&mut |node| {
+        if node.kind == NodeKind::Image {
+            if let Some(src) = node.attr_str("src") {
+                if src.starts_with(ASSETS_DIR) {
+                    names.insert(src.to_string());
+                }
+            }
+        }
+        for mark in &node.marks {
+            if mark.kind == MarkKind::Link {
+                if let Some(href) = mark.attrs.get("href").and_then(|v| v.as_str()) {
+                    if href.starts_with(ASSETS_DIR) {
+                        names.insert(href.to_string());
+                    }
+                }
+            }
+        }
+    });
+    names
+}
+
+/// Store `bytes` in the archive at `archive_path`, reusing an identical asset if present.
+pub fn add_asset(
+    archive_path: &Path,
+    file_name: &str,
+    bytes: Vec<u8>,
+) -> Result<AssetInfo, FlowError> {
+    let name = entry_name(file_name, &bytes);
+    let info = AssetInfo {
+        size: bytes.len() as u64,
+        mime_type: mime_type(&name).to_string(),
+        name: name.clone(),
+    };
+
+    if list_assets(archive_path)?.iter().any(|a| a.name == name) {
+        return Ok(info);
+    }
+
+    // Keep every existing entry; unreferenced assets are only dropped on save
+    let doc = flm::read_document(archive_path)?;
+    flm::write_archive(archive_path, &doc, |_| true, vec![(name, bytes)])?;
+    Ok(info)
+}
+
+/// Store the file at `source` in the archive at `archive_path`.
+pub fn add_asset_from_path(archive_path: &Path, source: &Path) -> Result<AssetInfo, FlowError> {
+    let bytes = std::fs::read(source).map_err(|e| FlowError::io(source, e))?;
+    let file_name = source
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    add_asset(archive_path, &file_name, bytes)
+}
+
+/// All assets stored in the archive at `archive_path`.
+pub fn list_assets(archive_path: &Path) -> Result<Vec<AssetInfo>, FlowError> {
+    let mut archive = flm::open_archive(archive_path)?;
+    let mut assets = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index_raw(index)
+            .map_err(|e| FlowError::zip(archive_path, e))?;
+        if entry.is_dir() || !entry.name().starts_with(ASSETS_DIR) {
+            continue;
+        }
+        assets.push(AssetInfo {
+            name: entry.name().to_string(),
+            size: entry.size(),
+            mime_type: mime_type(entry.name()).to_string(),
+        });
+    }
+    Ok(assets)
+}
+
+/// Contents of the asset `name` in the archive at `archive_path`.
+pub fn read_asset(archive_path: &Path, name: &str) -> Result<Vec<u8>, FlowError> {
+    if !name.starts_with(ASSETS_DIR) {
+        return Err(FlowError::invalid(format!("Not an asset: {}", name)));
+    }
+    let mut archive = flm::open_archive(archive_path)?;
+    let entry = archive.by_name(name).map_err(|e| match e {
+        zip::result::ZipError::FileNotFound => FlowError::MissingEntry {
+            path: archive_path.to_path_buf(),
+            entry: name.to_string(),
+        },
+        e => FlowError::zip(archive_path, e),
+    })?;
+    limits::read_entry(archive_path, name, entry)
+}
+
+/// Resolve a `flmasset` request to the asset bytes and MIME type.
+///
+/// The request path is the asset entry name and the `archive` query
+/// parameter the path of the .flm file, e.g.
+/// `flmasset://localhost/assets/images/<hash>.png?archive=%2Fhome%2Fme%2Fbook.flm`.
+pub fn resolve_request(
+    path: &str,
+    query: Option<&str>,
+) -> Result<(Vec<u8>, &'static str), FlowError> {
+    let name = decode(path.trim_start_matches('/'));
+    let archive = query
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("archive="))
+        .map(decode)
+        .ok_or_else(|| FlowError::invalid("Missing archive parameter"))?;
+    let bytes = read_asset(Path::new(&archive), &name)?;
+    Ok((bytes, mime_type(&name)))
+}
+
+fn decode(value: &str) -> String {
+    percent_encoding::percent_decode_str(value)
+        .decode_utf8_lossy()
+        .to_string()
+}
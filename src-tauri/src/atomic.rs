@@ -0,0 +1,86 @@
+//! Crash-safe file replacement.
+//!
+//! Content is written to a temporary file next to the target, flushed to
+//! disk and renamed over the target, so readers only ever see the old or
+//! the new file. On any error the temporary file is removed and the
+//! existing target is left untouched.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::error::FlowError;
+
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Atomically replace `path` with `bytes`.
+pub fn write(path: impl AsRef<Path>, bytes: &[u8]) -> Result<(), FlowError> {
+    let path = path.as_ref();
+    write_with(path, |mut file| {
+        file.write_all(bytes).map_err(|e| FlowError::io(path, e))?;
+        Ok(file)
+    })
+}
+
+/// Atomically replace `path` with whatever `write` produces.
+///
+/// `write` receives the temporary file and must hand it back once all data
+/// has been written so it can be synced before the rename.
+pub fn write_with<F>(path: impl AsRef<Path>, write: F) -> Result<(), FlowError>
+where
+    F: FnOnce(File) -> Result<File, FlowError>,
+{
+    let path = path.as_ref();
+    let tmp_path = temp_path(path)?;
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .map_err(|e| FlowError::io(path, e))?;
+
+    let result = write(file).and_then(|file| {
+        // Keep the permissions of the file being replaced
+        if let Ok(meta) = fs::metadata(path) {
+            let _ = file.set_permissions(meta.permissions());
+        }
+        file.sync_all().map_err(|e| FlowError::io(path, e))?;
+        drop(file);
+        fs::rename(&tmp_path, path).map_err(|e| FlowError::io(path, e))
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+
+    sync_parent(path);
+    Ok(())
+}
+
+/// Hidden, unique sibling of `path` in the same directory (and filesystem).
+fn temp_path(path: &Path) -> Result<PathBuf, FlowError> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| FlowError::invalid(format!("Invalid file path: {}", path.display())))?
+        .to_string_lossy();
+    let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_name = format!(".{}.{}-{}.tmp", name, std::process::id(), unique);
+    Ok(path.with_file_name(tmp_name))
+}
+
+/// Persist the rename itself by syncing the containing directory.
+#[cfg(unix)]
+fn sync_parent(path: &Path) {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) {}
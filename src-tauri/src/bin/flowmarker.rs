@@ -0,0 +1,5 @@
+//! Headless command-line entry point; see `app_lib::cli`.
+
+fn main() -> std::process::ExitCode {
+    app_lib::cli::main()
+}
@@ -0,0 +1,516 @@
+//! The `flowmarker` command-line interface.
+//!
+//! Converts, inspects, unpacks and repairs .flm files with the same code
+//! the app's commands use, without starting Tauri, so documents can be
+//! processed in scripts and build pipelines.
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use crate::assets;
+use crate::atomic;
+use crate::crypto;
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::export;
+use crate::flm;
+use crate::import;
+use crate::limits;
+use crate::notes;
+use crate::repair::{self, DocumentSource};
+use crate::schema;
+
+#[derive(Parser)]
+#[command(
+    name = "flowmarker",
+    version,
+    about = "Convert and inspect FlowMark (.flm) documents"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// Password of encrypted .flm files
+    #[arg(long, global = true, env = "FLOWMARK_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert documents to another format
+    Convert(ConvertArgs),
+    /// Show the metadata, notes and assets of a document
+    Info {
+        file: PathBuf,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that documents can be opened and match the editor schema
+    Validate {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Unpack the entries of a .flm archive into a directory
+    Extract {
+        file: PathBuf,
+        /// Target directory; defaults to the file name without extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check that the notes of a .flm file match the references to them
+    Notes {
+        file: PathBuf,
+        /// Drop references to missing notes and renumber by document order
+        #[arg(long)]
+        fix: bool,
+        /// With --fix, also remove notes nothing refers to
+        #[arg(long, requires = "fix")]
+        remove_orphans: bool,
+        /// Print the report as JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Salvage what can be read from a damaged .flm archive into a new one
+    Repair {
+        file: PathBuf,
+        /// Salvaged copy; defaults to <name>.repaired.flm next to the file
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Print the report as JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// .flm archives, FlowMark JSON or Markdown files
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Output file, for a single input
+    #[arg(short, long, conflicts_with = "out_dir")]
+    output: Option<PathBuf>,
+    /// Output format; inferred from --output when omitted
+    #[arg(short, long = "to", value_enum)]
+    to: Option<Format>,
+    /// Directory for the converted files; defaults to each input's directory
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+    /// Exporter options as JSON, e.g. '{"footnotes":"endnotes"}'
+    #[arg(long)]
+    options: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Flm,
+    Json,
+    Md,
+    Html,
+    Epub,
+    Pdf,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Flm => "flm",
+            Format::Json => "json",
+            Format::Md => "md",
+            Format::Html => "html",
+            Format::Epub => "epub",
+            Format::Pdf => "pdf",
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "flm" => Some(Format::Flm),
+            "json" => Some(Format::Json),
+            "md" | "markdown" => Some(Format::Md),
+            "html" | "htm" => Some(Format::Html),
+            "epub" => Some(Format::Epub),
+            "pdf" => Some(Format::Pdf),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the process arguments and run the requested subcommand.
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let password = cli.password.as_deref();
+    let result = match cli.command {
+        Command::Convert(args) => convert(&args, password),
+        Command::Info { file, json } => info(&file, json, password),
+        Command::Validate { files } => validate(&files, password),
+        Command::Extract { file, output } => extract(&file, output, password),
+        Command::Notes {
+            file,
+            fix,
+            remove_orphans,
+            json,
+        } => notes(&file, fix, remove_orphans, json, password),
+        Command::Repair { file, output, json } => repair(&file, output.as_deref(), json),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("flowmarker: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn convert(args: &ConvertArgs, password: Option<&str>) -> Result<ExitCode, FlowError> {
+    if args.output.is_some() && args.inputs.len() > 1 {
+        return Err(FlowError::invalid(
+            "--output takes a single input; use --out-dir for several",
+        ));
+    }
+    let format = match (args.to, &args.output) {
+        (Some(format), _) => format,
+        (None, Some(output)) => Format::from_path(output).ok_or_else(|| {
+            FlowError::invalid(format!(
+                "Unknown output format for {}; pass --to",
+                output.display()
+            ))
+        })?,
+        (None, None) => {
+            return Err(FlowError::invalid(
+                "Pass --to or --output to choose the output format",
+            ))
+        }
+    };
+    let options = match &args.options {
+        Some(options) => serde_json::from_str(options)
+            .map_err(|e| FlowError::invalid(format!("Invalid --options: {}", e)))?,
+        None => serde_json::Value::Object(Default::default()),
+    };
+    for input in &args.inputs {
+        let output = match (&args.output, &args.out_dir) {
+            (Some(output), _) => output.clone(),
+            (None, out_dir) => {
+                let dir = out_dir
+                    .clone()
+                    .or_else(|| input.parent().map(Path::to_path_buf))
+                    .unwrap_or_default();
+                // Append rather than `with_extension`, which would cut
+                // "draft.v2" down to "draft"
+                let stem = input.file_stem().unwrap_or(input.as_os_str());
+                dir.join(format!("{}.{}", stem.to_string_lossy(), format.extension()))
+            }
+        };
+        if let Some(dir) = output.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| FlowError::io(dir, e))?;
+        }
+        if output == *input {
+            return Err(FlowError::invalid(format!(
+                "Refusing to overwrite {} with itself",
+                input.display()
+            )));
+        }
+
+        if format == Format::Flm && Format::from_path(input) == Some(Format::Md) {
+            // Local images are only kept when there is an archive to hold them
+            import::markdown::import_file(input, Some(&output))?;
+        } else {
+            let (doc, archive) = read_input(input, password)?;
+            write_output(&doc, archive, &output, format, &options)?;
+        }
+        println!("{} -> {}", input.display(), output.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Unlock `path` with `password` if it is an encrypted .flm file.
+fn unlock(path: &Path, password: Option<&str>) -> Result<(), FlowError> {
+    match password {
+        Some(password) if crypto::is_encrypted(path) => crypto::unlock(path, password),
+        _ => Ok(()),
+    }
+}
+
+/// Read a .flm archive, a bare FlowMark JSON document or a Markdown file.
+///
+/// Returns the archive path along with the document when there is one, so
+/// exporters can pull assets from it.
+fn read_input<'a>(
+    path: &'a Path,
+    password: Option<&str>,
+) -> Result<(FlowmarkDocument, Option<&'a Path>), FlowError> {
+    match Format::from_path(path) {
+        Some(Format::Json) => {
+            let json = fs::read_to_string(path).map_err(|e| FlowError::io(path, e))?;
+            Ok((FlowmarkDocument::from_json(&json)?, None))
+        }
+        Some(Format::Md) => Ok((import::markdown::import_file(path, None)?, None)),
+        _ => {
+            unlock(path, password)?;
+            Ok((flm::read_document(path)?, Some(path)))
+        }
+    }
+}
+
+fn write_output(
+    doc: &FlowmarkDocument,
+    archive: Option<&Path>,
+    output: &Path,
+    format: Format,
+    options: &serde_json::Value,
+) -> Result<(), FlowError> {
+    // Every exporter takes its options struct; unknown fields are ignored
+    fn parse<T: serde::de::DeserializeOwned>(options: &serde_json::Value) -> Result<T, FlowError> {
+        serde_json::from_value(options.clone())
+            .map_err(|e| FlowError::invalid(format!("Invalid --options: {}", e)))
+    }
+
+    match format {
+        Format::Flm => {
+            // Carry the referenced assets over into the new archive
+            let mut entries = Vec::new();
+            if let Some(archive) = archive {
+                for name in assets::referenced(doc) {
+                    let bytes = assets::read_asset(archive, &name)?;
+                    entries.push((name, bytes));
+                }
+                // A copy of an encrypted archive is encrypted as well
+                crypto::carry_key(archive, output)?;
+            }
+            flm::write_archive(output, doc, |_| false, entries)
+        }
+        Format::Json => atomic::write(output, doc.to_json()?.as_bytes()),
+        Format::Md => export::markdown::write_markdown(doc, archive, output),
+        Format::Html => export::html::write_html(doc, &parse(options)?, archive, output),
+        Format::Epub => export::epub::write_epub(doc, &parse(options)?, archive, output),
+        Format::Pdf => export::pdf::write_pdf(doc, &parse(options)?, output),
+    }
+}
+
+fn info(path: &Path, as_json: bool, password: Option<&str>) -> Result<ExitCode, FlowError> {
+    unlock(path, password)?;
+    let manifest = flm::read_manifest(path)?;
+    let doc = flm::read_document(path)?;
+    let assets = assets::list_assets(path)?;
+    let title = export::html::document_title(&doc);
+
+    if as_json {
+        let info = json!({
+            "path": path,
+            "format": doc.format,
+            "version": doc.version,
+            "schemaVersion": doc.content.schema_version,
+            "generator": manifest.as_ref().map(|m| &m.generator),
+            "title": title,
+            "createdAt": doc.created_at,
+            "updatedAt": doc.updated_at,
+            "blocks": doc.content.doc.content.len(),
+            "notes": doc.notes.len(),
+            "assets": assets,
+        });
+        let json = serde_json::to_string_pretty(&info)
+            .map_err(|e| FlowError::other(format!("Failed to serialize info: {}", e)))?;
+        println!("{}", json);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!("File:       {}", path.display());
+    println!(
+        "Format:     {} {} (schema {})",
+        doc.format, doc.version, doc.content.schema_version
+    );
+    if let Some(manifest) = &manifest {
+        println!("Generator:  {}", manifest.generator);
+    }
+    println!("Title:      {}", title.as_deref().unwrap_or("-"));
+    println!("Created:    {}", doc.created_at);
+    println!("Updated:    {}", doc.updated_at);
+    println!("Blocks:     {}", doc.content.doc.content.len());
+    println!("Notes:      {}", doc.notes.len());
+    let total: u64 = assets.iter().map(|asset| asset.size).sum();
+    println!("Assets:     {} ({} bytes)", assets.len(), total);
+    for asset in &assets {
+        println!(
+            "  {}  {} bytes  {}",
+            asset.name, asset.size, asset.mime_type
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(paths: &[PathBuf], password: Option<&str>) -> Result<ExitCode, FlowError> {
+    let mut failed = false;
+    for path in paths {
+        match read_input(path, password) {
+            Ok((doc, _)) => {
+                let violations = schema::check(&doc);
+                if violations.is_empty() {
+                    println!("{}: ok", path.display());
+                    continue;
+                }
+                println!("{}: doesn't match the editor schema", path.display());
+                for violation in violations {
+                    println!("  {}: {}", violation.path, violation.message);
+                }
+                failed = true;
+            }
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                failed = true;
+            }
+        }
+    }
+    Ok(if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+fn extract(
+    path: &Path,
+    output: Option<PathBuf>,
+    password: Option<&str>,
+) -> Result<ExitCode, FlowError> {
+    let output = output.unwrap_or_else(|| {
+        let stem = path.file_stem().unwrap_or(path.as_os_str());
+        path.with_file_name(stem)
+    });
+    if output == path {
+        return Err(FlowError::invalid(format!(
+            "Refusing to extract {} onto itself",
+            path.display()
+        )));
+    }
+    // Entry names and sizes were checked against the limits on opening
+    unlock(path, password)?;
+    let mut archive = flm::open_archive(path)?;
+    fs::create_dir_all(&output).map_err(|e| FlowError::io(&output, e))?;
+
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index(index)
+            .map_err(|e| FlowError::zip(path, e))?;
+        let name = entry.name().to_string();
+        let target = output.join(&name);
+        if entry.is_dir() {
+            fs::create_dir_all(&target).map_err(|e| FlowError::io(&target, e))?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| FlowError::io(parent, e))?;
+        }
+        let bytes = limits::read_entry(path, &name, entry)?;
+        fs::write(&target, bytes).map_err(|e| FlowError::io(&target, e))?;
+    }
+    println!("{} -> {}", path.display(), output.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+fn notes(
+    path: &Path,
+    fix: bool,
+    remove_orphans: bool,
+    as_json: bool,
+    password: Option<&str>,
+) -> Result<ExitCode, FlowError> {
+    unlock(path, password)?;
+    let mut doc = flm::read_document(path)?;
+    let report = if fix {
+        notes::reconcile(&mut doc, remove_orphans)
+    } else {
+        notes::check(&doc)
+    };
+    if fix && !report.is_consistent() {
+        flm::write_document(path, &doc)?;
+    }
+    // Without --fix an inconsistent file fails, so scripts can check for it
+    let code = if fix || report.is_consistent() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    };
+    if as_json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| FlowError::other(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+        return Ok(code);
+    }
+
+    if report.is_consistent() {
+        println!("{}: ok", path.display());
+        return Ok(code);
+    }
+    let list = |ids: &[String]| {
+        if ids.is_empty() {
+            "-".to_string()
+        } else {
+            ids.join(", ")
+        }
+    };
+    println!("File:       {}", path.display());
+    println!("Missing:    {}", list(&report.missing));
+    println!("Orphaned:   {}", list(&report.orphaned));
+    println!("Duplicates: {}", list(&report.duplicates));
+    println!(
+        "Numbering:  {}",
+        if report.renumbered {
+            "out of order"
+        } else {
+            "ok"
+        }
+    );
+    if fix {
+        println!(
+            "Fixed{}",
+            if report.orphans_removed {
+                ", orphaned notes removed"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(code)
+}
+
+fn repair(path: &Path, output: Option<&Path>, as_json: bool) -> Result<ExitCode, FlowError> {
+    let report = repair::repair(path, output)?;
+    if as_json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| FlowError::other(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    println!("{} -> {}", path.display(), report.output.display());
+    let document = match (report.document, &report.revision) {
+        (DocumentSource::Intact, _) => "intact".to_string(),
+        (DocumentSource::Partial, _) => match report.lost_bytes {
+            Some(bytes) => format!("partial, {} bytes lost at the end", bytes),
+            None => "partial, the end is lost".to_string(),
+        },
+        (DocumentSource::Revision, revision) => format!(
+            "lost, revision {} used instead",
+            revision.as_deref().unwrap_or("-")
+        ),
+    };
+    println!("Document:   {}", document);
+    println!("Blocks:     {}", report.blocks);
+    println!("Notes:      {}", report.notes);
+    println!("Recovered:  {} entries", report.recovered.len());
+    println!("Lost:       {} entries", report.lost.len());
+    for entry in &report.lost {
+        println!("  {}  {}", entry.name, entry.reason);
+    }
+    if !report.missing_assets.is_empty() {
+        println!("Missing assets:");
+        for name in &report.missing_assets {
+            println!("  {}", name);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
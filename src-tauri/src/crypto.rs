@@ -0,0 +1,459 @@
+//! Password-protected .flm files.
+//!
+//! An encrypted .flm file is the regular ZIP archive (document, assets and
+//! history) sealed with XChaCha20-Poly1305 under a key derived from the
+//! password with Argon2id. It starts with a plain header
+//!
+//! ```text
+//! "FLMCRYPT"  magic, 8 bytes
+//! 1           format version, 1 byte
+//! m, t, p     Argon2id memory (KiB), iterations and lanes, u32 LE each
+//! salt        16 bytes
+//! nonce       24 bytes
+//! ```
+//!
+//! followed by the ciphertext; the header is authenticated along with it.
+//!
+//! Once a file has been unlocked its key is kept in memory, so every read
+//! and write of it through [`flm`](crate::flm) transparently decrypts and
+//! re-encrypts it (with a fresh nonce) until it is locked again.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::atomic;
+use crate::error::FlowError;
+use crate::files::normalize;
+
+pub const MAGIC: &[u8; 8] = b"FLMCRYPT";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 12 + SALT_LEN + NONCE_LEN;
+/// Upper bounds on the Argon2 parameters accepted from a header, so a
+/// crafted file can't make opening it take forever.
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// Derived key of an unlocked file, with what's needed to seal it again.
+#[derive(Clone)]
+struct Unlocked {
+    key: [u8; 32],
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KdfParams {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+struct Header {
+    params: KdfParams,
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+}
+
+/// Whether the file at `path` is an encrypted .flm file.
+pub fn is_encrypted(path: impl AsRef<Path>) -> bool {
+    let mut magic = [0u8; 8];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == MAGIC
+}
+
+/// Check `password` against the encrypted file at `path` and keep its key
+/// for later reads and writes.
+pub fn unlock(path: &Path, password: &str) -> Result<(), FlowError> {
+    let bytes = read_file(path)?;
+    let header = parse_header(path, &bytes)?
+        .ok_or_else(|| FlowError::invalid(format!("{} is not encrypted", path.display())))?;
+    let unlocked = Unlocked {
+        key: derive_key(password, header.params, &header.salt)?,
+        params: header.params,
+        salt: header.salt,
+    };
+    open(path, &unlocked, &header, &bytes)?;
+    keys()?.insert(normalize(path), unlocked);
+    Ok(())
+}
+
+/// Forget the key of `path`, e.g. when its document was closed.
+pub fn lock(path: &Path) {
+    if let Ok(mut keys) = keys() {
+        keys.remove(&normalize(path));
+    }
+}
+
+/// Contents of the file at `path`: the archive as stored, or decrypted if
+/// the file is encrypted and unlocked.
+pub(crate) fn read(path: &Path) -> Result<Vec<u8>, FlowError> {
+    let bytes = read_file(path)?;
+    let Some(header) = parse_header(path, &bytes)? else {
+        return Ok(bytes);
+    };
+    let unlocked =
+        keys()?
+            .get(&normalize(path))
+            .cloned()
+            .ok_or_else(|| FlowError::PasswordRequired {
+                path: path.to_path_buf(),
+            })?;
+    open(path, &unlocked, &header, &bytes)
+}
+
+/// Turn an archive about to be written to `path` into what is stored: the
+/// archive itself, or sealed if `path` is an unlocked encrypted file.
+///
+/// Fails for an encrypted file that is locked rather than replacing it
+/// with an unencrypted one.
+pub(crate) fn prepare_write(path: &Path, archive: Vec<u8>) -> Result<Vec<u8>, FlowError> {
+    let unlocked = keys()?.get(&normalize(path)).cloned();
+    match unlocked {
+        Some(unlocked) => seal(&unlocked, &archive),
+        None if is_encrypted(path) => Err(FlowError::PasswordRequired {
+            path: path.to_path_buf(),
+        }),
+        None => Ok(archive),
+    }
+}
+
+/// Encrypt writes to `to` with the key of `from`, if `from` is unlocked,
+/// so a copy of an encrypted document isn't written in plain text. A file
+/// at `to` that is encrypted or unlocked keeps its own key.
+pub fn carry_key(from: &Path, to: &Path) -> Result<(), FlowError> {
+    let (from, to) = (normalize(from), normalize(to));
+    let mut keys = keys()?;
+    if from == to || keys.contains_key(&to) || is_encrypted(&to) {
+        return Ok(());
+    }
+    if let Some(unlocked) = keys.get(&from).cloned() {
+        keys.insert(to, unlocked);
+    }
+    Ok(())
+}
+
+/// Whether writes to `path` are encrypted.
+pub(crate) fn is_unlocked(path: &Path) -> bool {
+    keys().is_ok_and(|keys| keys.contains_key(&normalize(path)))
+}
+
+/// Encrypt the .flm file at `path` with `new_password`, or decrypt it for
+/// good when `None`. An encrypted file needs its `current_password`.
+pub fn set_password(
+    path: &Path,
+    current_password: Option<&str>,
+    new_password: Option<&str>,
+) -> Result<(), FlowError> {
+    let bytes = read_file(path)?;
+    let archive = match parse_header(path, &bytes)? {
+        Some(header) => {
+            let password = current_password.ok_or_else(|| FlowError::PasswordRequired {
+                path: path.to_path_buf(),
+            })?;
+            let unlocked = Unlocked {
+                key: derive_key(password, header.params, &header.salt)?,
+                params: header.params,
+                salt: header.salt,
+            };
+            open(path, &unlocked, &header, &bytes)?
+        }
+        None => bytes,
+    };
+    zip::ZipArchive::new(Cursor::new(&archive[..])).map_err(|e| FlowError::zip(path, e))?;
+
+    match new_password {
+        Some(password) => {
+            if password.is_empty() {
+                return Err(FlowError::invalid("The password must not be empty"));
+            }
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let params = KdfParams::default();
+            let unlocked = Unlocked {
+                key: derive_key(password, params, &salt)?,
+                params,
+                salt,
+            };
+            atomic::write(path, &seal(&unlocked, &archive)?)?;
+            keys()?.insert(normalize(path), unlocked);
+        }
+        None => {
+            atomic::write(path, &archive)?;
+            lock(path);
+        }
+    }
+    Ok(())
+}
+
+fn seal(unlocked: &Unlocked, archive: &[u8]) -> Result<Vec<u8>, FlowError> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&unlocked.key));
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut out = Vec::with_capacity(HEADER_LEN + archive.len() + 16);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&unlocked.params.m_cost.to_le_bytes());
+    out.extend_from_slice(&unlocked.params.t_cost.to_le_bytes());
+    out.extend_from_slice(&unlocked.params.p_cost.to_le_bytes());
+    out.extend_from_slice(&unlocked.salt);
+    out.extend_from_slice(&nonce);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: archive,
+                aad: &out,
+            },
+        )
+        .map_err(|_| FlowError::other("Failed to encrypt document"))?;
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn open(
+    path: &Path,
+    unlocked: &Unlocked,
+    header: &Header,
+    bytes: &[u8],
+) -> Result<Vec<u8>, FlowError> {
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(&unlocked.key));
+    let (aad, ciphertext) = bytes.split_at(HEADER_LEN);
+    cipher
+        .decrypt(
+            XNonce::from_slice(&header.nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        // A wrong key and a damaged file can't be told apart
+        .map_err(|_| FlowError::WrongPassword {
+            path: path.to_path_buf(),
+        })
+}
+
+/// The header of `bytes`, or `None` if they aren't an encrypted file.
+fn parse_header(path: &Path, bytes: &[u8]) -> Result<Option<Header>, FlowError> {
+    let corrupt = |reason: &str| FlowError::CorruptArchive {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+    if !bytes.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(corrupt("encrypted file is truncated"));
+    }
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(FlowError::UnsupportedVersion {
+            found: version.to_string(),
+            supported: VERSION.to_string(),
+            reason: format!(
+                "This file uses encryption version {}; please update FlowMarker to open it.",
+                version
+            ),
+        });
+    }
+    let u32_at = |offset: usize| {
+        let mut le = [0u8; 4];
+        le.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(le)
+    };
+    let offset = MAGIC.len() + 1;
+    let params = KdfParams {
+        m_cost: u32_at(offset),
+        t_cost: u32_at(offset + 4),
+        p_cost: u32_at(offset + 8),
+    };
+    if params.m_cost > MAX_M_COST || params.t_cost > MAX_T_COST || params.p_cost > MAX_P_COST {
+        return Err(corrupt("unsupported key parameters"));
+    }
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&bytes[offset + 12..offset + 12 + SALT_LEN]);
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&bytes[HEADER_LEN - NONCE_LEN..HEADER_LEN]);
+    Ok(Some(Header {
+        params,
+        salt,
+        nonce,
+    }))
+}
+
+fn derive_key(password: &str, params: KdfParams, salt: &[u8]) -> Result<[u8; 32], FlowError> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| FlowError::other(format!("Invalid key parameters: {}", e)))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| FlowError::other(format!("Failed to derive key: {}", e)))?;
+    Ok(key)
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, FlowError> {
+    fs::read(path).map_err(|e| FlowError::io(path, e))
+}
+
+fn keys() -> Result<MutexGuard<'static, HashMap<PathBuf, Unlocked>>, FlowError> {
+    static KEYS: OnceLock<Mutex<HashMap<PathBuf, Unlocked>>> = OnceLock::new();
+    KEYS.get_or_init(Mutex::default)
+        .lock()
+        .map_err(|_| FlowError::other("Encryption keys are unavailable"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{FlowmarkDocument, Node, NodeKind};
+    use crate::flm;
+
+    /// Cheap key parameters, the defaults take a while to derive.
+    const FAST: KdfParams = KdfParams {
+        m_cost: 8,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn unlocked(password: &str) -> Unlocked {
+        let salt = [7u8; SALT_LEN];
+        Unlocked {
+            key: derive_key(password, FAST, &salt).unwrap(),
+            params: FAST,
+            salt,
+        }
+    }
+
+    fn header(sealed: &[u8]) -> Result<Option<Header>, FlowError> {
+        parse_header(Path::new("a.flm"), sealed)
+    }
+
+    #[test]
+    fn sealed_archives_open_with_the_same_key() {
+        let key = unlocked("secret");
+        let sealed = seal(&key, b"PK archive").unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert_eq!(sealed.len(), HEADER_LEN + b"PK archive".len() + 16);
+
+        let parsed = header(&sealed).unwrap().unwrap();
+        assert_eq!(parsed.params, FAST);
+        assert_eq!(parsed.salt, key.salt);
+        let path = Path::new("a.flm");
+        assert_eq!(open(path, &key, &parsed, &sealed).unwrap(), b"PK archive");
+        // Every seal gets a fresh nonce
+        assert_ne!(seal(&key, b"PK archive").unwrap(), sealed);
+    }
+
+    #[test]
+    fn wrong_key_or_tampering_fails() {
+        let key = unlocked("secret");
+        let sealed = seal(&key, b"PK archive").unwrap();
+        let path = Path::new("a.flm");
+        let parsed = header(&sealed).unwrap().unwrap();
+        assert!(matches!(
+            open(path, &unlocked("wrong"), &parsed, &sealed),
+            Err(FlowError::WrongPassword { .. })
+        ));
+        // The header is authenticated along with the ciphertext
+        let mut tampered = sealed.clone();
+        tampered[MAGIC.len() + 1 + 12] ^= 1;
+        let parsed = header(&tampered).unwrap().unwrap();
+        assert!(open(path, &key, &parsed, &tampered).is_err());
+        let mut tampered = sealed;
+        *tampered.last_mut().unwrap() ^= 1;
+        let parsed = header(&tampered).unwrap().unwrap();
+        assert!(open(path, &key, &parsed, &tampered).is_err());
+    }
+
+    #[test]
+    fn header_parsing() {
+        // Plain archives have no header
+        assert!(header(b"PK\x03\x04...").unwrap().is_none());
+        assert!(header(b"").unwrap().is_none());
+
+        let sealed = seal(&unlocked("secret"), b"PK").unwrap();
+        assert!(matches!(
+            header(&sealed[..HEADER_LEN - 1]),
+            Err(FlowError::CorruptArchive { .. })
+        ));
+
+        let mut newer = sealed.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            header(&newer),
+            Err(FlowError::UnsupportedVersion { .. })
+        ));
+
+        // Key parameters that would take forever to derive
+        for (offset, limit) in [(0, MAX_M_COST), (4, MAX_T_COST), (8, MAX_P_COST)] {
+            let mut costly = sealed.clone();
+            let at = MAGIC.len() + 1 + offset;
+            costly[at..at + 4].copy_from_slice(&(limit + 1).to_le_bytes());
+            assert!(matches!(
+                header(&costly),
+                Err(FlowError::CorruptArchive { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn keys_depend_on_password_salt_and_params() {
+        let salt = [1u8; SALT_LEN];
+        let key = derive_key("secret", FAST, &salt).unwrap();
+        assert_eq!(derive_key("secret", FAST, &salt).unwrap(), key);
+        assert_ne!(derive_key("Secret", FAST, &salt).unwrap(), key);
+        assert_ne!(derive_key("secret", FAST, &[2u8; SALT_LEN]).unwrap(), key);
+        let slower = KdfParams { t_cost: 2, ..FAST };
+        assert_ne!(derive_key("secret", slower, &salt).unwrap(), key);
+    }
+
+    #[test]
+    fn copies_of_unlocked_files_are_encrypted() {
+        let dir = std::env::temp_dir().join(format!("flowmark-crypto-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (source, copy) = (dir.join("source.flm"), dir.join("copy.flm"));
+        let doc = FlowmarkDocument::new(Node::new(NodeKind::Doc), Vec::new());
+        flm::write_document(&source, &doc).unwrap();
+        set_password(&source, None, Some("secret")).unwrap();
+
+        carry_key(&source, &copy).unwrap();
+        flm::write_document(&copy, &doc).unwrap();
+        assert!(is_encrypted(&copy));
+        lock(&copy);
+        assert!(matches!(
+            flm::read_document(&copy),
+            Err(FlowError::PasswordRequired { .. })
+        ));
+        unlock(&copy, "secret").unwrap();
+        assert_eq!(flm::read_document(&copy).unwrap(), doc);
+
+        // Nothing to carry from a file that isn't unlocked
+        let plain = dir.join("plain.flm");
+        lock(&source);
+        carry_key(&source, &plain).unwrap();
+        flm::write_document(&plain, &doc).unwrap();
+        assert!(!is_encrypted(&plain));
+        lock(&copy);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
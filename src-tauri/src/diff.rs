@@ -0,0 +1,570 @@
+//! Structural comparison of two FlowMark documents.
+//!
+//! Top-level blocks are aligned first; between blocks both versions share,
+//! a removed and an added block of the same type with enough words in
+//! common are paired up as one modified block and compared word by word.
+//! Notes are matched by `noteId`, so a note whose number changed only
+//! because notes before it were added or removed shows up as renumbered.
+//! Besides the change list the result carries an HTML redline of the new
+//! version with insertions and deletions marked up.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::document::{FlowmarkDocument, Node, NodeKind, Note};
+use crate::error::FlowError;
+use crate::export::html::{escape, Renderer};
+use crate::flm;
+use crate::history;
+use crate::search::is_cjk;
+
+/// Below this share of common words a changed block counts as removed and
+/// replaced rather than modified.
+const MIN_SIMILARITY: f64 = 0.4;
+/// Largest table computed for an alignment; longer stretches that differ
+/// are treated as replaced wholesale.
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+const REDLINE_CSS: &str =
+    ".redline ins { color: #1a7f37; background: #e6ffec; text-decoration: underline; }
+.redline del { color: #cf222e; background: #ffebe9; text-decoration: line-through; }
+.redline ins.block, .redline del.block { display: block; }
+.redline .note-number { color: #6e7781; }
+";
+
+/// A document to compare: a .flm file, or one of its history revisions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Source {
+    pub path: PathBuf,
+    pub revision: Option<String>,
+}
+
+impl Source {
+    pub fn load(&self) -> Result<FlowmarkDocument, FlowError> {
+        match &self.revision {
+            Some(id) => history::read(&self.path, id),
+            None => flm::read_document(&self.path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+    /// Same note content under a different number.
+    Renumbered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SegmentKind {
+    Unchanged,
+    Added,
+    Removed,
+}
+
+/// A run of text in a word-level diff.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Segment {
+    pub kind: SegmentKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockChange {
+    pub kind: ChangeKind,
+    /// Index among the old document's top-level blocks.
+    pub old_index: Option<usize>,
+    /// Index among the new document's top-level blocks.
+    pub new_index: Option<usize>,
+    /// Node type, e.g. `paragraph`.
+    pub block_type: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Word-level changes of a modified block. All unchanged when only the
+    /// formatting or attributes differ.
+    pub words: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteChange {
+    pub kind: ChangeKind,
+    pub note_id: String,
+    pub old_number: Option<u32>,
+    pub new_number: Option<u32>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub words: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffSummary {
+    pub blocks_added: usize,
+    pub blocks_removed: usize,
+    pub blocks_modified: usize,
+    pub words_added: usize,
+    pub words_removed: usize,
+    pub notes_changed: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiff {
+    pub changes: Vec<BlockChange>,
+    pub notes: Vec<NoteChange>,
+    pub summary: DiffSummary,
+    /// Redline fragment with its own `<style>`, rooted at `div.redline`.
+    pub html: String,
+}
+
+/// One step of the block alignment.
+enum Step {
+    Same(usize),
+    Removed(usize),
+    Added(usize),
+    Modified(usize, usize),
+}
+
+/// Compare `old` with `new`.
+pub fn diff_documents(old: &FlowmarkDocument, new: &FlowmarkDocument) -> DocumentDiff {
+    let old_blocks = &old.content.doc.content;
+    let new_blocks = &new.content.doc.content;
+    let steps = align_blocks(old_blocks, new_blocks);
+
+    let images = HashMap::new();
+    let old_renderer = Renderer::new(old, &images);
+    let new_renderer = Renderer::new(new, &images);
+    let mut html = format!(
+        "<style>\n{}</style>\n<div class=\"redline\">\n",
+        REDLINE_CSS
+    );
+    let mut changes = Vec::new();
+    let mut summary = DiffSummary::default();
+
+    for step in steps {
+        match step {
+            Step::Same(j) => html.push_str(&new_renderer.block(&new_blocks[j])),
+            Step::Removed(i) => {
+                let block = &old_blocks[i];
+                summary.blocks_removed += 1;
+                summary.words_removed += count_words(&block_text(block));
+                html.push_str(&format!(
+                    "<del class=\"block\">{}</del>\n",
+                    old_renderer.block(block).trim_end()
+                ));
+                changes.push(BlockChange {
+                    kind: ChangeKind::Removed,
+                    old_index: Some(i),
+                    new_index: None,
+                    block_type: block.kind.as_str().to_string(),
+                    before: Some(block_text(block)),
+                    after: None,
+                    words: Vec::new(),
+                });
+            }
+            Step::Added(j) => {
+                let block = &new_blocks[j];
+                summary.blocks_added += 1;
+                summary.words_added += count_words(&block_text(block));
+                html.push_str(&format!(
+                    "<ins class=\"block\">{}</ins>\n",
+                    new_renderer.block(block).trim_end()
+                ));
+                changes.push(BlockChange {
+                    kind: ChangeKind::Added,
+                    old_index: None,
+                    new_index: Some(j),
+                    block_type: block.kind.as_str().to_string(),
+                    before: None,
+                    after: Some(block_text(block)),
+                    words: Vec::new(),
+                });
+            }
+            Step::Modified(i, j) => {
+                let (before, after) = (block_text(&old_blocks[i]), block_text(&new_blocks[j]));
+                let words = diff_words(&before, &after);
+                summary.blocks_modified += 1;
+                count_segments(&words, &mut summary);
+                html.push_str(&modified_block_html(&new_blocks[j], &words));
+                changes.push(BlockChange {
+                    kind: ChangeKind::Modified,
+                    old_index: Some(i),
+                    new_index: Some(j),
+                    block_type: new_blocks[j].kind.as_str().to_string(),
+                    before: Some(before),
+                    after: Some(after),
+                    words,
+                });
+            }
+        }
+    }
+    html.push_str("</div>\n");
+
+    let notes = diff_notes(&old.notes, &new.notes);
+    summary.notes_changed = notes.len();
+    if !notes.is_empty() {
+        html.push_str("<section class=\"redline notes\">\n<h2>Notes</h2>\n<ul>\n");
+        for change in &notes {
+            html.push_str(&note_html(change));
+        }
+        html.push_str("</ul>\n</section>\n");
+    }
+
+    DocumentDiff {
+        changes,
+        notes,
+        summary,
+        html,
+    }
+}
+
+/// Just the counts of [`diff_documents`], without the change list and
+/// redline.
+pub fn diff_summary(old: &FlowmarkDocument, new: &FlowmarkDocument) -> DiffSummary {
+    let old_blocks = &old.content.doc.content;
+    let new_blocks = &new.content.doc.content;
+    let mut summary = DiffSummary::default();
+    for step in align_blocks(old_blocks, new_blocks) {
+        match step {
+            Step::Same(_) => {}
+            Step::Removed(i) => {
+                summary.blocks_removed += 1;
+                summary.words_removed += count_words(&block_text(&old_blocks[i]));
+            }
+            Step::Added(j) => {
+                summary.blocks_added += 1;
+                summary.words_added += count_words(&block_text(&new_blocks[j]));
+            }
+            Step::Modified(i, j) => {
+                summary.blocks_modified += 1;
+                let words = diff_words(&block_text(&old_blocks[i]), &block_text(&new_blocks[j]));
+                count_segments(&words, &mut summary);
+            }
+        }
+    }
+    summary.notes_changed = diff_notes(&old.notes, &new.notes).len();
+    summary
+}
+
+/// Word-level diff of two texts. Whitespace and punctuation are compared
+/// as separate tokens, CJK text character by character.
+pub fn diff_words(old: &str, new: &str) -> Vec<Segment> {
+    let old_tokens = tokens(old);
+    let new_tokens = tokens(new);
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut push = |kind, text: &str| match segments.last_mut() {
+        Some(last) if last.kind == kind => last.text.push_str(text),
+        _ => segments.push(Segment {
+            kind,
+            text: text.to_string(),
+        }),
+    };
+
+    let (mut i, mut j) = (0, 0);
+    for (a, b) in lcs(&old_tokens, &new_tokens) {
+        old_tokens[i..a]
+            .iter()
+            .for_each(|t| push(SegmentKind::Removed, t));
+        new_tokens[j..b]
+            .iter()
+            .for_each(|t| push(SegmentKind::Added, t));
+        push(SegmentKind::Unchanged, old_tokens[a]);
+        (i, j) = (a + 1, b + 1);
+    }
+    old_tokens[i..]
+        .iter()
+        .for_each(|t| push(SegmentKind::Removed, t));
+    new_tokens[j..]
+        .iter()
+        .for_each(|t| push(SegmentKind::Added, t));
+    segments
+}
+
+/// Index pairs of a longest common subsequence of `a` and `b`.
+///
+/// The common prefix and suffix are matched directly. If what remains is
+/// too large to align, it is left unmatched.
+pub fn lcs<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n > 0 && m > 0 && (n + 1) * (m + 1) <= MAX_LCS_CELLS {
+        let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if a_mid[i] == b_mid[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if a_mid[i] == b_mid[j] {
+                pairs.push((prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    pairs.extend((0..suffix).map(|k| (a.len() - suffix + k, b.len() - suffix + k)));
+    pairs
+}
+
+/// Align blocks, pairing removed and added blocks between matches into
+/// modifications where they are similar enough.
+fn align_blocks(old: &[Node], new: &[Node]) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (a, b) in lcs(old, new) {
+        pair_changed(old, new, i..a, j..b, &mut steps);
+        steps.push(Step::Same(b));
+        (i, j) = (a + 1, b + 1);
+    }
+    pair_changed(old, new, i..old.len(), j..new.len(), &mut steps);
+    steps
+}
+
+fn pair_changed(
+    old: &[Node],
+    new: &[Node],
+    removed: std::ops::Range<usize>,
+    added: std::ops::Range<usize>,
+    steps: &mut Vec<Step>,
+) {
+    let mut next_added = added.start;
+    for i in removed {
+        let partner = (next_added..added.end).find(|&j| {
+            old[i].kind == new[j].kind
+                && similarity(&block_text(&old[i]), &block_text(&new[j])) >= MIN_SIMILARITY
+        });
+        match partner {
+            Some(j) => {
+                steps.extend((next_added..j).map(Step::Added));
+                steps.push(Step::Modified(i, j));
+                next_added = j + 1;
+            }
+            None => steps.push(Step::Removed(i)),
+        }
+    }
+    steps.extend((next_added..added.end).map(Step::Added));
+}
+
+fn diff_notes(old: &[Note], new: &[Note]) -> Vec<NoteChange> {
+    let mut changes = Vec::new();
+    for note in new {
+        let change = match old.iter().find(|o| o.note_id == note.note_id) {
+            None => NoteChange {
+                kind: ChangeKind::Added,
+                note_id: note.note_id.clone(),
+                old_number: None,
+                new_number: Some(note.number),
+                before: None,
+                after: Some(note.content.clone()),
+                words: Vec::new(),
+            },
+            Some(previous) if previous.content != note.content => NoteChange {
+                kind: ChangeKind::Modified,
+                note_id: note.note_id.clone(),
+                old_number: Some(previous.number),
+                new_number: Some(note.number),
+                before: Some(previous.content.clone()),
+                after: Some(note.content.clone()),
+                words: diff_words(&previous.content, &note.content),
+            },
+            Some(previous) if previous.number != note.number => NoteChange {
+                kind: ChangeKind::Renumbered,
+                note_id: note.note_id.clone(),
+                old_number: Some(previous.number),
+                new_number: Some(note.number),
+                before: None,
+                after: None,
+                words: Vec::new(),
+            },
+            Some(_) => continue,
+        };
+        changes.push(change);
+    }
+    for note in old {
+        if !new.iter().any(|n| n.note_id == note.note_id) {
+            changes.push(NoteChange {
+                kind: ChangeKind::Removed,
+                note_id: note.note_id.clone(),
+                old_number: Some(note.number),
+                new_number: None,
+                before: Some(note.content.clone()),
+                after: None,
+                words: Vec::new(),
+            });
+        }
+    }
+    changes
+}
+
+fn modified_block_html(block: &Node, words: &[Segment]) -> String {
+    let tag = match block.kind {
+        NodeKind::Paragraph => "p".to_string(),
+        NodeKind::Heading => format!("h{}", block.level()),
+        NodeKind::CodeBlock => "pre".to_string(),
+        NodeKind::Blockquote => "blockquote".to_string(),
+        _ => "div".to_string(),
+    };
+    let line_break = if block.kind == NodeKind::CodeBlock {
+        "\n"
+    } else {
+        "<br />"
+    };
+    format!(
+        "<{} class=\"modified\">{}</{}>\n",
+        tag,
+        segments_html(words, line_break),
+        tag
+    )
+}
+
+fn note_html(change: &NoteChange) -> String {
+    let number = match (change.old_number, change.new_number) {
+        (Some(old), Some(new)) if old != new => format!("{} → {}", old, new),
+        (Some(number), _) | (_, Some(number)) => number.to_string(),
+        (None, None) => String::new(),
+    };
+    let body = match change.kind {
+        ChangeKind::Added => format!(
+            "<ins>{}</ins>",
+            escape(change.after.as_deref().unwrap_or(""))
+        ),
+        ChangeKind::Removed => format!(
+            "<del>{}</del>",
+            escape(change.before.as_deref().unwrap_or(""))
+        ),
+        ChangeKind::Modified => segments_html(&change.words, "<br />"),
+        ChangeKind::Renumbered => "<em>renumbered</em>".to_string(),
+    };
+    format!(
+        "<li><span class=\"note-number\">[{}]</span> {}</li>\n",
+        number, body
+    )
+}
+
+fn segments_html(segments: &[Segment], line_break: &str) -> String {
+    segments
+        .iter()
+        .map(|segment| {
+            let text = escape(&segment.text).replace('\n', line_break);
+            match segment.kind {
+                SegmentKind::Unchanged => text,
+                SegmentKind::Added => format!("<ins>{}</ins>", text),
+                SegmentKind::Removed => format!("<del>{}</del>", text),
+            }
+        })
+        .collect()
+}
+
+/// Text of a block, one line per text block it contains.
+fn block_text(block: &Node) -> String {
+    block.textblocks().join("\n")
+}
+
+/// Share of words two texts have in common, from 0 to 1.
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |text| {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for token in tokens(text) {
+            if token.chars().any(char::is_alphanumeric) {
+                *counts.entry(token).or_default() += 1;
+            }
+        }
+        counts
+    };
+    let (a, b) = (words(a), words(b));
+    let total = a.values().sum::<usize>().max(b.values().sum());
+    if total == 0 {
+        return 1.0;
+    }
+    let common: usize = a
+        .iter()
+        .map(|(word, count)| (*count).min(b.get(word).copied().unwrap_or(0)))
+        .sum();
+    common as f64 / total as f64
+}
+
+fn count_words(text: &str) -> usize {
+    tokens(text)
+        .iter()
+        .filter(|token| token.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+fn count_segments(segments: &[Segment], summary: &mut DiffSummary) {
+    for segment in segments {
+        match segment.kind {
+            SegmentKind::Added => summary.words_added += count_words(&segment.text),
+            SegmentKind::Removed => summary.words_removed += count_words(&segment.text),
+            SegmentKind::Unchanged => {}
+        }
+    }
+}
+
+/// Split `text` into words, whitespace runs and single other characters.
+/// CJK ideographs and kana count as single-character words.
+fn tokens(text: &str) -> Vec<&str> {
+    #[derive(PartialEq)]
+    enum Class {
+        Word,
+        Space,
+        Other,
+    }
+    let class = |c: char| {
+        if is_cjk(c) {
+            Class::Other
+        } else if c.is_alphanumeric() || c == '\'' || c == '’' {
+            Class::Word
+        } else if c.is_whitespace() {
+            Class::Space
+        } else {
+            Class::Other
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut current: Option<Class> = None;
+    for (i, c) in text.char_indices() {
+        let next = class(c);
+        let joins = match &current {
+            Some(previous) => *previous == next && next != Class::Other,
+            None => false,
+        };
+        if !joins && i > start {
+            tokens.push(&text[start..i]);
+            start = i;
+        }
+        current = Some(next);
+    }
+    if start < text.len() {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
@@ -0,0 +1,438 @@
+//! Typed model of the FlowMark document stored as `document.json`.
+//!
+//! Mirrors `FlowmarkDocument` in `frontend/src/lib/persistence/serializer.ts`
+//! and the ProseMirror node/mark tree defined by `frontend/src/editor/schema.ts`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::error::FlowError;
+use crate::migrate;
+
+pub const FORMAT: &str = "flowmark";
+pub const CONTENT_TYPE: &str = "prosemirror";
+/// Format version written by this build (`version`).
+pub const FORMAT_VERSION: &str = "0.1";
+/// Editor schema version written by this build (`content.schemaVersion`).
+pub const SCHEMA_VERSION: &str = "1";
+
+/// Top-level FlowMark document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowmarkDocument {
+    pub format: String,
+    pub version: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub content: Content,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<Note>,
+}
+
+/// Editor content wrapper.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub schema_version: String,
+    pub doc: Node,
+}
+
+/// A note stored as metadata next to the document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    pub note_id: String,
+    pub content: String,
+    pub number: u32,
+}
+
+/// Node types known to the editor schema.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum NodeKind {
+    Doc,
+    Paragraph,
+    Blockquote,
+    HorizontalRule,
+    Heading,
+    CodeBlock,
+    Text,
+    Image,
+    HardBreak,
+    OrderedList,
+    BulletList,
+    ListItem,
+    Table,
+    TableRow,
+    TableCell,
+    TableHeader,
+    NoteRef,
+    /// Node type not defined by the editor schema, kept verbatim.
+    Other(String),
+}
+
+impl NodeKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            NodeKind::Doc => "doc",
+            NodeKind::Paragraph => "paragraph",
+            NodeKind::Blockquote => "blockquote",
+            NodeKind::HorizontalRule => "horizontal_rule",
+            NodeKind::Heading => "heading",
+            NodeKind::CodeBlock => "code_block",
+            NodeKind::Text => "text",
+            NodeKind::Image => "image",
+            NodeKind::HardBreak => "hard_break",
+            NodeKind::OrderedList => "ordered_list",
+            NodeKind::BulletList => "bullet_list",
+            NodeKind::ListItem => "list_item",
+            NodeKind::Table => "table",
+            NodeKind::TableRow => "table_row",
+            NodeKind::TableCell => "table_cell",
+            NodeKind::TableHeader => "table_header",
+            NodeKind::NoteRef => "note_ref",
+            NodeKind::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for NodeKind {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "doc" => NodeKind::Doc,
+            "paragraph" => NodeKind::Paragraph,
+            "blockquote" => NodeKind::Blockquote,
+            "horizontal_rule" => NodeKind::HorizontalRule,
+            "heading" => NodeKind::Heading,
+            "code_block" => NodeKind::CodeBlock,
+            "text" => NodeKind::Text,
+            "image" => NodeKind::Image,
+            "hard_break" => NodeKind::HardBreak,
+            "ordered_list" => NodeKind::OrderedList,
+            "bullet_list" => NodeKind::BulletList,
+            "list_item" => NodeKind::ListItem,
+            "table" => NodeKind::Table,
+            "table_row" => NodeKind::TableRow,
+            "table_cell" => NodeKind::TableCell,
+            "table_header" => NodeKind::TableHeader,
+            "note_ref" => NodeKind::NoteRef,
+            _ => NodeKind::Other(name),
+        }
+    }
+}
+
+impl From<NodeKind> for String {
+    fn from(kind: NodeKind) -> Self {
+        match kind {
+            NodeKind::Other(name) => name,
+            kind => kind.as_str().to_string(),
+        }
+    }
+}
+
+/// Mark types known to the editor schema.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum MarkKind {
+    Link,
+    Em,
+    Strong,
+    Code,
+    /// Mark type not defined by the editor schema, kept verbatim.
+    Other(String),
+}
+
+impl MarkKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MarkKind::Link => "link",
+            MarkKind::Em => "em",
+            MarkKind::Strong => "strong",
+            MarkKind::Code => "code",
+            MarkKind::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for MarkKind {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "link" => MarkKind::Link,
+            "em" => MarkKind::Em,
+            "strong" => MarkKind::Strong,
+            "code" => MarkKind::Code,
+            _ => MarkKind::Other(name),
+        }
+    }
+}
+
+impl From<MarkKind> for String {
+    fn from(kind: MarkKind) -> Self {
+        match kind {
+            MarkKind::Other(name) => name,
+            kind => kind.as_str().to_string(),
+        }
+    }
+}
+
+/// ProseMirror node as produced by `Node.toJSON()`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(rename = "type")]
+    pub kind: NodeKind,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub attrs: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content: Vec<Node>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub marks: Vec<Mark>,
+}
+
+/// ProseMirror mark as produced by `Mark.toJSON()`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mark {
+    #[serde(rename = "type")]
+    pub kind: MarkKind,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub attrs: Map<String, Value>,
+}
+
+impl Node {
+    pub fn new(kind: NodeKind) -> Self {
+        Node {
+            kind,
+            attrs: Map::new(),
+            content: Vec::new(),
+            text: None,
+            marks: Vec::new(),
+        }
+    }
+
+    pub fn text(text: impl Into<String>) -> Self {
+        Node {
+            text: Some(text.into()),
+            ..Node::new(NodeKind::Text)
+        }
+    }
+
+    pub fn with_content(mut self, content: Vec<Node>) -> Self {
+        self.content = content;
+        self
+    }
+
+    pub fn with_attr(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.attrs.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn attr_str(&self, key: &str) -> Option<&str> {
+        self.attrs.get(key).and_then(|v| v.as_str())
+    }
+
+    pub fn attr_u64(&self, key: &str) -> Option<u64> {
+        self.attrs.get(key).and_then(|v| v.as_u64())
+    }
+
+    /// Heading level (1-6), defaults to 1 like the schema.
+    pub fn level(&self) -> u8 {
+        self.attr_u64("level")
+            .map(|l| l.clamp(1, 6) as u8)
+            .unwrap_or(1)
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.kind == NodeKind::Text
+    }
+
+    pub fn has_mark(&self, kind: &MarkKind) -> bool {
+        self.marks.iter().any(|m| &m.kind == kind)
+    }
+
+    /// Concatenated text of all descendant text nodes.
+    pub fn text_content(&self) -> String {
+        let mut out = String::new();
+        self.walk(&mut |node| {
+            if let Some(text) = &node.text {
+                out.push_str(text);
+            }
+        });
+        out
+    }
+
+    /// Visit this node and all descendants in document order.
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Node)) {
+        f(self);
+        for child in &self.content {
+            child.walk(f);
+        }
+    }
+
+    /// Like `walk`, with mutable access.
+    pub fn walk_mut(&mut self, f: &mut impl FnMut(&mut Node)) {
+        f(self);
+        for child in &mut self.content {
+            child.walk_mut(f);
+        }
+    }
+
+    /// Text of each text block (paragraph, heading, code block) at or below
+    /// this node, in document order. Hard breaks become spaces.
+    pub fn textblocks(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        self.collect_textblocks(&mut out);
+        out
+    }
+
+    fn collect_textblocks(&self, out: &mut Vec<String>) {
+        let inline_content = self.content.iter().any(|child| {
+            child.is_text()
+                || matches!(
+                    child.kind,
+                    NodeKind::HardBreak | NodeKind::NoteRef | NodeKind::Image
+                )
+        });
+        let textblock = inline_content
+            || matches!(
+                self.kind,
+                NodeKind::Paragraph | NodeKind::Heading | NodeKind::CodeBlock
+            );
+        if !textblock {
+            for child in &self.content {
+                child.collect_textblocks(out);
+            }
+            return;
+        }
+        let mut text = String::new();
+        self.walk(&mut |node| match &node.text {
+            Some(t) => text.push_str(t),
+            None if node.kind == NodeKind::HardBreak => text.push(' '),
+            None => {}
+        });
+        out.push(text);
+    }
+
+    /// Note ids referenced by `note_ref` nodes, in document order (with repeats).
+    pub fn note_refs(&self) -> Vec<&str> {
+        let mut refs = Vec::new();
+        self.walk(&mut |node| {
+            if node.kind == NodeKind::NoteRef {
+                if let Some(id) = node.attr_str("noteId") {
+                    refs.push(id);
+                }
+            }
+        });
+        refs
+    }
+}
+
+impl Note {
+    /// A fresh note id in the `note-<ms>-<random>` form used by the editor.
+    pub fn generate_id() -> String {
+        const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+        let suffix: String = (0..9)
+            .map(|_| ALPHABET[fastrand::usize(..ALPHABET.len())] as char)
+            .collect();
+        format!("note-{}-{}", chrono::Utc::now().timestamp_millis(), suffix)
+    }
+}
+
+impl FlowmarkDocument {
+    /// A new document at the current format version, created now.
+    pub fn new(doc: Node, notes: Vec<Note>) -> Self {
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        FlowmarkDocument {
+            format: FORMAT.to_string(),
+            version: FORMAT_VERSION.to_string(),
+            created_at: now.clone(),
+            updated_at: now,
+            content: Content {
+                kind: CONTENT_TYPE.to_string(),
+                schema_version: SCHEMA_VERSION.to_string(),
+                doc,
+            },
+            notes,
+        }
+    }
+
+    /// Parse and validate a `document.json` payload.
+    ///
+    /// Documents written by older FlowMarker versions are upgraded first.
+    pub fn from_json(json: &str) -> Result<Self, FlowError> {
+        let mut value: Value =
+            serde_json::from_str(json).map_err(|e| FlowError::json("document.json", e))?;
+        let upgraded = migrate::upgrade(&mut value)?;
+        if !upgraded.is_empty() {
+            log::info!("Upgraded document from format {}", upgraded[0]);
+        }
+        let doc: FlowmarkDocument =
+            serde_json::from_value(value).map_err(|e| FlowError::json("document.json", e))?;
+        doc.validate()?;
+        Ok(doc)
+    }
+
+    /// Serialize back to the pretty-printed form written by the frontend.
+    pub fn to_json(&self) -> Result<String, FlowError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| FlowError::other(format!("Failed to serialize document: {}", e)))
+    }
+
+    /// Basic structural checks shared by load and save.
+    pub fn validate(&self) -> Result<(), FlowError> {
+        let reason = if self.format != FORMAT {
+            "Invalid format: expected 'flowmark'".to_string()
+        } else if self.content.kind != CONTENT_TYPE {
+            format!(
+                "Unsupported content type '{}': expected '{}'",
+                self.content.kind, CONTENT_TYPE
+            )
+        } else if self.content.doc.kind != NodeKind::Doc {
+            format!(
+                "Invalid root node '{}': expected 'doc'",
+                self.content.doc.kind.as_str()
+            )
+        } else {
+            return Ok(());
+        };
+        Err(FlowError::WrongFormat { reason })
+    }
+
+    pub fn note(&self, note_id: &str) -> Option<&Note> {
+        self.notes.iter().find(|n| n.note_id == note_id)
+    }
+
+    /// Number notes by the first reference to them in document order and
+    /// update the `number` of every `note_ref` to match. Notes are sorted by
+    /// number; unreferenced notes keep their relative order at the end.
+    pub fn renumber_notes(&mut self) {
+        let mut numbers: HashMap<String, u32> = HashMap::new();
+        for id in self.content.doc.note_refs() {
+            let next = numbers.len() as u32 + 1;
+            numbers.entry(id.to_string()).or_insert(next);
+        }
+        self.content.doc.walk_mut(&mut |node| {
+            if node.kind == NodeKind::NoteRef {
+                if let Some(number) = node.attr_str("noteId").and_then(|id| numbers.get(id)) {
+                    node.attrs.insert("number".to_string(), (*number).into());
+                }
+            }
+        });
+
+        let referenced = numbers.len() as u32;
+        let mut unreferenced = 0;
+        for note in &mut self.notes {
+            note.number = match numbers.get(&note.note_id) {
+                Some(number) => *number,
+                None => {
+                    unreferenced += 1;
+                    referenced + unreferenced
+                }
+            };
+        }
+        self.notes.sort_by_key(|note| note.number);
+    }
+}
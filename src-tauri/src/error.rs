@@ -0,0 +1,404 @@
+//! Errors returned by every command.
+//!
+//! Each error has a stable `code` the frontend can act on (offer a repair,
+//! ask for a password, …) and serializes as
+//!
+//! ```json
+//! { "code": "CORRUPT_ARCHIVE", "message": "…", "path": "/…/book.flm" }
+//! ```
+//!
+//! with the human-readable `message` and whatever context the error has:
+//! `path`, `entry`, `found`/`supported` versions, the `actual` value and
+//! `limit` of an exceeded archive limit or the schema `violations` of a
+//! document.
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::migrate::MigrationError;
+use crate::schema::Violation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlowError {
+    /// Reading or writing a file failed for another reason than below.
+    Io {
+        path: Option<PathBuf>,
+        reason: String,
+    },
+    NotFound {
+        path: PathBuf,
+    },
+    PermissionDenied {
+        path: PathBuf,
+    },
+    /// The file is not a readable ZIP archive, or an entry of it is damaged.
+    CorruptArchive {
+        path: PathBuf,
+        reason: String,
+    },
+    /// The archive has no `document.json`.
+    MissingDocument {
+        path: PathBuf,
+    },
+    /// An entry, revision or asset that doesn't exist.
+    MissingEntry {
+        path: PathBuf,
+        entry: String,
+    },
+    /// JSON that can't be parsed or doesn't have the expected structure.
+    InvalidJson {
+        entry: String,
+        reason: String,
+    },
+    /// Valid JSON that isn't a FlowMark document.
+    WrongFormat {
+        reason: String,
+    },
+    /// Written by a newer FlowMarker, in format or editor schema.
+    UnsupportedVersion {
+        found: String,
+        supported: String,
+        reason: String,
+    },
+    /// A document tree the editor can't load; see `schema::check`.
+    InvalidSchema {
+        path: PathBuf,
+        violations: Vec<Violation>,
+    },
+    /// More entries than the archive limits allow.
+    TooManyEntries {
+        path: PathBuf,
+        count: u64,
+        limit: u64,
+    },
+    /// An entry that decompresses to more than the limit.
+    EntryTooLarge {
+        path: PathBuf,
+        entry: String,
+        size: u64,
+        limit: u64,
+    },
+    /// Entries that decompress to more than the limit together.
+    ArchiveTooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+    /// An entry compressed suspiciously well, as in a ZIP bomb.
+    CompressionRatioTooHigh {
+        path: PathBuf,
+        entry: String,
+        ratio: u64,
+        limit: u64,
+    },
+    /// A JSON entry nested deeper than the limit.
+    NestingTooDeep {
+        path: PathBuf,
+        entry: String,
+        limit: u32,
+    },
+    /// An entry name that is absolute or leads out of the archive.
+    UnsafeEntryName {
+        path: PathBuf,
+        entry: String,
+    },
+    /// The file is encrypted and hasn't been unlocked.
+    PasswordRequired {
+        path: PathBuf,
+    },
+    WrongPassword {
+        path: PathBuf,
+    },
+    /// Changed by another program since it was last opened or saved.
+    ExternalChange {
+        path: PathBuf,
+    },
+    /// A parameter the command can't work with.
+    InvalidArgument {
+        reason: String,
+    },
+    /// Anything else, e.g. a failed export.
+    Other {
+        reason: String,
+    },
+}
+
+impl FlowError {
+    /// Stable identifier of the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FlowError::Io { .. } => "IO",
+            FlowError::NotFound { .. } => "NOT_FOUND",
+            FlowError::PermissionDenied { .. } => "PERMISSION_DENIED",
+            FlowError::CorruptArchive { .. } => "CORRUPT_ARCHIVE",
+            FlowError::MissingDocument { .. } => "MISSING_DOCUMENT",
+            FlowError::MissingEntry { .. } => "MISSING_ENTRY",
+            FlowError::InvalidJson { .. } => "INVALID_JSON",
+            FlowError::WrongFormat { .. } => "WRONG_FORMAT",
+            FlowError::UnsupportedVersion { .. } => "UNSUPPORTED_VERSION",
+            FlowError::InvalidSchema { .. } => "INVALID_SCHEMA",
+            FlowError::TooManyEntries { .. } => "TOO_MANY_ENTRIES",
+            FlowError::EntryTooLarge { .. } => "ENTRY_TOO_LARGE",
+            FlowError::ArchiveTooLarge { .. } => "ARCHIVE_TOO_LARGE",
+            FlowError::CompressionRatioTooHigh { .. } => "COMPRESSION_RATIO_TOO_HIGH",
+            FlowError::NestingTooDeep { .. } => "NESTING_TOO_DEEP",
+            FlowError::UnsafeEntryName { .. } => "UNSAFE_ENTRY_NAME",
+            FlowError::PasswordRequired { .. } => "PASSWORD_REQUIRED",
+            FlowError::WrongPassword { .. } => "WRONG_PASSWORD",
+            FlowError::ExternalChange { .. } => "EXTERNAL_CHANGE",
+            FlowError::InvalidArgument { .. } => "INVALID_ARGUMENT",
+            FlowError::Other { .. } => "OTHER",
+        }
+    }
+
+    /// The file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            FlowError::Io { path, .. } => path.as_deref(),
+            FlowError::NotFound { path }
+            | FlowError::PermissionDenied { path }
+            | FlowError::CorruptArchive { path, .. }
+            | FlowError::MissingDocument { path }
+            | FlowError::MissingEntry { path, .. }
+            | FlowError::InvalidSchema { path, .. }
+            | FlowError::TooManyEntries { path, .. }
+            | FlowError::EntryTooLarge { path, .. }
+            | FlowError::ArchiveTooLarge { path, .. }
+            | FlowError::CompressionRatioTooHigh { path, .. }
+            | FlowError::NestingTooDeep { path, .. }
+            | FlowError::UnsafeEntryName { path, .. }
+            | FlowError::PasswordRequired { path }
+            | FlowError::WrongPassword { path }
+            | FlowError::ExternalChange { path } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Classify an I/O error on `path`.
+    pub fn io(path: impl Into<PathBuf>, e: io::Error) -> Self {
+        let path = path.into();
+        match e.kind() {
+            io::ErrorKind::NotFound => FlowError::NotFound { path },
+            io::ErrorKind::PermissionDenied => FlowError::PermissionDenied { path },
+            _ => FlowError::Io {
+                path: Some(path),
+                reason: e.to_string(),
+            },
+        }
+    }
+
+    /// Classify an error reading or writing the archive at `path`.
+    pub fn zip(path: impl Into<PathBuf>, e: zip::result::ZipError) -> Self {
+        let path = path.into();
+        match e {
+            zip::result::ZipError::Io(e) => FlowError::io(path, e),
+            e => FlowError::CorruptArchive {
+                path,
+                reason: e.to_string(),
+            },
+        }
+    }
+
+    /// Invalid JSON in `entry` (e.g. `document.json`).
+    pub fn json(entry: impl Into<String>, e: serde_json::Error) -> Self {
+        FlowError::InvalidJson {
+            entry: entry.into(),
+            reason: e.to_string(),
+        }
+    }
+
+    pub fn invalid(reason: impl Into<String>) -> Self {
+        FlowError::InvalidArgument {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn other(reason: impl Into<String>) -> Self {
+        FlowError::Other {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowError::Io {
+                path: Some(path),
+                reason,
+            } => write!(f, "Failed to access {}: {}", path.display(), reason),
+            FlowError::Io { path: None, reason } => write!(f, "{}", reason),
+            FlowError::NotFound { path } => write!(f, "{} does not exist", path.display()),
+            FlowError::PermissionDenied { path } => {
+                write!(f, "Permission denied: {}", path.display())
+            }
+            FlowError::CorruptArchive { path, reason } => {
+                write!(f, "{} is damaged: {}", path.display(), reason)
+            }
+            FlowError::MissingDocument { path } => {
+                write!(f, "document.json not found in {}", path.display())
+            }
+            FlowError::MissingEntry { path, entry } => {
+                write!(f, "{} not found in {}", entry, path.display())
+            }
+            FlowError::InvalidJson { entry, reason } => {
+                write!(f, "Invalid JSON in {}: {}", entry, reason)
+            }
+            FlowError::WrongFormat { reason } => write!(f, "{}", reason),
+            FlowError::InvalidSchema { path, violations } => {
+                write!(f, "{} doesn't match the editor schema", path.display())?;
+                if let Some(first) = violations.first() {
+                    write!(f, ": {} at {}", first.message, first.path)?;
+                }
+                match violations.len() {
+                    0 | 1 => Ok(()),
+                    2 => write!(f, " and 1 more problem"),
+                    n => write!(f, " and {} more problems", n - 1),
+                }
+            }
+            FlowError::TooManyEntries { path, count, limit } => write!(
+                f,
+                "{} has {} entries, more than the limit of {}",
+                path.display(),
+                count,
+                limit
+            ),
+            FlowError::EntryTooLarge {
+                path,
+                entry,
+                size,
+                limit,
+            } => write!(
+                f,
+                "{} in {} is {} bytes uncompressed, more than the limit of {}",
+                entry,
+                path.display(),
+                size,
+                limit
+            ),
+            FlowError::ArchiveTooLarge { path, size, limit } => write!(
+                f,
+                "{} is {} bytes uncompressed, more than the limit of {}",
+                path.display(),
+                size,
+                limit
+            ),
+            FlowError::CompressionRatioTooHigh {
+                path,
+                entry,
+                ratio,
+                limit,
+            } => write!(
+                f,
+                "{} in {} is compressed {}:1, more than the limit of {}:1",
+                entry,
+                path.display(),
+                ratio,
+                limit
+            ),
+            FlowError::NestingTooDeep { path, entry, limit } => write!(
+                f,
+                "{} in {} is nested more than {} levels deep",
+                entry,
+                path.display(),
+                limit
+            ),
+            FlowError::UnsafeEntryName { path, entry } => write!(
+                f,
+                "{} contains an entry with an unsafe name: {}",
+                path.display(),
+                entry
+            ),
+            FlowError::PasswordRequired { path } => {
+                write!(f, "{} is encrypted; a password is required", path.display())
+            }
+            FlowError::WrongPassword { .. } => write!(f, "Incorrect password"),
+            FlowError::ExternalChange { path } => write!(
+                f,
+                "{} was changed by another program since it was last opened or saved",
+                path.display()
+            ),
+            FlowError::UnsupportedVersion { reason, .. }
+            | FlowError::InvalidArgument { reason }
+            | FlowError::Other { reason } => {
+                write!(f, "{}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlowError {}
+
+impl Serialize for FlowError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("message", &self.to_string())?;
+        if let Some(path) = self.path() {
+            map.serialize_entry("path", path)?;
+        }
+        match self {
+            FlowError::MissingEntry { entry, .. }
+            | FlowError::InvalidJson { entry, .. }
+            | FlowError::UnsafeEntryName { entry, .. } => map.serialize_entry("entry", entry)?,
+            FlowError::TooManyEntries { count, limit, .. } => {
+                map.serialize_entry("actual", count)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            FlowError::ArchiveTooLarge { size, limit, .. } => {
+                map.serialize_entry("actual", size)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            FlowError::EntryTooLarge {
+                entry, size, limit, ..
+            }
+            | FlowError::CompressionRatioTooHigh {
+                entry,
+                ratio: size,
+                limit,
+                ..
+            } => {
+                map.serialize_entry("entry", entry)?;
+                map.serialize_entry("actual", size)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            FlowError::InvalidSchema { violations, .. } => {
+                map.serialize_entry("violations", violations)?;
+            }
+            FlowError::NestingTooDeep { entry, limit, .. } => {
+                map.serialize_entry("entry", entry)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            FlowError::UnsupportedVersion {
+                found, supported, ..
+            } => {
+                map.serialize_entry("found", found)?;
+                map.serialize_entry("supported", supported)?;
+            }
+            _ => {}
+        }
+        map.end()
+    }
+}
+
+impl From<MigrationError> for FlowError {
+    fn from(e: MigrationError) -> Self {
+        match &e {
+            MigrationError::UnsupportedVersion { found, supported }
+            | MigrationError::UnsupportedSchema { found, supported } => {
+                FlowError::UnsupportedVersion {
+                    found: found.clone(),
+                    supported: supported.clone(),
+                    reason: e.to_string(),
+                }
+            }
+            MigrationError::InvalidVersion(_) => FlowError::WrongFormat {
+                reason: e.to_string(),
+            },
+            MigrationError::Failed { .. } => FlowError::other(e.to_string()),
+        }
+    }
+}
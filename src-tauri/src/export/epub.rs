@@ -0,0 +1,355 @@
+//! EPUB 3 packaging of FlowMark documents.
+//!
+//! The document is split into one XHTML chapter per top-level heading. Notes
+//! become `epub:type="footnote"` asides at the end of each chapter that
+//! references them, so reading systems can show them as pop-ups. A nav
+//! document and an NCX are generated for EPUB 3 and EPUB 2 readers.
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::assets;
+use crate::atomic;
+use crate::document::{FlowmarkDocument, Node, NodeKind};
+use crate::error::FlowError;
+use crate::export::html::{self, escape, note_paragraphs, Renderer};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EpubOptions {
+    /// Book title; defaults to the first heading.
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// BCP 47 language tag.
+    pub language: String,
+    /// Embed the default HTML export theme.
+    pub embed_theme: bool,
+}
+
+impl Default for EpubOptions {
+    fn default() -> Self {
+        EpubOptions {
+            title: None,
+            author: None,
+            language: "en".to_string(),
+            embed_theme: true,
+        }
+    }
+}
+
+struct Chapter<'a> {
+    title: String,
+    nodes: &'a [Node],
+}
+
+impl Chapter<'_> {
+    fn file_name(index: usize) -> String {
+        format!("chapter-{:03}.xhtml", index + 1)
+    }
+}
+
+/// Write `doc` as an EPUB file at `output_path`.
+///
+/// `archive_path` is the .flm the document was read from; referenced assets
+/// are copied into the book from there.
+pub fn write_epub(
+    doc: &FlowmarkDocument,
+    options: &EpubOptions,
+    archive_path: Option<&Path>,
+    output_path: &Path,
+) -> Result<(), FlowError> {
+    let title = options
+        .title
+        .clone()
+        .or_else(|| html::document_title(doc))
+        .unwrap_or_else(|| "Untitled".to_string());
+    let chapters = split_chapters(&doc.content.doc.content, &title);
+
+    let mut images: Vec<(String, Vec<u8>)> = Vec::new();
+    if let Some(archive_path) = archive_path {
+        let mut names: Vec<String> = assets::referenced(doc).into_iter().collect();
+        names.sort();
+        for name in names {
+            let bytes = assets::read_asset(archive_path, &name)?;
+            images.push((name, bytes));
+        }
+    }
+
+    let identifier = book_identifier(doc);
+    let modified = modified_timestamp(&doc.updated_at);
+    let no_images = HashMap::new();
+
+    atomic::write_with(output_path, |file| {
+        let mut zip = ZipWriter::new(file);
+        let deflated = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(0o644);
+        let stored = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(0o644);
+
+        let mut add = |name: &str, data: &[u8], options: FileOptions| -> Result<(), FlowError> {
+            zip.start_file(name, options)
+                .map_err(|e| FlowError::other(format!("Failed to add {} to EPUB: {}", name, e)))?;
+            zip.write_all(data)
+                .map_err(|e| FlowError::io(output_path, e))
+        };
+
+        // The mimetype entry must come first and be stored uncompressed
+        add("mimetype", b"application/epub+zip", stored)?;
+        add("META-INF/container.xml", CONTAINER_XML.as_bytes(), deflated)?;
+
+        for (index, chapter) in chapters.iter().enumerate() {
+            let mut renderer = Renderer::new(doc, &no_images);
+            renderer.epub = true;
+            let xhtml = chapter_xhtml(doc, chapter, &renderer, &options.language);
+            add(
+                &format!("OEBPS/{}", Chapter::file_name(index)),
+                xhtml.as_bytes(),
+                deflated,
+            )?;
+        }
+
+        let css = if options.embed_theme {
+            html::THEME_CSS
+        } else {
+            ""
+        };
+        add("OEBPS/style.css", css.as_bytes(), deflated)?;
+        add(
+            "OEBPS/nav.xhtml",
+            nav_xhtml(&title, &chapters, &options.language).as_bytes(),
+            deflated,
+        )?;
+        add(
+            "OEBPS/toc.ncx",
+            toc_ncx(&title, &identifier, &chapters).as_bytes(),
+            deflated,
+        )?;
+
+        for (name, bytes) in &images {
+            add(&format!("OEBPS/{}", name), bytes, stored)?;
+        }
+
+        let opf = package_opf(&title, &identifier, &modified, options, &chapters, &images);
+        add("OEBPS/content.opf", opf.as_bytes(), deflated)?;
+
+        zip.finish()
+            .map_err(|e| FlowError::other(format!("Failed to finalize EPUB: {}", e)))
+    })
+}
+
+/// Split top-level content at the highest heading level used.
+fn split_chapters<'a>(nodes: &'a [Node], book_title: &str) -> Vec<Chapter<'a>> {
+    let split_level = nodes
+        .iter()
+        .filter(|node| node.kind == NodeKind::Heading)
+        .map(Node::level)
+        .min();
+    let is_split =
+        |node: &Node| node.kind == NodeKind::Heading && Some(node.level()) == split_level;
+
+    let mut chapters = Vec::new();
+    let mut start = 0;
+    for index in 1..=nodes.len() {
+        if index == nodes.len() || is_split(&nodes[index]) {
+            let slice = &nodes[start..index];
+            if !slice.is_empty() {
+                let title = match slice.first() {
+                    Some(first) if is_split(first) => first.text_content().trim().to_string(),
+                    _ => String::new(),
+                };
+                chapters.push(Chapter {
+                    title,
+                    nodes: slice,
+                });
+            }
+            start = index;
+        }
+    }
+
+    if chapters.is_empty() {
+        chapters.push(Chapter {
+            title: String::new(),
+            nodes,
+        });
+    }
+    for (index, chapter) in chapters.iter_mut().enumerate() {
+        if chapter.title.is_empty() {
+            chapter.title = if index == 0 {
+                book_title.to_string()
+            } else {
+                format!("Chapter {}", index + 1)
+            };
+        }
+    }
+    chapters
+}
+
+fn chapter_xhtml(
+    doc: &FlowmarkDocument,
+    chapter: &Chapter,
+    renderer: &Renderer,
+    language: &str,
+) -> String {
+    let body = renderer.blocks(chapter.nodes);
+
+    // Footnotes for the notes referenced in this chapter, in order of reference
+    let mut seen = HashSet::new();
+    let mut footnotes = String::new();
+    for node in chapter.nodes {
+        for note_id in node.note_refs() {
+            if !seen.insert(note_id) {
+                continue;
+            }
+            if let Some(note) = doc.note(note_id) {
+                footnotes.push_str(&format!(
+                    "<aside epub:type=\"footnote\" role=\"doc-footnote\" id=\"note-{}\"><p><a href=\"#noteref-{}\">{}.</a></p>{}</aside>\n",
+                    note.number,
+                    note.number,
+                    note.number,
+                    note_paragraphs(&note.content)
+                ));
+            }
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{lang}\" lang=\"{lang}\">\n<head>\n<meta charset=\"utf-8\" />\n<title>{title}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\" />\n</head>\n<body>\n<section epub:type=\"chapter\" role=\"doc-chapter\">\n{body}</section>\n{footnotes}</body>\n</html>\n",
+        lang = escape(language),
+        title = escape(&chapter.title),
+        body = body,
+        footnotes = footnotes
+    )
+}
+
+fn nav_xhtml(title: &str, chapters: &[Chapter], language: &str) -> String {
+    let items: String = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| {
+            format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                Chapter::file_name(index),
+                escape(&chapter.title)
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{lang}\" lang=\"{lang}\">\n<head>\n<meta charset=\"utf-8\" />\n<title>{title}</title>\n</head>\n<body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>{title}</h1>\n<ol>\n{items}</ol>\n</nav>\n</body>\n</html>\n",
+        lang = escape(language),
+        title = escape(title),
+        items = items
+    )
+}
+
+fn toc_ncx(title: &str, identifier: &str, chapters: &[Chapter]) -> String {
+    let points: String = chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| {
+            format!(
+                "<navPoint id=\"navpoint-{n}\" playOrder=\"{n}\">\n<navLabel><text>{label}</text></navLabel>\n<content src=\"{src}\" />\n</navPoint>\n",
+                n = index + 1,
+                label = escape(&chapter.title),
+                src = Chapter::file_name(index)
+            )
+        })
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n<head>\n<meta name=\"dtb:uid\" content=\"{id}\" />\n<meta name=\"dtb:depth\" content=\"1\" />\n<meta name=\"dtb:totalPageCount\" content=\"0\" />\n<meta name=\"dtb:maxPageNumber\" content=\"0\" />\n</head>\n<docTitle><text>{title}</text></docTitle>\n<navMap>\n{points}</navMap>\n</ncx>\n",
+        id = escape(identifier),
+        title = escape(title),
+        points = points
+    )
+}
+
+fn package_opf(
+    title: &str,
+    identifier: &str,
+    modified: &str,
+    options: &EpubOptions,
+    chapters: &[Chapter],
+    images: &[(String, Vec<u8>)],
+) -> String {
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n",
+        escape(identifier),
+        escape(title),
+        escape(&options.language),
+        modified
+    );
+    if let Some(author) = options.author.as_deref().filter(|a| !a.trim().is_empty()) {
+        metadata.push_str(&format!(
+            "<dc:creator id=\"author\">{}</dc:creator>\n",
+            escape(author.trim())
+        ));
+    }
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\" />\n<item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\" />\n<item id=\"style\" href=\"style.css\" media-type=\"text/css\" />\n",
+    );
+    let mut spine = String::new();
+    for index in 0..chapters.len() {
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{n}\" href=\"{href}\" media-type=\"application/xhtml+xml\" />\n",
+            n = index + 1,
+            href = Chapter::file_name(index)
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\" />\n", index + 1));
+    }
+    for (index, (name, _)) in images.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"asset-{}\" href=\"{}\" media-type=\"{}\" />\n",
+            index + 1,
+            escape(name),
+            assets::mime_type(name)
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{lang}\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{metadata}</metadata>\n<manifest>\n{manifest}</manifest>\n<spine toc=\"ncx\">\n{spine}</spine>\n</package>\n",
+        lang = escape(&options.language),
+        metadata = metadata,
+        manifest = manifest,
+        spine = spine
+    )
+}
+
+/// Stable `urn:uuid` derived from the document's creation time and first content.
+fn book_identifier(doc: &FlowmarkDocument) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(doc.created_at.as_bytes());
+    hasher.update(html::document_title(doc).unwrap_or_default().as_bytes());
+    let hash = hasher.finalize();
+    let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// `dcterms:modified` requires `CCYY-MM-DDThh:mm:ssZ`.
+fn modified_timestamp(updated_at: &str) -> String {
+    let time = chrono::DateTime::parse_from_rfc3339(updated_at)
+        .map(|t| t.with_timezone(&chrono::Utc))
+        .unwrap_or_else(|_| chrono::Utc::now());
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml" />
+</rootfiles>
+</container>
+"#;
@@ -0,0 +1,482 @@
+//! Standalone HTML rendering of FlowMark documents.
+//!
+//! Produces a single self-contained file: notes become an endnotes section
+//! linked from the `sup.note-ref` anchors, the theme is embedded as a
+//! `<style>` block and archive images can be inlined as data URIs.
+
+use base64::Engine;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::assets;
+use crate::atomic;
+use crate::document::{FlowmarkDocument, Mark, MarkKind, Node, NodeKind};
+use crate::error::FlowError;
+use crate::export::{extract_assets, note_number, referenced_notes};
+
+/// Default stylesheet embedded when `embed_theme` is set.
+pub const THEME_CSS: &str = r#"body {
+  margin: 0 auto;
+  max-width: 42em;
+  padding: 2em 1.5em;
+  font-family: Georgia, "Times New Roman", serif;
+  font-size: 1.1em;
+  line-height: 1.6;
+  color: #1a1a1a;
+  background: #ffffff;
+}
+h1, h2, h3, h4, h5, h6 {
+  font-family: system-ui, Avenir, Helvetica, Arial, sans-serif;
+  line-height: 1.2;
+}
+a { color: #3b44c4; }
+blockquote {
+  margin: 1em 0;
+  padding-left: 1em;
+  border-left: 3px solid #cccccc;
+  color: #555555;
+}
+pre {
+  padding: 1em;
+  overflow-x: auto;
+  background: #f5f5f5;
+  border-radius: 4px;
+}
+code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 0.9em; }
+table { border-collapse: collapse; margin: 1em 0; }
+th, td { border: 1px solid #cccccc; padding: 0.4em 0.7em; vertical-align: top; }
+th { background: #f5f5f5; }
+img { max-width: 100%; }
+sup.note-ref a { text-decoration: none; }
+section.endnotes { margin-top: 3em; font-size: 0.9em; }
+@media (prefers-color-scheme: dark) {
+  body { color: #e0e0e0; background: #1a1a1a; }
+  a { color: #8f96ff; }
+  pre, th { background: #2a2a2a; }
+  blockquote { border-color: #444444; color: #aaaaaa; }
+  th, td { border-color: #444444; }
+}
+"#;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HtmlOptions {
+    /// Document title; defaults to the first heading.
+    pub title: Option<String>,
+    /// Embed the default theme.
+    pub embed_theme: bool,
+    /// Extra CSS appended after the theme.
+    pub css: Option<String>,
+    /// Inline archive images as base64 data URIs.
+    pub inline_images: bool,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        HtmlOptions {
+            title: None,
+            embed_theme: true,
+            css: None,
+            inline_images: true,
+        }
+    }
+}
+
+/// Render `doc` as a complete HTML page.
+///
+/// `archive_path` is needed to inline images stored in the archive.
+pub fn to_html(
+    doc: &FlowmarkDocument,
+    options: &HtmlOptions,
+    archive_path: Option<&Path>,
+) -> Result<String, FlowError> {
+    let mut images: HashMap<String, String> = HashMap::new();
+    if options.inline_images {
+        if let Some(archive_path) = archive_path {
+            for name in assets::referenced(doc) {
+                let bytes = assets::read_asset(archive_path, &name)?;
+                let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+                let uri = format!("data:{};base64,{}", assets::mime_type(&name), data);
+                images.insert(name, uri);
+            }
+        }
+    }
+
+    let renderer = Renderer::new(doc, &images);
+    let body = renderer.blocks(&doc.content.doc.content);
+    let endnotes = renderer.endnotes();
+
+    let title = options
+        .title
+        .clone()
+        .or_else(|| document_title(doc))
+        .unwrap_or_else(|| "Untitled".to_string());
+
+    let mut style = String::new();
+    if options.embed_theme {
+        style.push_str(THEME_CSS);
+    }
+    if let Some(css) = &options.css {
+        style.push_str(css);
+        style.push('\n');
+    }
+
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\" />\n");
+    out.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\" />\n");
+    out.push_str("<meta name=\"generator\" content=\"FlowMarker\" />\n");
+    out.push_str(&format!("<title>{}</title>\n", escape(&title)));
+    if !style.is_empty() {
+        out.push_str(&format!(
+            "<style>\n{}</style>\n",
+            style.replace("</", "<\\/")
+        ));
+    }
+    out.push_str("</head>\n<body>\n<article>\n");
+    out.push_str(&body);
+    out.push_str("</article>\n");
+    out.push_str(&endnotes);
+    out.push_str("</body>\n</html>\n");
+    Ok(out)
+}
+
+/// Write `doc` as an HTML page at `output_path`.
+///
+/// Images that are not inlined are extracted next to the page.
+pub fn write_html(
+    doc: &FlowmarkDocument,
+    options: &HtmlOptions,
+    archive_path: Option<&Path>,
+    output_path: &Path,
+) -> Result<(), FlowError> {
+    let html = to_html(doc, options, archive_path)?;
+    atomic::write(output_path, html.as_bytes())?;
+
+    if let (false, Some(archive_path)) = (options.inline_images, archive_path) {
+        let out_dir = output_path.parent().unwrap_or(Path::new("."));
+        extract_assets(archive_path, doc, out_dir)?;
+    }
+    Ok(())
+}
+
+/// Text of the first heading, if any.
+pub fn document_title(doc: &FlowmarkDocument) -> Option<String> {
+    doc.content
+        .doc
+        .content
+        .iter()
+        .find(|node| node.kind == NodeKind::Heading)
+        .map(|node| node.text_content().trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// Renders document nodes to (X)HTML-compatible markup.
+pub(crate) struct Renderer<'a> {
+    doc: &'a FlowmarkDocument,
+    /// Replacement `src` for archive images.
+    images: &'a HashMap<String, String>,
+    /// Emit `epub:type` semantics for EPUB content documents.
+    pub epub: bool,
+    ref_counts: RefCell<HashMap<u64, usize>>,
+    slugs: RefCell<HashMap<String, usize>>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(doc: &'a FlowmarkDocument, images: &'a HashMap<String, String>) -> Self {
+        Renderer {
+            doc,
+            images,
+            epub: false,
+            ref_counts: RefCell::new(HashMap::new()),
+            slugs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn blocks(&self, nodes: &[Node]) -> String {
+        nodes.iter().map(|node| self.block(node)).collect()
+    }
+
+    pub fn block(&self, node: &Node) -> String {
+        match node.kind {
+            NodeKind::Paragraph => format!("<p>{}</p>\n", self.inline(&node.content)),
+            NodeKind::Heading => {
+                let level = node.level();
+                let id = self.slug(&node.text_content());
+                format!(
+                    "<h{} id=\"{}\">{}</h{}>\n",
+                    level,
+                    id,
+                    self.inline(&node.content),
+                    level
+                )
+            }
+            NodeKind::Blockquote => {
+                format!(
+                    "<blockquote>\n{}</blockquote>\n",
+                    self.blocks(&node.content)
+                )
+            }
+            NodeKind::HorizontalRule => "<hr />\n".to_string(),
+            NodeKind::CodeBlock => {
+                let class = node
+                    .attr_str("language")
+                    .or_else(|| node.attr_str("params"))
+                    .filter(|l| !l.is_empty())
+                    .map(|l| format!(" class=\"language-{}\"", escape(l)))
+                    .unwrap_or_default();
+                format!(
+                    "<pre><code{}>{}</code></pre>\n",
+                    class,
+                    escape(&node.text_content())
+                )
+            }
+            NodeKind::BulletList => format!("<ul>\n{}</ul>\n", self.blocks(&node.content)),
+            NodeKind::OrderedList => {
+                let start = match node.attr_u64("order") {
+                    Some(order) if order != 1 => format!(" start=\"{}\"", order),
+                    _ => String::new(),
+                };
+                format!("<ol{}>\n{}</ol>\n", start, self.blocks(&node.content))
+            }
+            NodeKind::ListItem => {
+                // Single-paragraph items are rendered tight
+                match node.content.as_slice() {
+                    [only] if only.kind == NodeKind::Paragraph => {
+                        format!("<li>{}</li>\n", self.inline(&only.content))
+                    }
+                    children => format!("<li>\n{}</li>\n", self.blocks(children)),
+                }
+            }
+            NodeKind::Table => self.table(node),
+            NodeKind::TableRow => format!("<tr>\n{}</tr>\n", self.blocks(&node.content)),
+            NodeKind::TableCell | NodeKind::TableHeader => {
+                let tag = if node.kind == NodeKind::TableHeader {
+                    "th"
+                } else {
+                    "td"
+                };
+                let mut attrs = String::new();
+                for key in ["colspan", "rowspan"] {
+                    if let Some(span) = node.attr_u64(key).filter(|span| *span > 1) {
+                        attrs.push_str(&format!(" {}=\"{}\"", key, span));
+                    }
+                }
+                let content = match node.content.as_slice() {
+                    [only] if only.kind == NodeKind::Paragraph => self.inline(&only.content),
+                    children => self.blocks(children),
+                };
+                format!("<{}{}>{}</{}>\n", tag, attrs, content, tag)
+            }
+            NodeKind::Text | NodeKind::Image | NodeKind::NoteRef | NodeKind::HardBreak => {
+                format!("<p>{}</p>\n", self.inline(std::slice::from_ref(node)))
+            }
+            _ => {
+                if node.content.iter().all(|child| child.is_text()) {
+                    format!("<div>{}</div>\n", self.inline(&node.content))
+                } else {
+                    format!("<div>\n{}</div>\n", self.blocks(&node.content))
+                }
+            }
+        }
+    }
+
+    fn table(&self, node: &Node) -> String {
+        let mut out = String::from("<table>\n");
+        let mut rows = node.content.iter().peekable();
+
+        // A leading row made only of header cells becomes the table head
+        if let Some(first) = rows.peek() {
+            let is_head = !first.content.is_empty()
+                && first
+                    .content
+                    .iter()
+                    .all(|cell| cell.kind == NodeKind::TableHeader);
+            if is_head {
+                out.push_str(&format!("<thead>\n{}</thead>\n", self.block(first)));
+                rows.next();
+            }
+        }
+
+        let body: String = rows.map(|row| self.block(row)).collect();
+        if !body.is_empty() {
+            out.push_str(&format!("<tbody>\n{}</tbody>\n", body));
+        }
+        out.push_str("</table>\n");
+        out
+    }
+
+    pub fn inline(&self, nodes: &[Node]) -> String {
+        let mut out = String::new();
+        for node in nodes {
+            let mut marks: Vec<&Mark> = node.marks.iter().collect();
+            marks.sort_by_key(|mark| mark_rank(mark));
+
+            let mut html = match node.kind {
+                NodeKind::Text => escape(node.text.as_deref().unwrap_or("")),
+                NodeKind::HardBreak => "<br />".to_string(),
+                NodeKind::Image => self.image(node),
+                NodeKind::NoteRef => self.note_ref(node),
+                _ => escape(&node.text_content()),
+            };
+            for mark in marks.iter().rev() {
+                html = wrap_mark(mark, &html);
+            }
+            out.push_str(&html);
+        }
+        out
+    }
+
+    fn image(&self, node: &Node) -> String {
+        let src = node.attr_str("src").unwrap_or("");
+        let src = self.images.get(src).map(String::as_str).unwrap_or(src);
+        let mut html = format!(
+            "<img src=\"{}\" alt=\"{}\"",
+            escape(src),
+            escape(node.attr_str("alt").unwrap_or(""))
+        );
+        if let Some(title) = node.attr_str("title").filter(|t| !t.is_empty()) {
+            html.push_str(&format!(" title=\"{}\"", escape(title)));
+        }
+        html.push_str(" />");
+        html
+    }
+
+    fn note_ref(&self, node: &Node) -> String {
+        let note_id = node.attr_str("noteId");
+        let number = note_number(self.doc, note_id, node.attr_u64("number"));
+
+        // Repeated references to one note get distinct anchors
+        let mut counts = self.ref_counts.borrow_mut();
+        let count = counts.entry(number).or_insert(0);
+        *count += 1;
+        let anchor = if *count == 1 {
+            format!("noteref-{}", number)
+        } else {
+            format!("noteref-{}-{}", number, count)
+        };
+
+        let epub_type = if self.epub {
+            " epub:type=\"noteref\""
+        } else {
+            ""
+        };
+        format!(
+            "<sup class=\"note-ref\" id=\"{}\" data-note-id=\"{}\" data-note-number=\"{}\"><a href=\"#note-{}\"{} role=\"doc-noteref\">{}</a></sup>",
+            anchor,
+            escape(note_id.unwrap_or("")),
+            number,
+            number,
+            epub_type,
+            number
+        )
+    }
+
+    /// Endnotes section for the notes referenced in the document.
+    pub fn endnotes(&self) -> String {
+        let notes = referenced_notes(self.doc);
+        if notes.is_empty() {
+            return String::new();
+        }
+
+        let mut out =
+            String::from("<section class=\"endnotes\" role=\"doc-endnotes\">\n<hr />\n<ol>\n");
+        for note in notes {
+            out.push_str(&format!(
+                "<li id=\"note-{}\" value=\"{}\" role=\"doc-endnote\">{} <a href=\"#noteref-{}\" class=\"note-backref\" role=\"doc-backlink\">&#8617;</a></li>\n",
+                note.number,
+                note.number,
+                note_paragraphs(&note.content),
+                note.number
+            ));
+        }
+        out.push_str("</ol>\n</section>\n");
+        out
+    }
+
+    /// Unique id for a heading.
+    fn slug(&self, text: &str) -> String {
+        let mut slug = String::new();
+        for c in text.trim().chars() {
+            if c.is_alphanumeric() {
+                slug.extend(c.to_lowercase());
+            } else if !slug.ends_with('-') && !slug.is_empty() {
+                slug.push('-');
+            }
+        }
+        let slug = match slug.trim_end_matches('-') {
+            "" => "section".to_string(),
+            s => s.to_string(),
+        };
+        let mut slugs = self.slugs.borrow_mut();
+        let count = slugs.entry(slug.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            slug
+        } else {
+            format!("{}-{}", slug, count)
+        }
+    }
+}
+
+/// Plain-text note content as paragraphs.
+pub(crate) fn note_paragraphs(content: &str) -> String {
+    content
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| format!("<p>{}</p>", escape(p).replace('\n', "<br />")))
+        .collect()
+}
+
+/// Schema order of marks: the first wraps outermost.
+fn mark_rank(mark: &Mark) -> u8 {
+    match mark.kind {
+        MarkKind::Link => 0,
+        MarkKind::Em => 1,
+        MarkKind::Strong => 2,
+        MarkKind::Code => 3,
+        MarkKind::Other(_) => 4,
+    }
+}
+
+fn wrap_mark(mark: &Mark, html: &str) -> String {
+    match mark.kind {
+        MarkKind::Link => {
+            let href = mark
+                .attrs
+                .get("href")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let title = mark
+                .attrs
+                .get("title")
+                .and_then(|v| v.as_str())
+                .filter(|t| !t.is_empty())
+                .map(|t| format!(" title=\"{}\"", escape(t)))
+                .unwrap_or_default();
+            format!("<a href=\"{}\"{}>{}</a>", escape(href), title, html)
+        }
+        MarkKind::Em => format!("<em>{}</em>", html),
+        MarkKind::Strong => format!("<strong>{}</strong>", html),
+        MarkKind::Code => format!("<code>{}</code>", html),
+        MarkKind::Other(_) => html.to_string(),
+    }
+}
+
+/// Escape text for element content and double-quoted attributes.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,397 @@
+//! CommonMark/GFM rendering of FlowMark documents.
+//!
+//! Tables use GFM pipe syntax and notes become `[^n]` footnotes with their
+//! definitions collected at the end of the document.
+
+use std::path::Path;
+
+use crate::atomic;
+use crate::document::{FlowmarkDocument, Mark, MarkKind, Node, NodeKind};
+use crate::error::FlowError;
+use crate::export::{extract_assets, note_number, referenced_notes};
+
+/// Render `doc` as Markdown.
+pub fn to_markdown(doc: &FlowmarkDocument) -> String {
+    let renderer = Renderer { doc };
+    let mut out = renderer.blocks(&doc.content.doc.content);
+
+    let notes = referenced_notes(doc);
+    if !notes.is_empty() {
+        out.push_str("\n\n");
+        let definitions: Vec<String> = notes
+            .iter()
+            .map(|note| {
+                let content = indent(note.content.trim(), "    ");
+                format!("[^{}]: {}", note.number, content)
+            })
+            .collect();
+        out.push_str(&definitions.join("\n"));
+    }
+
+    out.push('\n');
+    out
+}
+
+/// Write `doc` as Markdown at `output_path`.
+///
+/// Assets referenced from the archive at `archive_path` are extracted next
+/// to the file so the relative links resolve.
+pub fn write_markdown(
+    doc: &FlowmarkDocument,
+    archive_path: Option<&Path>,
+    output_path: &Path,
+) -> Result<(), FlowError> {
+    atomic::write(output_path, to_markdown(doc).as_bytes())?;
+
+    if let Some(archive_path) = archive_path {
+        let out_dir = output_path.parent().unwrap_or(Path::new("."));
+        extract_assets(archive_path, doc, out_dir)?;
+    }
+    Ok(())
+}
+
+struct Renderer<'a> {
+    doc: &'a FlowmarkDocument,
+}
+
+impl Renderer<'_> {
+    fn blocks(&self, nodes: &[Node]) -> String {
+        nodes
+            .iter()
+            .map(|node| self.block(node))
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn block(&self, node: &Node) -> String {
+        match node.kind {
+            NodeKind::Paragraph => escape_line_start(&self.inline(&node.content)),
+            NodeKind::Heading => {
+                let text = self.inline(&node.content).replace('\n', " ");
+                format!("{} {}", "#".repeat(node.level() as usize), text)
+            }
+            NodeKind::Blockquote => {
+                let content = self.blocks(&node.content);
+                content
+                    .lines()
+                    .map(|line| {
+                        if line.is_empty() {
+                            ">".to_string()
+                        } else {
+                            format!("> {}", line)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            NodeKind::HorizontalRule => "---".to_string(),
+            NodeKind::CodeBlock => {
+                let code = node.text_content();
+                let fence = "`".repeat((longest_run(&code, '`') + 1).max(3));
+                let language = node
+                    .attr_str("language")
+                    .or_else(|| node.attr_str("params"))
+                    .unwrap_or("");
+                format!("{}{}\n{}\n{}", fence, language, code, fence)
+            }
+            NodeKind::BulletList => self.list(node, None),
+            NodeKind::OrderedList => {
+                let start = node.attr_u64("order").unwrap_or(1);
+                self.list(node, Some(start))
+            }
+            NodeKind::ListItem => self.blocks(&node.content),
+            NodeKind::Table => self.table(node),
+            NodeKind::Image | NodeKind::NoteRef | NodeKind::Text | NodeKind::HardBreak => {
+                self.inline(std::slice::from_ref(node))
+            }
+            _ => {
+                if node.content.iter().all(|child| child.is_text()) {
+                    self.inline(&node.content)
+                } else {
+                    self.blocks(&node.content)
+                }
+            }
+        }
+    }
+
+    fn list(&self, node: &Node, start: Option<u64>) -> String {
+        // A list is tight when no item holds more than one block besides nested lists
+        let tight = node.content.iter().all(|item| {
+            item.content
+                .iter()
+                .filter(|child| !matches!(child.kind, NodeKind::BulletList | NodeKind::OrderedList))
+                .count()
+                <= 1
+        });
+        let items: Vec<String> = node
+            .content
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let marker = match start {
+                    Some(start) => format!("{}. ", start + index as u64),
+                    None => "- ".to_string(),
+                };
+                let content = if tight {
+                    item.content
+                        .iter()
+                        .map(|child| self.block(child))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                } else {
+                    self.block(item)
+                };
+                let padding = " ".repeat(marker.len());
+                format!("{}{}", marker, indent(&content, &padding))
+            })
+            .collect();
+        items.join(if tight { "\n" } else { "\n\n" })
+    }
+
+    fn table(&self, node: &Node) -> String {
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        for row in &node.content {
+            let mut cells = Vec::new();
+            for cell in &row.content {
+                let text = cell
+                    .content
+                    .iter()
+                    .map(|block| self.inline(&block.content))
+                    .collect::<Vec<_>>()
+                    .join("<br>")
+                    .replace('\n', " ")
+                    .replace('|', "\\|");
+                cells.push(text);
+                // Merged cells have no GFM equivalent; pad with empty cells
+                let colspan = cell.attr_u64("colspan").unwrap_or(1).max(1);
+                for _ in 1..colspan {
+                    cells.push(String::new());
+                }
+            }
+            rows.push(cells);
+        }
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+
+        let format_row = |cells: &[String]| {
+            let mut padded = cells.to_vec();
+            padded.resize(columns, String::new());
+            format!("| {} |", padded.join(" | "))
+        };
+
+        let mut lines = vec![format_row(&rows[0])];
+        lines.push(format!("|{}", " --- |".repeat(columns)));
+        for row in &rows[1..] {
+            lines.push(format_row(row));
+        }
+        lines.join("\n")
+    }
+
+    /// Render inline content, merging marks across adjacent nodes.
+    fn inline(&self, nodes: &[Node]) -> String {
+        let mut out = String::new();
+        let mut active: Vec<&Mark> = Vec::new();
+        // Trailing whitespace of the previous node, emitted after any closing delimiters
+        let mut pending = String::new();
+
+        for node in nodes {
+            // Marks that are already open stay outermost so they aren't closed and reopened
+            let mut marks = delimited_marks(node);
+            marks.sort_by_key(|mark| {
+                active
+                    .iter()
+                    .position(|open| open == mark)
+                    .unwrap_or(usize::MAX)
+            });
+
+            // Delimiters must hug the text, so surrounding whitespace moves outside
+            let (leading, core, trailing) = match &node.text {
+                Some(text) if node.has_mark(&MarkKind::Code) => ("", text.as_str(), ""),
+                Some(text) if node.is_text() => split_whitespace(text),
+                _ => ("", "", ""),
+            };
+            if node.is_text() && core.is_empty() {
+                pending.push_str(leading);
+                continue;
+            }
+
+            let keep = active
+                .iter()
+                .zip(&marks)
+                .take_while(|(a, b)| a == b)
+                .count();
+            while active.len() > keep {
+                let mark = active.pop().expect("mark to close");
+                out.push_str(&close_delimiter(mark));
+            }
+            out.push_str(&pending);
+            pending.clear();
+            out.push_str(leading);
+            for mark in &marks[keep..] {
+                out.push_str(open_delimiter(mark));
+                active.push(mark);
+            }
+
+            match node.kind {
+                NodeKind::Text if node.has_mark(&MarkKind::Code) => out.push_str(&code_span(core)),
+                NodeKind::Text => out.push_str(&escape(core)),
+                NodeKind::HardBreak => out.push_str("\\\n"),
+                NodeKind::Image => {
+                    let alt = node.attr_str("alt").unwrap_or("");
+                    let src = node.attr_str("src").unwrap_or("");
+                    out.push_str(&format!(
+                        "![{}]({})",
+                        escape(alt),
+                        destination(src, node.attr_str("title"))
+                    ));
+                }
+                NodeKind::NoteRef => {
+                    let number =
+                        note_number(self.doc, node.attr_str("noteId"), node.attr_u64("number"));
+                    out.push_str(&format!("[^{}]", number));
+                }
+                _ => out.push_str(&escape(&node.text_content())),
+            }
+            pending.push_str(trailing);
+        }
+
+        while let Some(mark) = active.pop() {
+            out.push_str(&close_delimiter(mark));
+        }
+        out.push_str(&pending);
+        out
+    }
+}
+
+/// Marks rendered with delimiters, in schema order (link, em, strong).
+fn delimited_marks(node: &Node) -> Vec<&Mark> {
+    let rank = |mark: &Mark| match mark.kind {
+        MarkKind::Link => Some(0),
+        MarkKind::Em => Some(1),
+        MarkKind::Strong => Some(2),
+        _ => None,
+    };
+    let mut marks: Vec<&Mark> = node.marks.iter().filter(|m| rank(m).is_some()).collect();
+    marks.sort_by_key(|m| rank(m));
+    marks
+}
+
+fn open_delimiter(mark: &Mark) -> &'static str {
+    match mark.kind {
+        MarkKind::Link => "[",
+        MarkKind::Em => "*",
+        MarkKind::Strong => "**",
+        _ => "",
+    }
+}
+
+fn close_delimiter(mark: &Mark) -> String {
+    match mark.kind {
+        MarkKind::Link => {
+            let href = mark
+                .attrs
+                .get("href")
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let title = mark.attrs.get("title").and_then(|v| v.as_str());
+            format!("]({})", destination(href, title))
+        }
+        MarkKind::Em => "*".to_string(),
+        MarkKind::Strong => "**".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Link/image destination with optional title.
+fn destination(url: &str, title: Option<&str>) -> String {
+    let url = if url.contains([' ', '(', ')']) {
+        format!("<{}>", url)
+    } else {
+        url.to_string()
+    };
+    match title {
+        Some(title) if !title.is_empty() => format!("{} \"{}\"", url, title.replace('"', "\\\"")),
+        _ => url,
+    }
+}
+
+fn code_span(text: &str) -> String {
+    let ticks = "`".repeat(longest_run(text, '`') + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{} {} {}", ticks, text, ticks)
+    } else {
+        format!("{}{}{}", ticks, text, ticks)
+    }
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    for ch in text.chars() {
+        if ch == c {
+            current += 1;
+            longest = longest.max(current);
+        } else {
+            current = 0;
+        }
+    }
+    longest
+}
+
+fn split_whitespace(text: &str) -> (&str, &str, &str) {
+    let trimmed_start = text.trim_start();
+    let leading = &text[..text.len() - trimmed_start.len()];
+    let core = trimmed_start.trim_end();
+    let trailing = &trimmed_start[core.len()..];
+    (leading, core, trailing)
+}
+
+/// Backslash-escape characters with inline meaning.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Escape text that would otherwise start a heading, quote, list or rule.
+fn escape_line_start(text: &str) -> String {
+    let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+    let after_digits = &text[digits..];
+    let needs_escape = text.starts_with(['#', '>', '+', '='])
+        || text.starts_with("- ")
+        || text == "-"
+        || text.starts_with("---")
+        || (digits > 0 && (after_digits.starts_with(". ") || after_digits.starts_with(") ")));
+    if !needs_escape {
+        return text.to_string();
+    }
+    if digits > 0 {
+        format!("{}\\{}", &text[..digits], after_digits)
+    } else {
+        format!("\\{}", text)
+    }
+}
+
+/// Prefix every line but the first with `prefix` (blank lines stay blank).
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            if index == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
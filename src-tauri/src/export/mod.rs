@@ -0,0 +1,55 @@
+//! Export of FlowMark documents to standard formats.
+
+pub mod epub;
+pub mod html;
+pub mod markdown;
+pub mod pdf;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::assets;
+use crate::document::{FlowmarkDocument, Note};
+use crate::error::FlowError;
+
+/// Notes in order of their first reference in the document.
+///
+/// Notes without a reference and references to missing notes are skipped.
+pub fn referenced_notes(doc: &FlowmarkDocument) -> Vec<&Note> {
+    let mut seen = HashSet::new();
+    doc.content
+        .doc
+        .note_refs()
+        .into_iter()
+        .filter(|id| seen.insert(*id))
+        .filter_map(|id| doc.note(id))
+        .collect()
+}
+
+/// Display number of the note `note_id`, falling back to the ref's own number.
+pub fn note_number(doc: &FlowmarkDocument, note_id: Option<&str>, fallback: Option<u64>) -> u64 {
+    note_id
+        .and_then(|id| doc.note(id))
+        .map(|note| note.number as u64)
+        .or(fallback)
+        .unwrap_or(1)
+}
+
+/// Copy the assets referenced by `doc` from the archive into `out_dir`,
+/// keeping their `assets/...` paths so relative references still resolve.
+pub fn extract_assets(
+    archive_path: &Path,
+    doc: &FlowmarkDocument,
+    out_dir: &Path,
+) -> Result<(), FlowError> {
+    for name in assets::referenced(doc) {
+        let bytes = assets::read_asset(archive_path, &name)?;
+        let target = out_dir.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| FlowError::io(parent, e))?;
+        }
+        fs::write(&target, bytes).map_err(|e| FlowError::io(&target, e))?;
+    }
+    Ok(())
+}
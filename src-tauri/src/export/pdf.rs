@@ -0,0 +1,1305 @@
+//! PDF typesetting of FlowMark documents.
+//!
+//! Line breaking, pagination and footnote placement happen here and the
+//! result is drawn with `printpdf`, so export needs neither a browser nor
+//! system fonts. The standard PDF fonts are used unless TrueType files are
+//! configured; images are replaced by their alt text.
+
+use owned_ttf_parser::{AsFaceRef, OwnedFace};
+use printpdf::path::PaintMode;
+use printpdf::{
+    BuiltinFont, Color, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference,
+    PdfPageIndex, Point, Pt, Rect, Rgb,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::document::{FlowmarkDocument, MarkKind, Node, NodeKind, Note};
+use crate::error::FlowError;
+use crate::export::html::document_title;
+use crate::export::{note_number, referenced_notes};
+
+/// Line height as a multiple of the font size.
+const LEADING: f32 = 1.35;
+/// Indentation of list items and blockquotes, in ems.
+const INDENT: f32 = 1.6;
+/// Padding inside table cells, in points.
+const CELL_PADDING: f32 = 4.0;
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSize {
+    #[default]
+    A4,
+    A5,
+    Letter,
+    Legal,
+    /// Width and height in millimetres.
+    Custom {
+        width: f32,
+        height: f32,
+    },
+}
+
+impl PageSize {
+    /// Width and height in millimetres.
+    fn dimensions(self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (210.0, 297.0),
+            PageSize::A5 => (148.0, 210.0),
+            PageSize::Letter => (215.9, 279.4),
+            PageSize::Legal => (215.9, 355.6),
+            PageSize::Custom { width, height } => (width, height),
+        }
+    }
+}
+
+/// Page margins in millimetres.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Margins {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Margins {
+            top: 25.0,
+            right: 25.0,
+            bottom: 25.0,
+            left: 25.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontFamily {
+    /// Times
+    #[default]
+    Serif,
+    /// Helvetica
+    Sans,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PdfFonts {
+    /// Standard PDF family used when no `regular` font file is given.
+    pub family: FontFamily,
+    /// TrueType/OpenType files; missing styles fall back to `regular`.
+    pub regular: Option<PathBuf>,
+    pub bold: Option<PathBuf>,
+    pub italic: Option<PathBuf>,
+    pub bold_italic: Option<PathBuf>,
+    /// Font for code; defaults to Courier.
+    pub monospace: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FootnoteStyle {
+    /// At the bottom of the page holding the reference.
+    #[default]
+    Page,
+    /// In a "Notes" section after the document.
+    Endnotes,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PdfOptions {
+    /// Document title; defaults to the first heading.
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub page_size: PageSize,
+    pub margins: Margins,
+    /// Body text size in points.
+    pub font_size: f32,
+    pub fonts: PdfFonts,
+    pub footnotes: FootnoteStyle,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        PdfOptions {
+            title: None,
+            author: None,
+            page_size: PageSize::default(),
+            margins: Margins::default(),
+            font_size: 11.0,
+            fonts: PdfFonts::default(),
+            footnotes: FootnoteStyle::default(),
+        }
+    }
+}
+
+/// Typeset `doc` as a PDF file.
+pub fn to_pdf(doc: &FlowmarkDocument, options: &PdfOptions) -> Result<Vec<u8>, FlowError> {
+    let (page_width, page_height) = options.page_size.dimensions();
+    let margins = options.margins;
+    let body_width = (page_width - margins.left - margins.right) * POINTS_PER_MM;
+    let body_height = (page_height - margins.top - margins.bottom) * POINTS_PER_MM;
+    if body_width < 36.0 || body_height < 36.0 {
+        return Err(FlowError::invalid(
+            "Margins leave no room for text on the page",
+        ));
+    }
+    let size = options.font_size.clamp(4.0, 72.0);
+
+    let title = options
+        .title
+        .clone()
+        .or_else(|| document_title(doc))
+        .unwrap_or_else(|| "Untitled".to_string());
+    let (pdf, page, layer) = PdfDocument::new(&title, Mm(page_width), Mm(page_height), "Text");
+    let pdf = pdf
+        .with_author(options.author.clone().unwrap_or_default())
+        .with_creator("FlowMarker")
+        .with_producer("FlowMarker");
+
+    let fonts = Fonts::load(&pdf, &options.fonts)?;
+
+    let mut layout = Layout {
+        doc,
+        fonts: &fonts,
+        width: body_width,
+        size,
+        items: Vec::new(),
+    };
+    layout.blocks(&doc.content.doc.content, &Context::new(size));
+
+    let notes = referenced_notes(doc);
+    let mut footnotes = HashMap::new();
+    match options.footnotes {
+        FootnoteStyle::Page => {
+            for note in &notes {
+                footnotes.insert(note.note_id.clone(), layout.note_lines(note));
+            }
+        }
+        FootnoteStyle::Endnotes if !notes.is_empty() => {
+            layout.items.push(Item::Space(size * 1.5));
+            let heading = Style::text(size * 1.3).bold();
+            let mut lines = layout.wrap(&[Span::new("Notes", heading)], body_width, heading.size);
+            for line in &mut lines {
+                line.keep_with_next = true;
+            }
+            layout.push_lines(lines, &Context::new(size));
+            layout.items.push(Item::Space(size * 0.4));
+            for note in &notes {
+                let lines = layout.note_lines(note);
+                layout.push_lines(lines, &Context::new(size));
+                layout.items.push(Item::Space(size * 0.3));
+            }
+        }
+        FootnoteStyle::Endnotes => {}
+    }
+
+    let layer = pdf.get_page(page).get_layer(layer);
+    let mut pager = Pager {
+        pdf: &pdf,
+        fonts: &fonts,
+        page_width: page_width * POINTS_PER_MM,
+        page_height: page_height * POINTS_PER_MM,
+        top: margins.top * POINTS_PER_MM,
+        left: margins.left * POINTS_PER_MM,
+        body_width,
+        body_height,
+        size,
+        pages: vec![layer],
+        page,
+        y: 0.0,
+        empty: true,
+        bookmarked: false,
+        footnotes,
+        placed: HashSet::new(),
+        queue: VecDeque::new(),
+    };
+
+    let items = std::mem::take(&mut layout.items);
+    for (index, item) in items.iter().enumerate() {
+        // Headings stay on the page of the block that follows them
+        let keep = match item {
+            Item::Line(line) if line.keep_with_next => items[index + 1..]
+                .iter()
+                .find(|next| !matches!(next, Item::Space(_)))
+                .map(Item::height)
+                .unwrap_or(0.0),
+            _ => 0.0,
+        };
+        pager.place(item, keep);
+    }
+    pager.finish();
+
+    pdf.save_to_bytes()
+        .map_err(|e| FlowError::other(format!("Failed to write PDF: {}", e)))
+}
+
+/// Write `doc` as a PDF file at `output_path`.
+pub fn write_pdf(
+    doc: &FlowmarkDocument,
+    options: &PdfOptions,
+    output_path: &Path,
+) -> Result<(), FlowError> {
+    let pdf = to_pdf(doc, options)?;
+    atomic::write(output_path, &pdf)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Face {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+}
+
+/// Glyph widths, in thousandths of the font size.
+enum Metrics {
+    /// Widths of the printable ASCII range of a standard font.
+    Standard(&'static [u16; 95]),
+    Fixed(u16),
+    TrueType(Box<OwnedFace>),
+}
+
+impl Metrics {
+    fn advance(&self, c: char) -> f32 {
+        match self {
+            Metrics::Standard(widths) => {
+                let width = |c: char| widths[c as usize - 32] as f32;
+                match c {
+                    ' '..='~' => width(c),
+                    '\u{2018}' | '\u{2019}' | '\u{201A}' => width(','),
+                    '\u{201C}' | '\u{201D}' | '\u{201E}' => width('"'),
+                    '\u{2013}' => width('0'),
+                    '\u{2014}' | '\u{2026}' => width('0') * 2.0,
+                    '\u{2022}' => width('-'),
+                    c if c.is_uppercase() => width('N'),
+                    _ => width('n'),
+                }
+            }
+            Metrics::Fixed(width) => *width as f32,
+            Metrics::TrueType(face) => {
+                let face = face.as_face_ref();
+                let units = face.units_per_em().max(1) as f32;
+                face.glyph_index(c)
+                    .and_then(|glyph| face.glyph_hor_advance(glyph))
+                    .map(|advance| advance as f32 * 1000.0 / units)
+                    .unwrap_or(0.0)
+            }
+        }
+    }
+}
+
+struct Fonts {
+    loaded: Vec<(IndirectFontRef, Metrics)>,
+    /// Index into `loaded` for each `Face`
+    faces: [usize; 5],
+}
+
+impl Fonts {
+    fn load(pdf: &PdfDocumentReference, options: &PdfFonts) -> Result<Self, FlowError> {
+        let mut loaded = Vec::new();
+        let mut standard = |font: BuiltinFont, metrics: Metrics| -> Result<usize, FlowError> {
+            let font = pdf
+                .add_builtin_font(font)
+                .map_err(|e| FlowError::other(format!("Failed to add font: {}", e)))?;
+            loaded.push((font, metrics));
+            Ok(loaded.len() - 1)
+        };
+
+        let mut faces = match options.family {
+            FontFamily::Serif => [
+                standard(BuiltinFont::TimesRoman, Metrics::Standard(&TIMES_ROMAN))?,
+                standard(BuiltinFont::TimesBold, Metrics::Standard(&TIMES_BOLD))?,
+                standard(BuiltinFont::TimesItalic, Metrics::Standard(&TIMES_ITALIC))?,
+                // Bold italic is close enough to bold for line breaking
+                standard(BuiltinFont::TimesBoldItalic, Metrics::Standard(&TIMES_BOLD))?,
+                0,
+            ],
+            FontFamily::Sans => [
+                standard(BuiltinFont::Helvetica, Metrics::Standard(&HELVETICA))?,
+                standard(
+                    BuiltinFont::HelveticaBold,
+                    Metrics::Standard(&HELVETICA_BOLD),
+                )?,
+                standard(BuiltinFont::HelveticaOblique, Metrics::Standard(&HELVETICA))?,
+                standard(
+                    BuiltinFont::HelveticaBoldOblique,
+                    Metrics::Standard(&HELVETICA_BOLD),
+                )?,
+                0,
+            ],
+        };
+        faces[Face::Mono as usize] = standard(BuiltinFont::Courier, Metrics::Fixed(600))?;
+
+        let mut external = |path: &Path| -> Result<usize, FlowError> {
+            let bytes = fs::read(path).map_err(|e| FlowError::io(path, e))?;
+            let font = pdf.add_external_font(bytes.as_slice()).map_err(|e| {
+                FlowError::invalid(format!("Failed to add font {}: {}", path.display(), e))
+            })?;
+            let face = OwnedFace::from_vec(bytes, 0).map_err(|e| {
+                FlowError::invalid(format!("Failed to parse font {}: {}", path.display(), e))
+            })?;
+            loaded.push((font, Metrics::TrueType(Box::new(face))));
+            Ok(loaded.len() - 1)
+        };
+
+        // Standard fonts can't show what a custom font can, so once a regular
+        // file is given every text style uses files
+        if let Some(regular) = &options.regular {
+            let regular = external(regular)?;
+            let styles = [
+                (Face::Regular, None),
+                (Face::Bold, options.bold.as_ref()),
+                (Face::Italic, options.italic.as_ref()),
+                (Face::BoldItalic, options.bold_italic.as_ref()),
+            ];
+            for (face, path) in styles {
+                faces[face as usize] = match path {
+                    Some(path) => external(path)?,
+                    None => regular,
+                };
+            }
+        }
+        if let Some(monospace) = &options.monospace {
+            faces[Face::Mono as usize] = external(monospace)?;
+        }
+
+        Ok(Fonts { loaded, faces })
+    }
+
+    fn get(&self, face: Face) -> &(IndirectFontRef, Metrics) {
+        &self.loaded[self.faces[face as usize]]
+    }
+
+    fn width(&self, text: &str, style: &Style) -> f32 {
+        let (_, metrics) = self.get(style.face());
+        text.chars().map(|c| metrics.advance(c)).sum::<f32>() * style.size / 1000.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    mono: bool,
+    link: bool,
+    size: f32,
+    /// Baseline shift in points, for superscripts
+    rise: f32,
+}
+
+impl Style {
+    fn text(size: f32) -> Self {
+        Style {
+            bold: false,
+            italic: false,
+            mono: false,
+            link: false,
+            size,
+            rise: 0.0,
+        }
+    }
+
+    fn bold(self) -> Self {
+        Style { bold: true, ..self }
+    }
+
+    fn italic(self) -> Self {
+        Style {
+            italic: true,
+            ..self
+        }
+    }
+
+    fn mono(self) -> Self {
+        Style { mono: true, ..self }
+    }
+
+    fn face(&self) -> Face {
+        match (self.mono, self.bold, self.italic) {
+            (true, _, _) => Face::Mono,
+            (false, true, true) => Face::BoldItalic,
+            (false, true, false) => Face::Bold,
+            (false, false, true) => Face::Italic,
+            (false, false, false) => Face::Regular,
+        }
+    }
+}
+
+/// A run of inline text in one style.
+struct Span {
+    text: String,
+    style: Style,
+    /// Note referenced by this span
+    note: Option<String>,
+}
+
+impl Span {
+    fn new(text: impl Into<String>, style: Style) -> Self {
+        Span {
+            text: text.into(),
+            style,
+            note: None,
+        }
+    }
+}
+
+/// Text positioned on a line; `x` is relative to the line's indent.
+#[derive(Clone)]
+struct Fragment {
+    text: String,
+    style: Style,
+    x: f32,
+}
+
+#[derive(Clone)]
+struct TextLine {
+    fragments: Vec<Fragment>,
+    height: f32,
+    /// Distance from the top of the line to the baseline
+    ascent: f32,
+    indent: f32,
+    /// Indents of the enclosing blockquotes, for their rules
+    bars: Vec<f32>,
+    /// Code block background
+    shade: bool,
+    notes: Vec<String>,
+    keep_with_next: bool,
+    bookmark: Option<String>,
+}
+
+struct Cell {
+    x: f32,
+    width: f32,
+    lines: Vec<TextLine>,
+    header: bool,
+}
+
+struct Row {
+    cells: Vec<Cell>,
+    height: f32,
+    indent: f32,
+}
+
+enum Item {
+    Line(TextLine),
+    Row(Row),
+    Rule(f32),
+    Space(f32),
+}
+
+impl Item {
+    fn height(&self) -> f32 {
+        match self {
+            Item::Line(line) => line.height,
+            Item::Row(row) => row.height,
+            Item::Rule(_) => 6.0,
+            Item::Space(height) => *height,
+        }
+    }
+
+    fn notes(&self) -> Vec<&str> {
+        match self {
+            Item::Line(line) => line.notes.iter().map(String::as_str).collect(),
+            Item::Row(row) => row
+                .cells
+                .iter()
+                .flat_map(|cell| &cell.lines)
+                .flat_map(|line| &line.notes)
+                .map(String::as_str)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Word-level pieces of inline content.
+enum Token {
+    Word(Vec<Span>),
+    Space(Style),
+    Break,
+}
+
+#[derive(Clone)]
+struct Context {
+    indent: f32,
+    bars: Vec<f32>,
+    /// Space after paragraphs
+    gap: f32,
+}
+
+impl Context {
+    fn new(size: f32) -> Self {
+        Context {
+            indent: 0.0,
+            bars: Vec::new(),
+            gap: size * 0.6,
+        }
+    }
+}
+
+/// Turns document nodes into a flat list of lines and rows.
+struct Layout<'a> {
+    doc: &'a FlowmarkDocument,
+    fonts: &'a Fonts,
+    /// Width of the text column in points
+    width: f32,
+    size: f32,
+    items: Vec<Item>,
+}
+
+impl Layout<'_> {
+    fn blocks(&mut self, nodes: &[Node], ctx: &Context) {
+        for node in nodes {
+            self.block(node, ctx);
+        }
+    }
+
+    fn block(&mut self, node: &Node, ctx: &Context) {
+        let size = self.size;
+        match node.kind {
+            NodeKind::Paragraph => {
+                let spans = self.inline(&node.content, Style::text(size));
+                let lines = self.wrap(&spans, self.width - ctx.indent, size);
+                self.push_lines(lines, ctx);
+                self.items.push(Item::Space(ctx.gap));
+            }
+            NodeKind::Heading => {
+                let scale = match node.level() {
+                    1 => 1.8,
+                    2 => 1.5,
+                    3 => 1.25,
+                    4 => 1.1,
+                    _ => 1.0,
+                };
+                let style = Style::text(size * scale).bold();
+                let spans = self.inline(&node.content, style);
+                let mut lines = self.wrap(&spans, self.width - ctx.indent, style.size);
+                for line in &mut lines {
+                    line.keep_with_next = true;
+                }
+                let title = node.text_content().trim().to_string();
+                if let Some(first) = lines.first_mut() {
+                    first.bookmark = Some(title).filter(|t| !t.is_empty());
+                }
+                if !self.items.is_empty() {
+                    self.items.push(Item::Space(size * 0.8));
+                }
+                self.push_lines(lines, ctx);
+                self.items.push(Item::Space(size * 0.4));
+            }
+            NodeKind::Blockquote => {
+                let mut inner = ctx.clone();
+                inner.bars.push(ctx.indent);
+                inner.indent += size * INDENT;
+                self.blocks(&node.content, &inner);
+            }
+            NodeKind::HorizontalRule => {
+                self.items.push(Item::Rule(ctx.indent));
+                self.items.push(Item::Space(ctx.gap));
+            }
+            NodeKind::CodeBlock => {
+                let style = Style::text(size * 0.9).mono();
+                let code = node.text_content();
+                let mut lines = Vec::new();
+                for source in code.split('\n') {
+                    lines.extend(self.wrap_code(source, style, self.width - ctx.indent));
+                }
+                for line in &mut lines {
+                    line.shade = true;
+                }
+                self.push_lines(lines, ctx);
+                self.items.push(Item::Space(ctx.gap));
+            }
+            NodeKind::BulletList => self.list(node, ctx, None),
+            NodeKind::OrderedList => {
+                let start = node.attr_u64("order").unwrap_or(1);
+                self.list(node, ctx, Some(start))
+            }
+            NodeKind::ListItem => self.blocks(&node.content, ctx),
+            NodeKind::Table => self.table(node, ctx),
+            NodeKind::Image | NodeKind::NoteRef | NodeKind::Text | NodeKind::HardBreak => {
+                let spans = self.inline(std::slice::from_ref(node), Style::text(size));
+                let lines = self.wrap(&spans, self.width - ctx.indent, size);
+                self.push_lines(lines, ctx);
+                self.items.push(Item::Space(ctx.gap));
+            }
+            _ => {
+                if node.content.iter().all(|child| child.is_text()) {
+                    let spans = self.inline(&node.content, Style::text(size));
+                    let lines = self.wrap(&spans, self.width - ctx.indent, size);
+                    self.push_lines(lines, ctx);
+                    self.items.push(Item::Space(ctx.gap));
+                } else {
+                    self.blocks(&node.content, ctx);
+                }
+            }
+        }
+    }
+
+    fn list(&mut self, node: &Node, ctx: &Context, start: Option<u64>) {
+        let mut inner = ctx.clone();
+        inner.indent += self.size * INDENT;
+        inner.gap = self.size * 0.25;
+
+        for (index, item) in node.content.iter().enumerate() {
+            let marker = match start {
+                Some(start) => format!("{}.", start + index as u64),
+                None => "\u{2022}".to_string(),
+            };
+            let first = self.items.len();
+            self.blocks(&item.content, &inner);
+
+            // The marker hangs in the indent of the item's first line
+            let style = Style::text(self.size);
+            let x = -(self.fonts.width(&marker, &style) + self.size * 0.4);
+            let line = self.items[first..].iter_mut().find_map(|item| match item {
+                Item::Line(line) => Some(line),
+                _ => None,
+            });
+            if let Some(line) = line {
+                line.fragments.insert(
+                    0,
+                    Fragment {
+                        text: marker,
+                        style,
+                        x,
+                    },
+                );
+            }
+        }
+        if let Some(Item::Space(gap)) = self.items.last_mut() {
+            *gap = ctx.gap;
+        }
+    }
+
+    fn table(&mut self, node: &Node, ctx: &Context) {
+        let colspan = |cell: &Node| cell.attr_u64("colspan").unwrap_or(1).max(1) as usize;
+        let columns = node
+            .content
+            .iter()
+            .map(|row| row.content.iter().map(colspan).sum::<usize>())
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let column_width = (self.width - ctx.indent) / columns as f32;
+
+        for row in &node.content {
+            let mut cells = Vec::new();
+            let mut column = 0;
+            for cell in &row.content {
+                if column >= columns {
+                    break;
+                }
+                let span = colspan(cell).min(columns - column);
+                let header = cell.kind == NodeKind::TableHeader;
+                let mut style = Style::text(self.size * 0.95);
+                if header {
+                    style = style.bold();
+                }
+                let width = column_width * span as f32;
+                let mut lines = Vec::new();
+                for block in &cell.content {
+                    let spans = self.inline(&block.content, style);
+                    lines.extend(self.wrap(&spans, width - 2.0 * CELL_PADDING, style.size));
+                }
+                cells.push(Cell {
+                    x: column_width * column as f32,
+                    width,
+                    lines,
+                    header,
+                });
+                column += span;
+            }
+            let height = cells
+                .iter()
+                .map(|cell| cell.lines.iter().map(|line| line.height).sum::<f32>())
+                .fold(self.size * LEADING, f32::max)
+                + 2.0 * CELL_PADDING;
+            self.items.push(Item::Row(Row {
+                cells,
+                height,
+                indent: ctx.indent,
+            }));
+        }
+        self.items.push(Item::Space(ctx.gap));
+    }
+
+    fn inline(&self, nodes: &[Node], base: Style) -> Vec<Span> {
+        let mut spans = Vec::new();
+        for node in nodes {
+            match node.kind {
+                NodeKind::Text => {
+                    let mut style = base;
+                    style.bold |= node.has_mark(&MarkKind::Strong);
+                    style.italic |= node.has_mark(&MarkKind::Em);
+                    style.link |= node.has_mark(&MarkKind::Link);
+                    if node.has_mark(&MarkKind::Code) {
+                        style = style.mono();
+                        style.size *= 0.9;
+                    }
+                    spans.push(Span::new(node.text.clone().unwrap_or_default(), style));
+                }
+                NodeKind::HardBreak => spans.push(Span::new("\n", base)),
+                NodeKind::Image => {
+                    let alt = node
+                        .attr_str("alt")
+                        .filter(|alt| !alt.is_empty())
+                        .unwrap_or("image");
+                    spans.push(Span::new(format!("[{}]", alt), base.italic()));
+                }
+                NodeKind::NoteRef => {
+                    let number =
+                        note_number(self.doc, node.attr_str("noteId"), node.attr_u64("number"));
+                    let style = Style {
+                        size: base.size * 0.65,
+                        rise: base.size * 0.35,
+                        ..base
+                    };
+                    spans.push(Span {
+                        text: number.to_string(),
+                        style,
+                        note: node.attr_str("noteId").map(str::to_string),
+                    });
+                }
+                _ => spans.extend(self.inline(&node.content, base)),
+            }
+        }
+        spans
+    }
+
+    /// Lines of a note, with its number hanging in the indent.
+    fn note_lines(&self, note: &Note) -> Vec<TextLine> {
+        let style = Style::text(self.size * 0.85);
+        let indent = style.size * 1.8;
+        let mut spans = Vec::new();
+        for paragraph in note
+            .content
+            .split("\n\n")
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+        {
+            if !spans.is_empty() {
+                spans.push(Span::new("\n", style));
+            }
+            spans.push(Span::new(paragraph, style));
+        }
+
+        let mut lines = self.wrap(&spans, self.width - indent, style.size);
+        let marker = format!("{}.", note.number);
+        let x = -(self.fonts.width(&marker, &style) + style.size * 0.4);
+        for line in &mut lines {
+            line.indent = indent;
+        }
+        if let Some(first) = lines.first_mut() {
+            first.fragments.insert(
+                0,
+                Fragment {
+                    text: marker,
+                    style,
+                    x,
+                },
+            );
+        }
+        lines
+    }
+
+    fn push_lines(&mut self, lines: Vec<TextLine>, ctx: &Context) {
+        for mut line in lines {
+            line.indent += ctx.indent;
+            line.bars = ctx.bars.clone();
+            self.items.push(Item::Line(line));
+        }
+    }
+
+    /// Break `spans` into lines at most `width` points wide.
+    fn wrap(&self, spans: &[Span], width: f32, size: f32) -> Vec<TextLine> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut word: Vec<Span> = Vec::new();
+        let flush = |word: &mut Vec<Span>, tokens: &mut Vec<Token>| {
+            if !word.is_empty() {
+                tokens.push(Token::Word(std::mem::take(word)));
+            }
+        };
+        for span in spans {
+            if span.note.is_some() {
+                word.push(Span {
+                    text: span.text.clone(),
+                    style: span.style,
+                    note: span.note.clone(),
+                });
+                continue;
+            }
+            for c in span.text.chars() {
+                if c == '\n' {
+                    flush(&mut word, &mut tokens);
+                    tokens.push(Token::Break);
+                } else if c.is_whitespace() {
+                    flush(&mut word, &mut tokens);
+                    if !matches!(tokens.last(), Some(Token::Space(_))) {
+                        tokens.push(Token::Space(span.style));
+                    }
+                } else {
+                    match word.last_mut() {
+                        Some(last) if last.style == span.style && last.note.is_none() => {
+                            last.text.push(c)
+                        }
+                        _ => word.push(Span::new(c.to_string(), span.style)),
+                    }
+                }
+            }
+        }
+        flush(&mut word, &mut tokens);
+
+        let mut builder = LineBuilder::new(self.fonts, size);
+        let mut space: Option<Style> = None;
+        for token in tokens {
+            match token {
+                Token::Space(style) => {
+                    if !builder.fragments.is_empty() {
+                        space = Some(style);
+                    }
+                }
+                Token::Break => {
+                    builder.finish();
+                    space = None;
+                }
+                Token::Word(pieces) => {
+                    let word_width: f32 = pieces
+                        .iter()
+                        .map(|p| self.fonts.width(&p.text, &p.style))
+                        .sum();
+                    let space_width = space.map(|s| self.fonts.width(" ", &s)).unwrap_or(0.0);
+                    if !builder.fragments.is_empty() && builder.x + space_width + word_width > width
+                    {
+                        builder.finish();
+                        space = None;
+                    }
+                    if let Some(style) = space.take() {
+                        builder.push(" ", style, None);
+                    }
+                    if word_width <= width {
+                        for piece in pieces {
+                            builder.push(&piece.text, piece.style, piece.note);
+                        }
+                    } else {
+                        // Break words that can't fit on any line between characters
+                        for piece in pieces {
+                            for c in piece.text.chars() {
+                                let c = c.to_string();
+                                let char_width = self.fonts.width(&c, &piece.style);
+                                if !builder.fragments.is_empty() && builder.x + char_width > width {
+                                    builder.finish();
+                                }
+                                builder.push(&c, piece.style, piece.note.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        builder.finish();
+        builder.lines
+    }
+
+    /// Break a line of code between characters, keeping its spaces.
+    fn wrap_code(&self, source: &str, style: Style, width: f32) -> Vec<TextLine> {
+        let mut builder = LineBuilder::new(self.fonts, style.size);
+        for c in source.replace('\t', "    ").chars() {
+            let c = c.to_string();
+            if !builder.fragments.is_empty() && builder.x + self.fonts.width(&c, &style) > width {
+                builder.finish();
+            }
+            builder.push(&c, style, None);
+        }
+        builder.finish();
+        builder.lines
+    }
+}
+
+struct LineBuilder<'a> {
+    fonts: &'a Fonts,
+    /// Size used for empty lines
+    size: f32,
+    fragments: Vec<Fragment>,
+    notes: Vec<String>,
+    x: f32,
+    lines: Vec<TextLine>,
+}
+
+impl<'a> LineBuilder<'a> {
+    fn new(fonts: &'a Fonts, size: f32) -> Self {
+        LineBuilder {
+            fonts,
+            size,
+            fragments: Vec::new(),
+            notes: Vec::new(),
+            x: 0.0,
+            lines: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, text: &str, style: Style, note: Option<String>) {
+        let width = self.fonts.width(text, &style);
+        match self.fragments.last_mut() {
+            Some(last) if last.style == style && note.is_none() => last.text.push_str(text),
+            _ => self.fragments.push(Fragment {
+                text: text.to_string(),
+                style,
+                x: self.x,
+            }),
+        }
+        if let Some(note) = note {
+            self.notes.push(note);
+        }
+        self.x += width;
+    }
+
+    fn finish(&mut self) {
+        // Superscripts don't make the line taller
+        let size = self
+            .fragments
+            .iter()
+            .filter(|fragment| fragment.style.rise == 0.0)
+            .map(|fragment| fragment.style.size)
+            .fold(0.0, f32::max);
+        let size = if size > 0.0 { size } else { self.size };
+        let height = size * LEADING;
+        self.lines.push(TextLine {
+            fragments: std::mem::take(&mut self.fragments),
+            height,
+            ascent: (height - size) / 2.0 + size * 0.8,
+            indent: 0.0,
+            bars: Vec::new(),
+            shade: false,
+            notes: std::mem::take(&mut self.notes),
+            keep_with_next: false,
+            bookmark: None,
+        });
+        self.x = 0.0;
+    }
+}
+
+/// Places items on pages, top to bottom.
+///
+/// Positions are in points from the top-left corner of the text area; they
+/// are converted to PDF's bottom-left origin when drawing.
+struct Pager<'a> {
+    pdf: &'a PdfDocumentReference,
+    fonts: &'a Fonts,
+    page_width: f32,
+    page_height: f32,
+    top: f32,
+    left: f32,
+    body_width: f32,
+    body_height: f32,
+    size: f32,
+    pages: Vec<PdfLayerReference>,
+    page: PdfPageIndex,
+    y: f32,
+    /// Nothing has been placed on the current page yet
+    empty: bool,
+    bookmarked: bool,
+    /// Laid out footnotes by note id; empty for endnotes
+    footnotes: HashMap<String, Vec<TextLine>>,
+    placed: HashSet<String>,
+    /// Footnote lines waiting for the bottom of a page
+    queue: VecDeque<TextLine>,
+}
+
+impl Pager<'_> {
+    fn place(&mut self, item: &Item, keep: f32) {
+        if let Item::Space(height) = item {
+            if !self.empty {
+                self.y += height;
+            }
+            return;
+        }
+
+        let mut seen = HashSet::new();
+        let new_notes: f32 = item
+            .notes()
+            .into_iter()
+            .filter(|id| !self.placed.contains(*id) && seen.insert(*id))
+            .filter_map(|id| self.footnotes.get(id))
+            .flatten()
+            .map(|line| line.height)
+            .sum();
+
+        if !self.empty && !self.fits(item.height() + keep, new_notes) {
+            self.flush_footnotes();
+            self.add_page();
+        }
+
+        match item {
+            Item::Line(line) => {
+                if let Some(title) = &line.bookmark {
+                    if !self.bookmarked {
+                        self.pdf.add_bookmark(title.as_str(), self.page);
+                        self.bookmarked = true;
+                    }
+                }
+                self.draw_line(line, self.left + line.indent, self.y);
+            }
+            Item::Row(row) => self.draw_row(row),
+            Item::Rule(indent) => {
+                let y = self.y + 3.0;
+                self.stroke(
+                    &[(self.left + indent, y), (self.left + self.body_width, y)],
+                    0.5,
+                    0.6,
+                );
+            }
+            Item::Space(_) => {}
+        }
+        self.y += item.height();
+        self.empty = false;
+
+        for id in item.notes() {
+            if self.placed.insert(id.to_string()) {
+                if let Some(lines) = self.footnotes.get(id) {
+                    self.queue.extend(lines.iter().cloned());
+                }
+            }
+        }
+    }
+
+    /// Whether `height` more content fits above the footnotes.
+    fn fits(&self, height: f32, new_notes: f32) -> bool {
+        let notes: f32 = self.queue.iter().map(|line| line.height).sum::<f32>() + new_notes;
+        let reserve = if notes > 0.0 {
+            // Long notes continue on the next page instead of crowding out the text
+            (notes + self.size).min(self.body_height / 2.0)
+        } else {
+            0.0
+        };
+        self.y + height + reserve <= self.body_height
+    }
+
+    fn add_page(&mut self) {
+        let (page, layer) = self.pdf.add_page(
+            Mm::from(Pt(self.page_width)),
+            Mm::from(Pt(self.page_height)),
+            "Text",
+        );
+        self.pages.push(self.pdf.get_page(page).get_layer(layer));
+        self.page = page;
+        self.y = 0.0;
+        self.empty = true;
+        self.bookmarked = false;
+    }
+
+    /// Draw as many queued footnotes as fit below the text of this page.
+    fn flush_footnotes(&mut self) {
+        let available = self.body_height - self.y - self.size;
+        let mut used = 0.0;
+        let mut count = 0;
+        for line in &self.queue {
+            if used + line.height > available {
+                break;
+            }
+            used += line.height;
+            count += 1;
+        }
+        if count == 0 {
+            // A line taller than a whole page still goes on a fresh page, so
+            // the queue always drains
+            match self.queue.front() {
+                Some(line) if self.empty => {
+                    used = line.height;
+                    count = 1;
+                }
+                _ => return,
+            }
+        }
+
+        let rule = self.body_height - used - self.size / 2.0;
+        self.stroke(
+            &[(self.left, rule), (self.left + self.body_width / 3.0, rule)],
+            0.5,
+            0.0,
+        );
+        let mut y = self.body_height - used;
+        for line in self.queue.drain(..count).collect::<Vec<_>>() {
+            self.draw_line(&line, self.left + line.indent, y);
+            y += line.height;
+        }
+    }
+
+    fn finish(&mut self) {
+        self.flush_footnotes();
+        while !self.queue.is_empty() {
+            self.add_page();
+            self.flush_footnotes();
+        }
+
+        let style = Style::text(self.size * 0.8);
+        let (font, _) = self.fonts.get(Face::Regular);
+        for (index, layer) in self.pages.iter().enumerate() {
+            let number = (index + 1).to_string();
+            let x = (self.page_width - self.fonts.width(&number, &style)) / 2.0;
+            let y = (self.page_height - self.top - self.body_height) / 2.0;
+            layer.use_text(number, style.size, Mm::from(Pt(x)), Mm::from(Pt(y)), font);
+        }
+    }
+
+    fn layer(&self) -> &PdfLayerReference {
+        self.pages.last().expect("at least one page")
+    }
+
+    fn draw_line(&self, line: &TextLine, x: f32, y: f32) {
+        if line.shade {
+            let right = self.left + self.body_width;
+            self.fill(x - 3.0, y, right, y + line.height, 0.95);
+        }
+        for bar in &line.bars {
+            let bar_x = self.left + bar + 2.0;
+            self.stroke(&[(bar_x, y), (bar_x, y + line.height)], 1.5, 0.75);
+        }
+
+        let layer = self.layer();
+        for fragment in &line.fragments {
+            let (font, _) = self.fonts.get(fragment.style.face());
+            if fragment.style.link {
+                layer.set_fill_color(rgb(0.23, 0.27, 0.77));
+            }
+            let baseline = self.page_height - self.top - y - line.ascent + fragment.style.rise;
+            layer.use_text(
+                fragment.text.as_str(),
+                fragment.style.size,
+                Mm::from(Pt(x + fragment.x)),
+                Mm::from(Pt(baseline)),
+                font,
+            );
+            if fragment.style.link {
+                layer.set_fill_color(rgb(0.0, 0.0, 0.0));
+            }
+        }
+    }
+
+    fn draw_row(&self, row: &Row) {
+        for cell in &row.cells {
+            let x = self.left + row.indent + cell.x;
+            if cell.header {
+                self.fill(x, self.y, x + cell.width, self.y + row.height, 0.93);
+            }
+            let (top, bottom) = (self.y, self.y + row.height);
+            let corners = [
+                (x, top),
+                (x + cell.width, top),
+                (x + cell.width, bottom),
+                (x, bottom),
+                (x, top),
+            ];
+            self.stroke(&corners, 0.5, 0.6);
+
+            let mut y = self.y + CELL_PADDING;
+            for line in &cell.lines {
+                self.draw_line(line, x + CELL_PADDING + line.indent, y);
+                y += line.height;
+            }
+        }
+    }
+
+    /// Fill a rectangle given in text-area coordinates with a grey level.
+    fn fill(&self, x1: f32, y1: f32, x2: f32, y2: f32, grey: f32) {
+        let layer = self.layer();
+        let bottom = self.page_height - self.top - y2;
+        let top = self.page_height - self.top - y1;
+        layer.set_fill_color(rgb(grey, grey, grey));
+        layer.add_rect(
+            Rect::new(
+                Mm::from(Pt(x1)),
+                Mm::from(Pt(bottom)),
+                Mm::from(Pt(x2)),
+                Mm::from(Pt(top)),
+            )
+            .with_mode(PaintMode::Fill),
+        );
+        layer.set_fill_color(rgb(0.0, 0.0, 0.0));
+    }
+
+    /// Stroke a polyline given in text-area coordinates.
+    fn stroke(&self, points: &[(f32, f32)], thickness: f32, grey: f32) {
+        let layer = self.layer();
+        layer.set_outline_color(rgb(grey, grey, grey));
+        layer.set_outline_thickness(thickness);
+        layer.add_line(printpdf::Line {
+            points: points
+                .iter()
+                .map(|(x, y)| {
+                    (
+                        Point::new(
+                            Mm::from(Pt(*x)),
+                            Mm::from(Pt(self.page_height - self.top - y)),
+                        ),
+                        false,
+                    )
+                })
+                .collect(),
+            is_closed: false,
+        });
+    }
+}
+
+fn rgb(r: f32, g: f32, b: f32) -> Color {
+    Color::Rgb(Rgb::new(r, g, b, None))
+}
+
+// Advance widths of the standard fonts for ' '..='~', from their AFM files.
+
+const HELVETICA: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, //
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, //
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, //
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, //
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, //
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+const HELVETICA_BOLD: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278, //
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611, //
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778, //
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556, //
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611, //
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+const TIMES_ROMAN: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278, //
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444, //
+    921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722, //
+    556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500, //
+    333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500, //
+    500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+];
+
+const TIMES_BOLD: [u16; 95] = [
+    250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278, //
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500, //
+    930, 722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778, //
+    611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667, 333, 278, 333, 581, 500, //
+    333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500, //
+    556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520,
+];
+
+const TIMES_ITALIC: [u16; 95] = [
+    250, 333, 420, 500, 500, 833, 778, 214, 333, 333, 500, 675, 250, 333, 250, 278, //
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 675, 675, 675, 500, //
+    920, 611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833, 667, 722, //
+    611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556, 389, 278, 389, 422, 500, //
+    333, 500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722, 500, 500, //
+    500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389, 400, 275, 400, 541,
+];
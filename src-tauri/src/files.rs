@@ -0,0 +1,30 @@
+//! How files and their content are identified.
+//!
+//! Modules that keep state per file (the keys of unlocked files, the open
+//! files being watched) must agree on the path they key it by, and content
+//! is identified by the same hash wherever it is compared or stored.
+
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// `path` with a canonical parent, so a file is found under the same path
+/// however it was opened, even before it exists. Matches the paths the
+/// file watcher reports.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match (parent.canonicalize(), path.file_name()) {
+        (Ok(parent), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// SHA-256 of `bytes` as lowercase hex.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
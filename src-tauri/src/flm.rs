@@ -0,0 +1,283 @@
+//! Reading and writing `.flm` archives.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use zip::read::ZipArchive;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::assets;
+use crate::atomic;
+use crate::crypto;
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::history;
+use crate::limits;
+use crate::manifest::{Manifest, MANIFEST_ENTRY};
+
+pub const DOCUMENT_ENTRY: &str = "document.json";
+
+/// An opened .flm archive.
+pub type Archive = ZipArchive<ArchiveReader>;
+
+/// The bytes of an archive: the file itself, or the decrypted content of
+/// an encrypted file (see [`crypto`]).
+pub enum ArchiveReader {
+    File(File),
+    Decrypted(Cursor<Vec<u8>>),
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ArchiveReader::File(file) => file.read(buf),
+            ArchiveReader::Decrypted(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for ArchiveReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ArchiveReader::File(file) => file.seek(pos),
+            ArchiveReader::Decrypted(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// Write `doc` as a .flm archive at `path`.
+///
+/// The archive is built in a temporary file and atomically renamed over
+/// `path`, so an existing file is never left half-written. The document it
+/// replaces is added to the history, and assets of the previous archive are
+/// carried over if the document or a kept revision still references them.
+pub fn write_document(path: impl AsRef<Path>, doc: &FlowmarkDocument) -> Result<(), FlowError> {
+    write_document_with(path.as_ref(), doc, Vec::new())
+}
+
+/// Like [`write_document`], also adding `entries` (e.g. assets taken from
+/// another archive).
+pub fn write_document_with(
+    path: &Path,
+    doc: &FlowmarkDocument,
+    mut entries: Vec<(String, Vec<u8>)>,
+) -> Result<(), FlowError> {
+    let history = history::prepare(path, doc)?;
+    let mut keep = assets::referenced(doc);
+    keep.extend(history.keep);
+    entries.extend(history.entries);
+    write_archive(path, doc, |name| keep.contains(name), entries)
+}
+
+/// Rewrite the archive at `path` with `doc`, new `entries`, and every entry
+/// of the existing archive for which `carry` returns true.
+pub fn write_archive<F>(
+    path: &Path,
+    doc: &FlowmarkDocument,
+    carry: F,
+    entries: Vec<(String, Vec<u8>)>,
+) -> Result<(), FlowError>
+where
+    F: Fn(&str) -> bool,
+{
+    let document_json = doc.to_json()?;
+    let manifest_json = Manifest::for_document(doc).to_json()?;
+
+    // The previous archive stays intact until the rename, so entries can be
+    // copied straight from it
+    let mut previous = open_previous(path)?;
+    let mut contents = ArchiveContents {
+        path,
+        document_json: &document_json,
+        manifest_json: &manifest_json,
+        entries: &entries,
+        previous: previous.as_mut(),
+        carry: &carry,
+    };
+
+    if crypto::is_unlocked(path) || crypto::is_encrypted(path) {
+        // Encrypted archives are built in memory and sealed as a whole
+        let archive = contents
+            .write_to(ZipWriter::new(Cursor::new(Vec::new())))?
+            .into_inner();
+        let sealed = crypto::prepare_write(path, archive)?;
+        return atomic::write(path, &sealed);
+    }
+    atomic::write_with(path, |file| contents.write_to(ZipWriter::new(file)))
+}
+
+/// Everything that goes into a new archive.
+struct ArchiveContents<'a, F> {
+    path: &'a Path,
+    document_json: &'a str,
+    manifest_json: &'a str,
+    entries: &'a [(String, Vec<u8>)],
+    previous: Option<&'a mut Archive>,
+    carry: &'a F,
+}
+
+impl<F: Fn(&str) -> bool> ArchiveContents<'_, F> {
+    fn write_to<W: Write + Seek>(&mut self, mut zip: ZipWriter<W>) -> Result<W, FlowError> {
+        let path = self.path;
+        // Add document.json to the ZIP
+        let options = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(0o644);
+
+        zip.start_file(DOCUMENT_ENTRY, options)
+            .map_err(|e| FlowError::zip(path, e))?;
+
+        zip.write_all(self.document_json.as_bytes())
+            .map_err(|e| FlowError::io(path, e))?;
+
+        zip.start_file(MANIFEST_ENTRY, options)
+            .map_err(|e| FlowError::zip(path, e))?;
+
+        zip.write_all(self.manifest_json.as_bytes())
+            .map_err(|e| FlowError::io(path, e))?;
+
+        let mut written: HashSet<String> = HashSet::new();
+        written.insert(DOCUMENT_ENTRY.to_string());
+        written.insert(MANIFEST_ENTRY.to_string());
+
+        for (name, data) in self.entries {
+            if !written.insert(name.clone()) {
+                continue;
+            }
+            zip.start_file(name.as_str(), options)
+                .map_err(|e| FlowError::zip(path, e))?;
+            zip.write_all(data).map_err(|e| FlowError::io(path, e))?;
+        }
+
+        if let Some(archive) = self.previous.as_mut() {
+            for index in 0..archive.len() {
+                let entry = archive
+                    .by_index_raw(index)
+                    .map_err(|e| FlowError::zip(path, e))?;
+                let name = entry.name().to_string();
+                if written.contains(&name) || !(self.carry)(&name) {
+                    continue;
+                }
+                zip.raw_copy_file(entry)
+                    .map_err(|e| FlowError::zip(path, e))?;
+                written.insert(name);
+            }
+        }
+
+        zip.finish().map_err(|e| FlowError::zip(path, e))
+    }
+}
+
+/// Open the .flm archive at `path`, decrypting it if it is encrypted.
+///
+/// Encrypted files must have been unlocked first, or this fails with
+/// [`FlowError::PasswordRequired`]. Archives exceeding the
+/// [`limits`](crate::limits) are rejected before any entry is read.
+pub fn open_archive(path: impl AsRef<Path>) -> Result<Archive, FlowError> {
+    let path = path.as_ref();
+    let mut reader = open_reader(path)?;
+    limits::check_directory(path, &mut reader)?;
+    let mut archive = ZipArchive::new(reader).map_err(|e| FlowError::zip(path, e))?;
+    limits::check_archive(path, &mut archive)?;
+    Ok(archive)
+}
+
+fn open_reader(path: &Path) -> Result<ArchiveReader, FlowError> {
+    if crypto::is_encrypted(path) {
+        Ok(ArchiveReader::Decrypted(Cursor::new(crypto::read(path)?)))
+    } else {
+        File::open(path)
+            .map(ArchiveReader::File)
+            .map_err(|e| FlowError::io(path, e))
+    }
+}
+
+/// Open the archive a save is about to replace, or `None` if there is none.
+///
+/// Only a missing file or one that isn't a ZIP archive at all counts as no
+/// archive. Anything else fails the save, instead of writing a file without
+/// the assets and history it should have carried over.
+///
+/// The archive limits aren't applied: entries are copied without being
+/// decompressed, and the ones that are read are still capped in size, so a
+/// file over a limit keeps everything it has.
+pub(crate) fn open_previous(path: &Path) -> Result<Option<Archive>, FlowError> {
+    let opened = open_reader(path)
+        .and_then(|reader| ZipArchive::new(reader).map_err(|e| FlowError::zip(path, e)));
+    match opened {
+        Ok(archive) => Ok(Some(archive)),
+        Err(FlowError::NotFound { .. }) => Ok(None),
+        Err(e @ FlowError::CorruptArchive { .. }) => {
+            if crypto::is_encrypted(path) || has_zip_signature(path)? {
+                Err(e)
+            } else {
+                Ok(None)
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn has_zip_signature(path: &Path) -> Result<bool, FlowError> {
+    let mut signature = [0u8; 2];
+    let mut file = File::open(path).map_err(|e| FlowError::io(path, e))?;
+    match file.read_exact(&mut signature) {
+        Ok(()) => Ok(&signature == b"PK"),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(FlowError::io(path, e)),
+    }
+}
+
+/// Read and validate the document stored in the .flm archive at `path`.
+pub fn read_document(path: impl AsRef<Path>) -> Result<FlowmarkDocument, FlowError> {
+    let path = path.as_ref();
+    let mut archive = open_archive(path)?;
+
+    // Archives from before manifest.json rely on the document's own version
+    if let Some(manifest_json) = read_entry_string(path, &mut archive, MANIFEST_ENTRY)? {
+        Manifest::from_json(&manifest_json)?.check()?;
+    }
+
+    // Find and read document.json
+    let document_json =
+        read_entry_string(path, &mut archive, DOCUMENT_ENTRY)?.ok_or_else(|| {
+            FlowError::MissingDocument {
+                path: path.to_path_buf(),
+            }
+        })?;
+
+    FlowmarkDocument::from_json(&document_json)
+}
+
+/// Read the manifest of the .flm archive at `path`, if it has one.
+pub fn read_manifest(path: impl AsRef<Path>) -> Result<Option<Manifest>, FlowError> {
+    let path = path.as_ref();
+    let mut archive = open_archive(path)?;
+    read_entry_string(path, &mut archive, MANIFEST_ENTRY)?
+        .map(|json| Manifest::from_json(&json))
+        .transpose()
+}
+
+/// Read a text entry, or `None` if the archive doesn't contain it.
+pub(crate) fn read_entry_string(
+    path: &Path,
+    archive: &mut Archive,
+    name: &str,
+) -> Result<Option<String>, FlowError> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(FlowError::zip(path, e)),
+    };
+    let bytes = limits::read_entry(path, name, file)?;
+    let contents = String::from_utf8(bytes).map_err(|e| FlowError::CorruptArchive {
+        path: path.to_path_buf(),
+        reason: format!("{}: {}", name, e),
+    })?;
+    if name.ends_with(".json") {
+        limits::check_nesting(path, name, &contents)?;
+    }
+    Ok(Some(contents))
+}
@@ -0,0 +1,333 @@
+//! Writing goals and session tracking per document.
+//!
+//! The frontend reports the document as it is being edited (see
+//! [`Goals::record`]). Words added and removed since the previous report
+//! are added to the current writing session, which ends after
+//! [`SESSION_GAP_MINUTES`] without changes. Sessions and goals are kept in a
+//! single JSON file in the app data directory, keyed by document path, and
+//! summed up into daily and weekly progress in local time.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::atomic;
+use crate::diff;
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::flm;
+use crate::stats;
+
+/// Bumped whenever the stored layout changes.
+const STORE_VERSION: u32 = 1;
+/// A pause longer than this ends a session.
+pub const SESSION_GAP_MINUTES: i64 = 30;
+/// Days of daily progress returned, including today.
+const HISTORY_DAYS: i64 = 30;
+/// Most recent sessions returned.
+const RECENT_SESSIONS: usize = 20;
+
+/// Targets set for a document. All of them are optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Goal {
+    /// Length of the finished manuscript in words.
+    pub target_words: Option<usize>,
+    pub deadline: Option<NaiveDate>,
+    /// Net words to write per day.
+    pub daily_words: Option<usize>,
+    /// Net words to write per week.
+    pub weekly_words: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub start_words: usize,
+    pub end_words: usize,
+    pub words_added: usize,
+    pub words_removed: usize,
+}
+
+/// Writing done in a day or week.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Period {
+    pub words_added: usize,
+    pub words_removed: usize,
+    pub net_words: i64,
+    /// Time spent in sessions, in minutes.
+    pub minutes: i64,
+    pub sessions: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Day {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub period: Period,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WritingProgress {
+    pub path: PathBuf,
+    /// Current length of the document, `None` if it was never recorded and
+    /// can't be read.
+    pub words: Option<usize>,
+    pub goal: Goal,
+    pub today: Period,
+    /// The week so far, starting on Monday.
+    pub this_week: Period,
+    /// The last 30 days, oldest first.
+    pub days: Vec<Day>,
+    /// Most recent sessions, newest first.
+    pub sessions: Vec<Session>,
+    /// Words still missing to reach the target.
+    pub remaining_words: Option<usize>,
+    /// Days left until the deadline, including today; 0 once it has passed.
+    pub days_left: Option<i64>,
+    /// Words per day needed to reach the target by the deadline.
+    pub words_per_day_needed: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    version: u32,
+    documents: BTreeMap<PathBuf, Record>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    #[serde(default)]
+    goal: Goal,
+    #[serde(default)]
+    sessions: Vec<Session>,
+    /// Length when last recorded.
+    words: Option<usize>,
+}
+
+struct Inner {
+    store: Store,
+    /// Document as last reported, per path, to compute what changed.
+    last: HashMap<PathBuf, FlowmarkDocument>,
+}
+
+/// Goal and session store shared by all commands.
+pub struct Goals {
+    file: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl Goals {
+    /// Load the store kept in `file`, starting empty if it is missing or
+    /// unreadable.
+    pub fn open(file: impl Into<PathBuf>) -> Self {
+        let file = file.into();
+        let store = match fs::read(&file) {
+            Ok(bytes) => match serde_json::from_slice::<Store>(&bytes) {
+                Ok(store) if store.version == STORE_VERSION => store,
+                Ok(_) => Store::default(),
+                Err(e) => {
+                    log::warn!("Discarding unreadable goals {}: {}", file.display(), e);
+                    Store::default()
+                }
+            },
+            Err(_) => Store::default(),
+        };
+        Goals {
+            file,
+            inner: Mutex::new(Inner {
+                store,
+                last: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Record `doc` as the current state of the document at `path`. Without
+    /// a baseline from [`Goals::start`] the first report only sets one.
+    pub fn record(
+        &self,
+        path: &Path,
+        doc: &FlowmarkDocument,
+    ) -> Result<WritingProgress, FlowError> {
+        let path = normalize(path);
+        let now = Utc::now();
+        let words = stats::document_stats(doc).counts.words;
+        let mut inner = self.lock()?;
+        let previous = inner.last.insert(path.clone(), doc.clone());
+        let record = inner.store.documents.entry(path.clone()).or_default();
+        let mut changed = record.words.replace(words) != Some(words);
+
+        if let Some(previous) = previous {
+            let summary = diff::diff_summary(&previous, doc);
+            if summary.words_added + summary.words_removed > 0 {
+                changed = true;
+                match record.sessions.last_mut() {
+                    Some(session)
+                        if now - session.ended_at <= Duration::minutes(SESSION_GAP_MINUTES) =>
+                    {
+                        session.ended_at = now;
+                        session.end_words = words;
+                        session.words_added += summary.words_added;
+                        session.words_removed += summary.words_removed;
+                    }
+                    _ => record.sessions.push(Session {
+                        started_at: now,
+                        ended_at: now,
+                        start_words: (words + summary.words_removed)
+                            .saturating_sub(summary.words_added),
+                        end_words: words,
+                        words_added: summary.words_added,
+                        words_removed: summary.words_removed,
+                    }),
+                }
+            }
+        }
+        if changed {
+            self.save(&mut inner.store)?;
+        }
+        Ok(progress(&path, &inner.store, Local::now().date_naive()))
+    }
+
+    /// Make `doc` the baseline for the document at `path`, e.g. when it has
+    /// just been opened, without counting any difference as writing.
+    pub fn start(&self, path: &Path, doc: &FlowmarkDocument) -> Result<(), FlowError> {
+        let path = normalize(path);
+        let words = stats::document_stats(doc).counts.words;
+        let mut inner = self.lock()?;
+        inner.last.insert(path.clone(), doc.clone());
+        let record = inner.store.documents.entry(path).or_default();
+        if record.words.replace(words) != Some(words) {
+            self.save(&mut inner.store)?;
+        }
+        Ok(())
+    }
+
+    /// Forget the baseline of `path`, e.g. when its document was closed.
+    /// The current session ends on its own.
+    pub fn close(&self, path: &Path) -> Result<(), FlowError> {
+        self.lock()?.last.remove(&normalize(path));
+        Ok(())
+    }
+
+    pub fn set_goal(&self, path: &Path, goal: Goal) -> Result<WritingProgress, FlowError> {
+        let path = normalize(path);
+        let mut inner = self.lock()?;
+        inner.store.documents.entry(path.clone()).or_default().goal = goal;
+        self.save(&mut inner.store)?;
+        Ok(progress(&path, &inner.store, Local::now().date_naive()))
+    }
+
+    pub fn progress(&self, path: &Path) -> Result<WritingProgress, FlowError> {
+        let path = normalize(path);
+        let inner = self.lock()?;
+        Ok(progress(&path, &inner.store, Local::now().date_naive()))
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Inner>, FlowError> {
+        self.inner
+            .lock()
+            .map_err(|_| FlowError::other("Writing goals are unavailable"))
+    }
+
+    fn save(&self, store: &mut Store) -> Result<(), FlowError> {
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir).map_err(|e| FlowError::io(dir, e))?;
+        }
+        store.version = STORE_VERSION;
+        let json = serde_json::to_vec(store)
+            .map_err(|e| FlowError::other(format!("Failed to serialize writing goals: {}", e)))?;
+        atomic::write(&self.file, &json)
+    }
+}
+
+fn progress(path: &Path, store: &Store, today: NaiveDate) -> WritingProgress {
+    let empty = Record::default();
+    let record = store.documents.get(path).unwrap_or(&empty);
+    let words = record.words.or_else(|| {
+        flm::read_document(path)
+            .ok()
+            .map(|doc| stats::document_stats(&doc).counts.words)
+    });
+
+    let week_start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let first_day = today - Duration::days(HISTORY_DAYS - 1);
+    let mut days: Vec<Day> = (0..HISTORY_DAYS)
+        .map(|offset| Day {
+            date: first_day + Duration::days(offset),
+            period: Period::default(),
+        })
+        .collect();
+    let mut this_week = Period::default();
+    for session in &record.sessions {
+        let date = session.started_at.with_timezone(&Local).date_naive();
+        if date >= week_start && date <= today {
+            this_week.add(session);
+        }
+        if date >= first_day && date <= today {
+            days[(date - first_day).num_days() as usize]
+                .period
+                .add(session);
+        }
+    }
+    let today_period = days
+        .last()
+        .map(|day| day.period.clone())
+        .unwrap_or_default();
+
+    let goal = record.goal.clone();
+    let remaining_words = goal
+        .target_words
+        .zip(words)
+        .map(|(target, words)| target.saturating_sub(words));
+    let days_left = goal
+        .deadline
+        .map(|deadline| ((deadline - today).num_days() + 1).max(0));
+    let words_per_day_needed = match (remaining_words, days_left) {
+        (Some(remaining), Some(days)) if days > 0 => Some(remaining.div_ceil(days as usize)),
+        _ => None,
+    };
+
+    WritingProgress {
+        path: path.to_path_buf(),
+        words,
+        goal,
+        today: today_period,
+        this_week,
+        days,
+        sessions: record
+            .sessions
+            .iter()
+            .rev()
+            .take(RECENT_SESSIONS)
+            .cloned()
+            .collect(),
+        remaining_words,
+        days_left,
+        words_per_day_needed,
+    }
+}
+
+impl Period {
+    fn add(&mut self, session: &Session) {
+        self.words_added += session.words_added;
+        self.words_removed += session.words_removed;
+        self.net_words += session.words_added as i64 - session.words_removed as i64;
+        self.minutes += (session.ended_at - session.started_at).num_minutes();
+        self.sessions += 1;
+    }
+}
+
+/// `path` made absolute where possible, so a document is found under the
+/// same key however it was opened.
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
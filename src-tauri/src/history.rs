@@ -0,0 +1,335 @@
+//! Revision history kept inside a .flm archive under `history/`.
+//!
+//! Every save stores the document it replaces as `history/<timestamp>.json`,
+//! named after that revision's `updatedAt`. Older revisions are thinned out
+//! on each save (see [`retain`]) so the archive stays bounded, and assets
+//! referenced by a kept revision are kept with it.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::assets;
+use crate::diff::{self, DocumentDiff};
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::flm::{self, DOCUMENT_ENTRY};
+
+pub const HISTORY_DIR: &str = "history/";
+
+/// Format of revision ids, e.g. `20250114T093012345Z`.
+const ID_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+/// Within this many hours, the last revision of every few minutes is kept.
+const RECENT_HOURS: i64 = 24;
+/// Length of those few minutes; autosave would otherwise add a revision
+/// every couple of seconds of writing.
+const RECENT_SLOT_MINUTES: i64 = 10;
+/// After that, the last revision of each day within this many days, and the
+/// last revision of each week beyond.
+const KEEP_DAILY_DAYS: i64 = 30;
+/// Upper bound on stored revisions.
+const MAX_REVISIONS: usize = 100;
+/// Each tier has its own share of [`MAX_REVISIONS`], so a busy day can't
+/// push out the daily and weekly revisions; the oldest of a tier go first.
+const MAX_RECENT: usize = 48;
+const MAX_WEEKLY: usize = MAX_REVISIONS - MAX_RECENT - KEEP_DAILY_DAYS as usize;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Revision {
+    /// Identifier passed back to the other history commands.
+    pub id: String,
+    /// When the revision was saved, as RFC 3339.
+    pub saved_at: String,
+    /// Size of the stored document in bytes.
+    pub size: u64,
+}
+
+/// History entries to write along with a new document.
+pub(crate) struct Pending {
+    pub entries: Vec<(String, Vec<u8>)>,
+    /// Existing entries to carry over: kept revisions and their assets.
+    pub keep: HashSet<String>,
+}
+
+/// Revisions stored in the archive at `path`, newest first.
+pub fn list(path: &Path) -> Result<Vec<Revision>, FlowError> {
+    let mut archive = flm::open_archive(path)?;
+    let mut revisions = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index_raw(index)
+            .map_err(|e| FlowError::zip(path, e))?;
+        let Some((id, time)) = parse_entry_name(entry.name()) else {
+            continue;
+        };
+        revisions.push((
+            time,
+            Revision {
+                id,
+                saved_at: time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+                size: entry.size(),
+            },
+        ));
+    }
+    revisions.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+    Ok(revisions
+        .into_iter()
+        .map(|(_, revision)| revision)
+        .collect())
+}
+
+/// The document stored as revision `id` in the archive at `path`.
+pub fn read(path: &Path, id: &str) -> Result<FlowmarkDocument, FlowError> {
+    if parse_id(id).is_none() {
+        return Err(FlowError::invalid(format!("Invalid revision id: {}", id)));
+    }
+    let mut archive = flm::open_archive(path)?;
+    let name = entry_name(id);
+    let json = flm::read_entry_string(path, &mut archive, &name)?.ok_or_else(|| {
+        FlowError::MissingEntry {
+            path: path.to_path_buf(),
+            entry: name.clone(),
+        }
+    })?;
+    FlowmarkDocument::from_json(&json)
+}
+
+/// Changes from revision `id` to revision `against`, or to the current
+/// document when `against` is `None`.
+pub fn diff(path: &Path, id: &str, against: Option<&str>) -> Result<DocumentDiff, FlowError> {
+    let before = read(path, id)?;
+    let after = match against {
+        Some(against) => read(path, against)?,
+        None => flm::read_document(path)?,
+    };
+    Ok(diff::diff_documents(&before, &after))
+}
+
+/// Make revision `id` the current document of the archive at `path`.
+///
+/// The document being replaced goes into the history like on any other
+/// save, so a restore can itself be undone.
+pub fn restore(path: &Path, id: &str) -> Result<FlowmarkDocument, FlowError> {
+    let current = flm::read_document(path)?;
+    let mut doc = read(path, id)?;
+    doc.created_at = current.created_at;
+    doc.updated_at = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    flm::write_document(path, &doc)?;
+    Ok(doc)
+}
+
+/// Snapshot the document about to be replaced by `doc` and apply the
+/// retention policy to the revisions already in the archive at `path`.
+pub(crate) fn prepare(path: &Path, doc: &FlowmarkDocument) -> Result<Pending, FlowError> {
+    let mut pending = Pending {
+        entries: Vec::new(),
+        keep: HashSet::new(),
+    };
+    // Nothing to keep when creating a file or replacing one that isn't an archive
+    let Some(mut archive) = flm::open_previous(path)? else {
+        return Ok(pending);
+    };
+
+    let mut revisions: HashMap<String, DateTime<Utc>> = (0..archive.len())
+        .filter_map(|index| {
+            let entry = archive.by_index_raw(index).ok()?;
+            parse_entry_name(entry.name())
+        })
+        .collect();
+
+    let mut snapshot = None;
+    if let Some(previous_json) = flm::read_entry_string(path, &mut archive, DOCUMENT_ENTRY)? {
+        // An unreadable previous document is still worth keeping
+        let previous = FlowmarkDocument::from_json(&previous_json).ok();
+        let unchanged = previous
+            .as_ref()
+            .is_some_and(|p| p.content == doc.content && p.notes == doc.notes);
+        if !unchanged {
+            let mut time = previous
+                .as_ref()
+                .and_then(|p| DateTime::parse_from_rfc3339(&p.updated_at).ok())
+                .map(|t| t.with_timezone(&Utc))
+                .unwrap_or_else(Utc::now);
+            while revisions.contains_key(&format_id(time)) {
+                time += Duration::milliseconds(1);
+            }
+            revisions.insert(format_id(time), time);
+            snapshot = Some((format_id(time), previous_json));
+        }
+    }
+
+    let kept = retain(&revisions, Utc::now());
+    for id in &kept {
+        let name = entry_name(id);
+        let json = match &snapshot {
+            Some((snapshot_id, json)) if snapshot_id == id => Some(json.clone()),
+            _ => flm::read_entry_string(path, &mut archive, &name)?,
+        };
+        // Keep the assets the revision needs to be restored faithfully
+        if let Some(json) = json.filter(|json| json.contains(assets::ASSETS_DIR)) {
+            if let Ok(revision) = FlowmarkDocument::from_json(&json) {
+                pending.keep.extend(assets::referenced(&revision));
+            }
+        }
+        pending.keep.insert(name);
+    }
+    if let Some((id, json)) = snapshot {
+        if kept.contains(&id) {
+            pending.entries.push((entry_name(&id), json.into_bytes()));
+        }
+    }
+    Ok(pending)
+}
+
+/// Ids of the revisions to keep as of `now`.
+///
+/// Keeps the newest revision of every [`RECENT_SLOT_MINUTES`] from the last
+/// [`RECENT_HOURS`], up to [`MAX_RECENT`] of them, then the newest of each
+/// day up to [`KEEP_DAILY_DAYS`] back, then the newest of each ISO week, up
+/// to [`MAX_WEEKLY`] of them.
+pub fn retain(revisions: &HashMap<String, DateTime<Utc>>, now: DateTime<Utc>) -> HashSet<String> {
+    let mut sorted: Vec<(&String, &DateTime<Utc>)> = revisions.iter().collect();
+    sorted.sort_by_key(|(_, time)| std::cmp::Reverse(**time));
+
+    let mut slots = HashSet::new();
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    sorted
+        .into_iter()
+        .filter(|(_, time)| {
+            let age = now - **time;
+            if age < Duration::hours(RECENT_HOURS) {
+                let slot = time.timestamp().div_euclid(RECENT_SLOT_MINUTES * 60);
+                slots.len() < MAX_RECENT && slots.insert(slot)
+            } else if age < Duration::days(KEEP_DAILY_DAYS) {
+                days.len() < KEEP_DAILY_DAYS as usize && days.insert(time.date_naive())
+            } else {
+                let week = time.iso_week();
+                weeks.len() < MAX_WEEKLY && weeks.insert((week.year(), week.week()))
+            }
+        })
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+fn entry_name(id: &str) -> String {
+    format!("{}{}.json", HISTORY_DIR, id)
+}
+
+fn format_id(time: DateTime<Utc>) -> String {
+    time.format(ID_FORMAT).to_string()
+}
+
+fn parse_id(id: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(id, ID_FORMAT)
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+fn parse_entry_name(name: &str) -> Option<(String, DateTime<Utc>)> {
+    let id = name.strip_prefix(HISTORY_DIR)?.strip_suffix(".json")?;
+    Some((id.to_string(), parse_id(id)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Timelike};
+
+    fn revisions(times: impl IntoIterator<Item = DateTime<Utc>>) -> HashMap<String, DateTime<Utc>> {
+        times
+            .into_iter()
+            .map(|time| (format_id(time), time))
+            .collect()
+    }
+
+    /// Kept revisions per tier: recent, daily and weekly.
+    fn tiers(kept: &HashSet<String>, now: DateTime<Utc>) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for id in kept {
+            let age = now - parse_id(id).unwrap();
+            if age < Duration::hours(RECENT_HOURS) {
+                counts.0 += 1;
+            } else if age < Duration::days(KEEP_DAILY_DAYS) {
+                counts.1 += 1;
+            } else {
+                counts.2 += 1;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn autosaves_dont_crowd_out_older_tiers() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        // An autosave every two minutes for half a year
+        let all = revisions((0..200 * 24 * 30).map(|i| now - Duration::minutes(2 * i)));
+        let kept = retain(&all, now);
+
+        assert_eq!(
+            tiers(&kept, now),
+            (MAX_RECENT, KEEP_DAILY_DAYS as usize, MAX_WEEKLY)
+        );
+        assert_eq!(kept.len(), MAX_REVISIONS);
+        // The newest revision of each slot, day and week
+        assert!(kept.contains(&format_id(now)));
+        assert!(kept.contains(&format_id(now - Duration::minutes(2))));
+        assert!(!kept.contains(&format_id(now - Duration::minutes(4))));
+        assert!(kept.contains(&format_id(now - Duration::minutes(12))));
+        let yesterday = Utc.with_ymd_and_hms(2024, 6, 14, 12, 0, 0).unwrap();
+        assert!(kept.contains(&format_id(yesterday)));
+        let day_before = Utc.with_ymd_and_hms(2024, 6, 13, 23, 58, 0).unwrap();
+        assert!(kept.contains(&format_id(day_before)));
+        // One weekly revision per week, the last of it
+        let weekly: Vec<DateTime<Utc>> = kept
+            .iter()
+            .filter_map(|id| parse_id(id))
+            .filter(|time| now - *time >= Duration::days(KEEP_DAILY_DAYS))
+            .collect();
+        let weeks: HashSet<_> = weekly.iter().map(|time| time.iso_week()).collect();
+        assert_eq!(weeks.len(), MAX_WEEKLY);
+        let ends_of_weeks = weekly
+            .iter()
+            .filter(|time| time.weekday() == chrono::Weekday::Sun && time.hour() == 23)
+            .count();
+        assert!(ends_of_weeks >= MAX_WEEKLY - 1);
+    }
+
+    #[test]
+    fn recent_slots_keep_the_newest_revision() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let all = revisions((0..30).map(|i| now - Duration::minutes(i)));
+        let kept = retain(&all, now);
+        // 12:00 is a slot of its own, then 11:50-11:59 and 11:40-11:49
+        let expected: HashSet<String> = [0, 1, 11, 21]
+            .into_iter()
+            .map(|i| format_id(now - Duration::minutes(i)))
+            .collect();
+        assert_eq!(kept, expected);
+    }
+
+    #[test]
+    fn sparse_history_is_kept_whole() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap();
+        let all = revisions(
+            [0, 1, 3, 5, 29]
+                .into_iter()
+                .map(|days| now - Duration::days(days) - Duration::hours(1))
+                .chain((5..20).map(|weeks| now - Duration::weeks(weeks))),
+        );
+        assert_eq!(retain(&all, now).len(), all.len());
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        let time = Utc.with_ymd_and_hms(2024, 6, 15, 12, 0, 0).unwrap() + Duration::milliseconds(7);
+        let id = format_id(time);
+        assert_eq!(id, "20240615T120000007Z");
+        assert_eq!(parse_entry_name(&entry_name(&id)), Some((id, time)));
+        assert_eq!(parse_entry_name("history/notes.json"), None);
+        assert_eq!(parse_entry_name("assets/20240615T120000007Z.json"), None);
+    }
+}
@@ -0,0 +1,413 @@
+//! Markdown (CommonMark + GFM) to FlowMark conversion.
+//!
+//! Follows `frontend/src/editor/clipboard/markdownParser.ts`, which handles
+//! pasted Markdown, so imported and pasted text end up with the same
+//! structure. Footnotes become entries of the document's `notes`, numbered
+//! in order of first reference.
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::assets;
+use crate::document::{FlowmarkDocument, Mark, MarkKind, Node, NodeKind, Note};
+use crate::error::FlowError;
+use crate::flm;
+
+/// Read the Markdown file at `path` and convert it.
+///
+/// With an `output_path` the document is also written there as a .flm
+/// archive, and local images are stored in it as assets.
+pub fn import_file(path: &Path, output_path: Option<&Path>) -> Result<FlowmarkDocument, FlowError> {
+    let markdown = fs::read_to_string(path).map_err(|e| FlowError::io(path, e))?;
+    let mut doc = from_markdown(&markdown);
+
+    if let Some(output_path) = output_path {
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        let entries = embed_images(&mut doc, base_dir);
+        flm::write_archive(output_path, &doc, |_| false, entries)?;
+    }
+    Ok(doc)
+}
+
+/// Convert Markdown text to a new FlowMark document.
+pub fn from_markdown(markdown: &str) -> FlowmarkDocument {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+
+    let mut builder = Builder::default();
+    for event in Parser::new_ext(markdown, options) {
+        builder.event(event);
+    }
+    builder.finish()
+}
+
+/// Replace references to local image files with archive assets.
+///
+/// Returns the asset entries to store; images that are remote or can't be
+/// read keep their original `src`.
+fn embed_images(doc: &mut FlowmarkDocument, base_dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    doc.content.doc.walk_mut(&mut |node| {
+        if node.kind != NodeKind::Image {
+            return;
+        }
+        let Some(src) = node.attr_str("src") else {
+            return;
+        };
+        if src.contains("://") || src.starts_with("data:") || src.starts_with(assets::ASSETS_DIR) {
+            return;
+        }
+        let decoded = percent_encoding::percent_decode_str(src).decode_utf8_lossy();
+        let file = base_dir.join(decoded.as_ref());
+        let Ok(bytes) = fs::read(&file) else {
+            log::warn!("Image {} not found; keeping the reference", file.display());
+            return;
+        };
+        let file_name = file.file_name().and_then(|n| n.to_str()).unwrap_or("image");
+        let name = assets::entry_name(file_name, &bytes);
+        node.attrs
+            .insert("src".to_string(), Value::from(name.clone()));
+        if !entries.iter().any(|(existing, _)| *existing == name) {
+            entries.push((name, bytes));
+        }
+    });
+    entries
+}
+
+/// Builds the node tree from parser events.
+#[derive(Default)]
+struct Builder {
+    /// Open nodes; the document root is added in `finish`
+    stack: Vec<Node>,
+    root: Vec<Node>,
+    marks: Vec<Mark>,
+    /// Note id for each footnote label
+    labels: HashMap<String, String>,
+    /// Note id and text of each footnote definition, in document order
+    definitions: Vec<(String, String)>,
+}
+
+impl Builder {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                let mut node = Node::text(code.to_string());
+                node.marks = self.marks.clone();
+                node.marks.push(mark(MarkKind::Code));
+                self.push_inline(node);
+            }
+            Event::Html(html) | Event::InlineHtml(html) => self.text(&html),
+            Event::InlineMath(math) | Event::DisplayMath(math) => self.text(&math),
+            Event::FootnoteReference(label) => {
+                // Numbers are assigned in `finish`, once all references are known
+                let note_id = self.note_id(&label);
+                self.push_inline(Node::new(NodeKind::NoteRef).with_attr("noteId", note_id));
+            }
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.push_inline(Node::new(NodeKind::HardBreak)),
+            Event::Rule => self.push_block(Node::new(NodeKind::HorizontalRule)),
+            Event::TaskListMarker(checked) => self.text(if checked { "[x] " } else { "[ ] " }),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        let node = match tag {
+            Tag::Paragraph => Node::new(NodeKind::Paragraph),
+            Tag::Heading { level, .. } => {
+                Node::new(NodeKind::Heading).with_attr("level", level as u64)
+            }
+            Tag::BlockQuote(_) => Node::new(NodeKind::Blockquote),
+            Tag::CodeBlock(kind) => {
+                let node = Node::new(NodeKind::CodeBlock);
+                match kind {
+                    CodeBlockKind::Fenced(info) => match info.split_whitespace().next() {
+                        Some(language) => node.with_attr("language", language),
+                        None => node,
+                    },
+                    CodeBlockKind::Indented => node,
+                }
+            }
+            Tag::HtmlBlock => Node::new(NodeKind::Paragraph),
+            Tag::List(Some(start)) => Node::new(NodeKind::OrderedList).with_attr("order", start),
+            Tag::List(None) => Node::new(NodeKind::BulletList),
+            Tag::Item => Node::new(NodeKind::ListItem),
+            Tag::FootnoteDefinition(label) => {
+                let note_id = self.note_id(&label);
+                Node::new(NodeKind::Other("footnote".to_string())).with_attr("noteId", note_id)
+            }
+            Tag::Table(_) => Node::new(NodeKind::Table),
+            Tag::TableHead | Tag::TableRow => Node::new(NodeKind::TableRow),
+            Tag::TableCell => {
+                // Header cells become plain cells too, as when pasting. Cells
+                // hold blocks, so the paragraph is closed with the cell
+                self.stack.push(Node::new(NodeKind::TableCell));
+                Node::new(NodeKind::Paragraph)
+            }
+            Tag::Emphasis => {
+                self.marks.push(mark(MarkKind::Em));
+                return;
+            }
+            Tag::Strong => {
+                self.marks.push(mark(MarkKind::Strong));
+                return;
+            }
+            Tag::Link {
+                dest_url, title, ..
+            } => {
+                let mut link = mark(MarkKind::Link);
+                link.attrs
+                    .insert("href".to_string(), Value::from(dest_url.to_string()));
+                if !title.is_empty() {
+                    link.attrs
+                        .insert("title".to_string(), Value::from(title.to_string()));
+                }
+                self.marks.push(link);
+                return;
+            }
+            Tag::Image {
+                dest_url, title, ..
+            } => {
+                // The alt text arrives as text events inside the image
+                let image = Node::new(NodeKind::Image).with_attr("src", dest_url.to_string());
+                if title.is_empty() {
+                    image
+                } else {
+                    image.with_attr("title", title.to_string())
+                }
+            }
+            // No schema equivalent; the content is kept without the markup
+            Tag::Strikethrough
+            | Tag::Superscript
+            | Tag::Subscript
+            | Tag::DefinitionList
+            | Tag::DefinitionListTitle
+            | Tag::DefinitionListDefinition => return,
+            Tag::MetadataBlock(_) => Node::new(NodeKind::Other("metadata".to_string())),
+        };
+        self.stack.push(node);
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Link => {
+                self.marks.pop();
+                return;
+            }
+            TagEnd::Strikethrough
+            | TagEnd::Superscript
+            | TagEnd::Subscript
+            | TagEnd::DefinitionList
+            | TagEnd::DefinitionListTitle
+            | TagEnd::DefinitionListDefinition => return,
+            _ => {}
+        }
+
+        let Some(mut node) = self.stack.pop() else {
+            return;
+        };
+        match tag {
+            TagEnd::TableCell => {
+                let mut cell = self
+                    .stack
+                    .pop()
+                    .unwrap_or_else(|| Node::new(NodeKind::TableCell));
+                cell.content.push(node);
+                self.push_block(cell);
+            }
+            TagEnd::Image => {
+                let alt = node.text_content();
+                node.content.clear();
+                node.attrs.insert("alt".to_string(), Value::from(alt));
+                self.push_inline(node);
+            }
+            TagEnd::CodeBlock => {
+                if let Some(text) = node.content.first_mut().and_then(|n| n.text.as_mut()) {
+                    if text.ends_with('\n') {
+                        text.pop();
+                    }
+                }
+                node.content
+                    .retain(|n| n.text.as_ref().is_some_and(|t| !t.is_empty()));
+                self.push_block(node);
+            }
+            TagEnd::FootnoteDefinition => {
+                let note_id = node.attr_str("noteId").unwrap_or_default().to_string();
+                let text = wrap_inline(node.content)
+                    .iter()
+                    .map(|block| block.text_content().trim().to_string())
+                    .filter(|text| !text.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                self.definitions.push((note_id, text));
+            }
+            TagEnd::MetadataBlock(_) => {}
+            TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::HtmlBlock => {
+                trim_trailing_newline(&mut node);
+                self.push_block(node);
+            }
+            _ => {
+                node.content = wrap_inline(std::mem::take(&mut node.content));
+                self.push_block(node);
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let marks = self.marks.clone();
+        // Code blocks keep their text unmarked and in one node
+        if let Some(top) = self.stack.last_mut() {
+            if top.kind == NodeKind::CodeBlock {
+                match top.content.last_mut().and_then(|n| n.text.as_mut()) {
+                    Some(existing) => existing.push_str(text),
+                    None => top.content.push(Node::text(text)),
+                }
+                return;
+            }
+        }
+        let mut node = Node::text(text);
+        node.marks = marks;
+        self.push_inline(node);
+    }
+
+    /// Append an inline node, merging text with the previous node when the marks match.
+    fn push_inline(&mut self, node: Node) {
+        let content = match self.stack.last_mut() {
+            Some(top) => &mut top.content,
+            None => &mut self.root,
+        };
+        if let (Some(last), Some(text)) = (content.last_mut(), &node.text) {
+            if last.is_text() && last.marks == node.marks {
+                last.text.get_or_insert_with(String::new).push_str(text);
+                return;
+            }
+        }
+        content.push(node);
+    }
+
+    fn push_block(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            Some(top) => top.content.push(node),
+            None => self.root.push(node),
+        }
+    }
+
+    fn note_id(&mut self, label: &str) -> String {
+        self.labels
+            .entry(label.to_string())
+            .or_insert_with(Note::generate_id)
+            .clone()
+    }
+
+    fn finish(mut self) -> FlowmarkDocument {
+        // Unclosed nodes only happen on malformed input; keep their content
+        while let Some(node) = self.stack.pop() {
+            self.push_block(node);
+        }
+        let mut content = wrap_inline(std::mem::take(&mut self.root));
+        if content.is_empty() {
+            content.push(Node::new(NodeKind::Paragraph));
+        }
+        let mut doc = Node::new(NodeKind::Doc).with_content(content);
+
+        // Number notes in order of first reference, like the editor
+        let mut numbers: HashMap<String, u32> = HashMap::new();
+        let mut order: Vec<String> = Vec::new();
+        doc.walk_mut(&mut |node| {
+            if node.kind != NodeKind::NoteRef {
+                return;
+            }
+            let note_id = node.attr_str("noteId").unwrap_or_default().to_string();
+            let next = numbers.len() as u32 + 1;
+            let number = *numbers.entry(note_id.clone()).or_insert_with(|| {
+                order.push(note_id);
+                next
+            });
+            node.attrs.insert("number".to_string(), Value::from(number));
+        });
+
+        let mut texts: HashMap<&str, &str> = HashMap::new();
+        for (note_id, text) in &self.definitions {
+            texts.entry(note_id).or_insert(text);
+        }
+        let text_of = |note_id: &str| texts.get(note_id).copied().unwrap_or_default().to_string();
+
+        let mut notes: Vec<Note> = order
+            .iter()
+            .zip(1..)
+            .map(|(note_id, number)| Note {
+                note_id: note_id.clone(),
+                content: text_of(note_id),
+                number,
+            })
+            .collect();
+        // Definitions that are never referenced are kept after the others
+        for (note_id, _) in &self.definitions {
+            if numbers.contains_key(note_id) || notes.iter().any(|note| note.note_id == *note_id) {
+                continue;
+            }
+            notes.push(Note {
+                note_id: note_id.clone(),
+                content: text_of(note_id),
+                number: notes.len() as u32 + 1,
+            });
+        }
+
+        FlowmarkDocument::new(doc, notes)
+    }
+}
+
+fn mark(kind: MarkKind) -> Mark {
+    Mark {
+        kind,
+        attrs: Map::new(),
+    }
+}
+
+fn is_inline(node: &Node) -> bool {
+    matches!(
+        node.kind,
+        NodeKind::Text | NodeKind::HardBreak | NodeKind::Image | NodeKind::NoteRef
+    )
+}
+
+/// Wrap runs of inline nodes among blocks in paragraphs.
+///
+/// Tight list items hold their text directly, but list items, quotes and
+/// the document only accept blocks.
+fn wrap_inline(nodes: Vec<Node>) -> Vec<Node> {
+    let mut blocks = Vec::new();
+    let mut run: Vec<Node> = Vec::new();
+    for node in nodes {
+        if is_inline(&node) {
+            run.push(node);
+            continue;
+        }
+        if !run.is_empty() {
+            blocks.push(Node::new(NodeKind::Paragraph).with_content(std::mem::take(&mut run)));
+        }
+        blocks.push(node);
+    }
+    if !run.is_empty() {
+        blocks.push(Node::new(NodeKind::Paragraph).with_content(run));
+    }
+    blocks
+}
+
+/// Drop the line ending that closes raw HTML blocks.
+fn trim_trailing_newline(node: &mut Node) {
+    if let Some(text) = node.content.last_mut().and_then(|n| n.text.as_mut()) {
+        let trimmed = text.trim_end_matches('\n').len();
+        text.truncate(trimmed);
+    }
+    node.content
+        .retain(|n| !n.is_text() || n.text.as_ref().is_some_and(|t| !t.is_empty()));
+}
@@ -0,0 +1,3 @@
+//! Import of documents from other formats into FlowMark.
+
+pub mod markdown;
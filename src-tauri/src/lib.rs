@@ -1,57 +1,363 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use zip::write::FileOptions;
-use zip::ZipWriter;
-use zip::read::ZipArchive;
-
-#[tauri::command]
-fn save_flm(path: String, document_json: String) -> Result<(), String> {
-    // Create or open the ZIP file
-    let file = File::create(&path).map_err(|e| format!("Failed to create file: {}", e))?;
-    let mut zip = ZipWriter::new(file);
-    
-    // Add document.json to the ZIP
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o644);
-    
-    zip.start_file("document.json", options)
-        .map_err(|e| format!("Failed to add file to ZIP: {}", e))?;
-    
-    zip.write_all(document_json.as_bytes())
-        .map_err(|e| format!("Failed to write to ZIP: {}", e))?;
-    
-    zip.finish()
-        .map_err(|e| format!("Failed to finalize ZIP: {}", e))?;
-    
-    Ok(())
+pub mod assets;
+pub mod atomic;
+pub mod cli;
+pub mod crypto;
+pub mod diff;
+pub mod document;
+pub mod error;
+pub mod export;
+pub mod files;
+pub mod flm;
+pub mod goals;
+pub mod history;
+pub mod import;
+pub mod limits;
+pub mod manifest;
+pub mod merge;
+pub mod migrate;
+pub mod notes;
+pub mod recovery;
+pub mod repair;
+pub mod schema;
+pub mod search;
+pub mod stats;
+pub mod watch;
+pub mod workspace;
+
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use assets::AssetInfo;
+use diff::{DocumentDiff, Source};
+use document::FlowmarkDocument;
+use error::FlowError;
+use export::epub::EpubOptions;
+use export::html::HtmlOptions;
+use export::pdf::PdfOptions;
+use goals::{Goal, Goals, WritingProgress};
+use history::Revision;
+use limits::ArchiveLimits;
+use merge::MergeResult;
+use notes::NoteReport;
+use recovery::{Journal, RecoveredDocument};
+use repair::RepairReport;
+use search::{IndexStats, SearchHit, SearchIndex};
+use stats::DocumentStats;
+use watch::OpenFiles;
+use workspace::{Workspace, WorkspaceInfo, WorkspaceSettings};
+
+#[tauri::command]
+fn save_flm(
+    open_files: State<'_, OpenFiles>,
+    path: String,
+    document_json: String,
+    force: Option<bool>,
+    remove_orphaned_notes: Option<bool>,
+    source_path: Option<String>,
+) -> Result<NoteReport, FlowError> {
+    let mut doc = FlowmarkDocument::from_json(&document_json)?;
+    let report = notes::reconcile(&mut doc, remove_orphaned_notes.unwrap_or(false));
+    if let Some(source_path) = source_path {
+        // "Save As" of an encrypted document stays encrypted
+        crypto::carry_key(Path::new(&source_path), Path::new(&path))?;
+    }
+    open_files.write(Path::new(&path), force.unwrap_or(false), || {
+        flm::write_document(&path, &doc)
+    })?;
+    Ok(report)
 }
 
 #[tauri::command]
-fn load_flm(path: String) -> Result<String, String> {
-    // Open the ZIP file
-    let file = File::open(&path).map_err(|e| format!("Failed to open file: {}", e))?;
-    let mut archive = ZipArchive::new(file).map_err(|e| format!("Failed to read ZIP: {}", e))?;
-    
-    // Find and read document.json
-    let mut document_file = archive
-        .by_name("document.json")
-        .map_err(|e| format!("document.json not found in .flm file: {}", e))?;
-    
-    let mut document_json = String::new();
-    document_file
-        .read_to_string(&mut document_json)
-        .map_err(|e| format!("Failed to read document.json: {}", e))?;
-    
-    // Validate format (basic check)
-    let doc: serde_json::Value = serde_json::from_str(&document_json)
-        .map_err(|e| format!("Invalid JSON in document.json: {}", e))?;
-    
-    if doc.get("format").and_then(|v| v.as_str()) != Some("flowmark") {
-        return Err("Invalid format: expected 'flowmark'".to_string());
+fn load_flm(
+    open_files: State<'_, OpenFiles>,
+    goals: State<'_, Goals>,
+    path: String,
+    password: Option<String>,
+    fix_schema: Option<bool>,
+) -> Result<String, FlowError> {
+    if let Some(password) = password {
+        crypto::unlock(Path::new(&path), &password)?;
+    }
+    // Only tracked once the document loaded, so a failed open isn't watched
+    let doc = open_files.load(Path::new(&path), || {
+        let mut doc = flm::read_document(&path)?;
+        // Notes first: references to missing notes are theirs to report and drop
+        let report = notes::reconcile(&mut doc, false);
+        if !report.is_consistent() {
+            log::info!("Reconciled notes of {}: {:?}", path, report);
+        }
+        let fixed = schema::conform(Path::new(&path), &mut doc, fix_schema.unwrap_or(false))?;
+        if !fixed.is_empty() {
+            log::warn!("Fixed {} schema violations in {}", fixed.len(), path);
+        }
+        Ok(doc)
+    })?;
+    // Baseline for the writing session tracker
+    if let Err(e) = goals.start(Path::new(&path), &doc) {
+        log::warn!("Failed to record writing session: {}", e);
     }
-    
-    Ok(document_json)
+    doc.to_json()
+}
+
+#[tauri::command]
+fn is_flm_encrypted(path: String) -> bool {
+    crypto::is_encrypted(&path)
+}
+
+#[tauri::command]
+fn set_flm_password(
+    open_files: State<'_, OpenFiles>,
+    path: String,
+    current_password: Option<String>,
+    new_password: Option<String>,
+) -> Result<(), FlowError> {
+    let path = Path::new(&path);
+    open_files.write(path, false, || {
+        crypto::set_password(path, current_password.as_deref(), new_password.as_deref())
+    })
+}
+
+#[tauri::command]
+fn lock_flm(path: String) {
+    crypto::lock(Path::new(&path));
+}
+
+#[tauri::command]
+fn unwatch_file(open_files: State<'_, OpenFiles>, path: String) -> Result<(), FlowError> {
+    open_files.untrack(Path::new(&path))
+}
+
+#[tauri::command]
+fn add_asset(open_files: State<'_, OpenFiles>, path: String, source_path: String) -> Result<AssetInfo, FlowError> {
+    let path = Path::new(&path);
+    open_files.write(path, false, || {
+        assets::add_asset_from_path(path, Path::new(&source_path))
+    })
+}
+
+#[tauri::command]
+fn add_asset_bytes(
+    open_files: State<'_, OpenFiles>,
+    path: String,
+    file_name: String,
+    bytes: Vec<u8>,
+) -> Result<AssetInfo, FlowError> {
+    let path = Path::new(&path);
+    open_files.write(path, false, || assets::add_asset(path, &file_name, bytes))
+}
+
+#[tauri::command]
+fn list_assets(path: String) -> Result<Vec<AssetInfo>, FlowError> {
+    assets::list_assets(Path::new(&path))
+}
+
+#[tauri::command]
+fn export_markdown(path: String, output_path: String) -> Result<(), FlowError> {
+    let doc = flm::read_document(&path)?;
+    export::markdown::write_markdown(&doc, Some(Path::new(&path)), Path::new(&output_path))
+}
+
+#[tauri::command]
+fn export_html(path: String, output_path: String, options: Option<HtmlOptions>) -> Result<(), FlowError> {
+    let doc = flm::read_document(&path)?;
+    let options = options.unwrap_or_default();
+    export::html::write_html(&doc, &options, Some(Path::new(&path)), Path::new(&output_path))
+}
+
+#[tauri::command]
+fn export_epub(path: String, output_path: String, options: Option<EpubOptions>) -> Result<(), FlowError> {
+    let doc = flm::read_document(&path)?;
+    let options = options.unwrap_or_default();
+    export::epub::write_epub(&doc, &options, Some(Path::new(&path)), Path::new(&output_path))
+}
+
+#[tauri::command]
+fn export_pdf(path: String, output_path: String, options: Option<PdfOptions>) -> Result<(), FlowError> {
+    let doc = flm::read_document(&path)?;
+    let options = options.unwrap_or_default();
+    export::pdf::write_pdf(&doc, &options, Path::new(&output_path))
+}
+
+#[tauri::command]
+fn import_markdown(path: String, output_path: Option<String>) -> Result<String, FlowError> {
+    let output_path = output_path.as_deref().map(Path::new);
+    let doc = import::markdown::import_file(Path::new(&path), output_path)?;
+    doc.to_json()
+}
+
+#[tauri::command]
+fn list_history(path: String) -> Result<Vec<Revision>, FlowError> {
+    history::list(Path::new(&path))
+}
+
+#[tauri::command]
+fn preview_history(path: String, id: String) -> Result<String, FlowError> {
+    history::read(Path::new(&path), &id)?.to_json()
+}
+
+#[tauri::command]
+fn diff_history(path: String, id: String, against: Option<String>) -> Result<DocumentDiff, FlowError> {
+    history::diff(Path::new(&path), &id, against.as_deref())
+}
+
+#[tauri::command]
+fn restore_history(open_files: State<'_, OpenFiles>, path: String, id: String) -> Result<String, FlowError> {
+    let path = Path::new(&path);
+    open_files.write(path, false, || history::restore(path, &id))?.to_json()
+}
+
+#[tauri::command]
+fn journal_document(
+    journal: State<'_, Journal>,
+    id: String,
+    path: Option<String>,
+    document_json: String,
+) -> Result<(), FlowError> {
+    let doc = FlowmarkDocument::from_json(&document_json)?;
+    journal.record(&id, path, &doc)
+}
+
+#[tauri::command]
+fn discard_journal(journal: State<'_, Journal>, id: String) -> Result<(), FlowError> {
+    journal.discard(&id)
+}
+
+#[tauri::command]
+fn list_recovery(journal: State<'_, Journal>) -> Result<Vec<RecoveredDocument>, FlowError> {
+    journal.recovered()
+}
+
+#[tauri::command]
+fn restore_recovery(journal: State<'_, Journal>, id: String) -> Result<String, FlowError> {
+    journal.restore(&id)?.to_json()
+}
+
+#[tauri::command]
+fn discard_recovery(journal: State<'_, Journal>, id: String) -> Result<(), FlowError> {
+    journal.discard_recovered(&id)
+}
+
+#[tauri::command]
+fn index_folder(index: State<'_, SearchIndex>, folder: String) -> Result<IndexStats, FlowError> {
+    index.index_folder(Path::new(&folder))
+}
+
+#[tauri::command]
+fn unindex_folder(index: State<'_, SearchIndex>, folder: String) -> Result<(), FlowError> {
+    index.remove_folder(Path::new(&folder))
+}
+
+#[tauri::command]
+fn search(index: State<'_, SearchIndex>, query: String, limit: Option<usize>) -> Result<Vec<SearchHit>, FlowError> {
+    index.search(&query, limit.unwrap_or(50))
+}
+
+#[tauri::command]
+fn open_workspace(
+    app: AppHandle,
+    workspace: State<'_, Mutex<Option<Workspace>>>,
+    path: String,
+) -> Result<WorkspaceInfo, FlowError> {
+    let opened = Workspace::open(Path::new(&path), move |change| {
+        if let Err(e) = app.emit("workspace-changed", change) {
+            log::warn!("Failed to emit workspace change: {}", e);
+        }
+    })?;
+    let info = opened.info()?;
+    *workspace.lock().map_err(|e| FlowError::other(e.to_string()))? = Some(opened);
+    Ok(info)
+}
+
+#[tauri::command]
+fn workspace_info(workspace: State<'_, Mutex<Option<Workspace>>>) -> Result<Option<WorkspaceInfo>, FlowError> {
+    let workspace = workspace.lock().map_err(|e| FlowError::other(e.to_string()))?;
+    workspace.as_ref().map(Workspace::info).transpose()
+}
+
+#[tauri::command]
+fn save_workspace_settings(
+    workspace: State<'_, Mutex<Option<Workspace>>>,
+    settings: WorkspaceSettings,
+) -> Result<(), FlowError> {
+    let workspace = workspace.lock().map_err(|e| FlowError::other(e.to_string()))?;
+    workspace
+        .as_ref()
+        .ok_or_else(|| FlowError::invalid("No workspace is open"))?
+        .save_settings(&settings)
+}
+
+#[tauri::command]
+fn close_workspace(workspace: State<'_, Mutex<Option<Workspace>>>) -> Result<(), FlowError> {
+    *workspace.lock().map_err(|e| FlowError::other(e.to_string()))? = None;
+    Ok(())
+}
+
+#[tauri::command]
+fn merge_flm(
+    ours_path: String,
+    theirs_path: String,
+    base_path: Option<String>,
+    output_path: Option<String>,
+) -> Result<MergeResult, FlowError> {
+    merge::merge_files(
+        Path::new(&ours_path),
+        Path::new(&theirs_path),
+        base_path.as_deref().map(Path::new),
+        output_path.as_deref().map(Path::new),
+    )
+}
+
+#[tauri::command]
+fn diff_documents(old: Source, new: Source) -> Result<DocumentDiff, FlowError> {
+    Ok(diff::diff_documents(&old.load()?, &new.load()?))
+}
+
+#[tauri::command]
+fn document_stats(document_json: String) -> Result<DocumentStats, FlowError> {
+    let doc = FlowmarkDocument::from_json(&document_json)?;
+    Ok(stats::document_stats(&doc))
+}
+
+#[tauri::command]
+fn record_writing(goals: State<'_, Goals>, path: String, document_json: String) -> Result<WritingProgress, FlowError> {
+    let doc = FlowmarkDocument::from_json(&document_json)?;
+    goals.record(Path::new(&path), &doc)
+}
+
+#[tauri::command]
+fn end_writing_session(goals: State<'_, Goals>, path: String) -> Result<(), FlowError> {
+    goals.close(Path::new(&path))
+}
+
+#[tauri::command]
+fn set_writing_goal(goals: State<'_, Goals>, path: String, goal: Goal) -> Result<WritingProgress, FlowError> {
+    goals.set_goal(Path::new(&path), goal)
+}
+
+#[tauri::command]
+fn writing_progress(goals: State<'_, Goals>, path: String) -> Result<WritingProgress, FlowError> {
+    goals.progress(Path::new(&path))
+}
+
+#[tauri::command]
+fn archive_limits() -> ArchiveLimits {
+    limits::limits()
+}
+
+#[tauri::command]
+fn set_archive_limits(limits: ArchiveLimits) -> Result<(), FlowError> {
+    limits::set_limits(limits)
+}
+
+#[tauri::command]
+fn repair_flm(path: String, output_path: Option<String>) -> Result<RepairReport, FlowError> {
+    repair::repair(Path::new(&path), output_path.as_deref().map(Path::new))
+}
+
+#[tauri::command]
+fn check_notes(path: String) -> Result<NoteReport, FlowError> {
+    Ok(notes::check(&flm::read_document(&path)?))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -59,7 +365,63 @@ pub fn run() {
   tauri::Builder::default()
     .plugin(tauri_plugin_fs::init())
     .plugin(tauri_plugin_dialog::init())
-    .invoke_handler(tauri::generate_handler![save_flm, load_flm])
+    .register_uri_scheme_protocol(assets::URI_SCHEME, |_ctx, request| {
+      let uri = request.uri();
+      match assets::resolve_request(uri.path(), uri.query()) {
+        Ok((bytes, mime_type)) => tauri::http::Response::builder()
+          .header(tauri::http::header::CONTENT_TYPE, mime_type)
+          .body(bytes)
+          .unwrap(),
+        Err(e) => tauri::http::Response::builder()
+          .status(tauri::http::StatusCode::NOT_FOUND)
+          .body(e.to_string().into_bytes())
+          .unwrap(),
+      }
+    })
+    .manage(Mutex::new(None::<Workspace>))
+    .invoke_handler(tauri::generate_handler![
+      save_flm,
+      load_flm,
+      unwatch_file,
+      is_flm_encrypted,
+      set_flm_password,
+      lock_flm,
+      add_asset,
+      add_asset_bytes,
+      list_assets,
+      export_markdown,
+      export_html,
+      export_epub,
+      export_pdf,
+      import_markdown,
+      list_history,
+      preview_history,
+      diff_history,
+      restore_history,
+      journal_document,
+      discard_journal,
+      list_recovery,
+      restore_recovery,
+      discard_recovery,
+      index_folder,
+      unindex_folder,
+      search,
+      open_workspace,
+      workspace_info,
+      save_workspace_settings,
+      close_workspace,
+      merge_flm,
+      diff_documents,
+      document_stats,
+      record_writing,
+      end_writing_session,
+      set_writing_goal,
+      writing_progress,
+      repair_flm,
+      archive_limits,
+      set_archive_limits,
+      check_notes
+    ])
     .setup(|app| {
       if cfg!(debug_assertions) {
         app.handle().plugin(
@@ -68,8 +430,26 @@ pub fn run() {
             .build(),
         )?;
       }
+
+      // A leftover session lock means the last run crashed
+      let data_dir = app.path().app_data_dir()?;
+      app.manage(Journal::open(data_dir.join("recovery"))?);
+      app.manage(SearchIndex::open(data_dir.join("search-index.json")));
+      app.manage(Goals::open(data_dir.join("goals.json")));
+
+      let handle = app.handle().clone();
+      app.manage(OpenFiles::new(move |change| {
+        if let Err(e) = handle.emit("file-changed", change) {
+          log::warn!("Failed to emit file change: {}", e);
+        }
+      })?);
       Ok(())
     })
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|app, event| {
+      if let tauri::RunEvent::Exit = event {
+        app.state::<Journal>().close();
+      }
+    });
 }
@@ -0,0 +1,435 @@
+//! Limits on what an archive may contain.
+//!
+//! A .flm file is a ZIP archive from wherever it was downloaded or mailed
+//! from, so it is checked before anything is decompressed: the number of
+//! entries, their declared sizes and compression ratios, and their names.
+//! Declared sizes can lie, so reads are capped as well, and JSON entries
+//! are checked for nesting before they are parsed.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::error::FlowError;
+
+/// End of central directory record, and the ZIP64 locator and record.
+const EOCD: &[u8; 4] = b"PK\x05\x06";
+const EOCD_LEN: usize = 22;
+const ZIP64_LOCATOR: &[u8; 4] = b"PK\x06\x07";
+const ZIP64_LOCATOR_LEN: usize = 20;
+const ZIP64_EOCD: &[u8; 4] = b"PK\x06\x06";
+/// The record may be followed by a comment of up to this length.
+const MAX_COMMENT_LEN: usize = u16::MAX as usize;
+
+/// Entries smaller than this aren't checked for their compression ratio;
+/// short, repetitive JSON compresses very well.
+const RATIO_MIN_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ArchiveLimits {
+    pub max_entries: u64,
+    /// Uncompressed size of a single entry, in bytes.
+    pub max_entry_size: u64,
+    /// Uncompressed size of all entries together, in bytes.
+    pub max_total_size: u64,
+    /// Uncompressed to compressed size of an entry.
+    pub max_compression_ratio: u64,
+    /// Depth of nested objects and arrays in a JSON entry.
+    pub max_nesting: u32,
+}
+
+const DEFAULT_LIMITS: ArchiveLimits = ArchiveLimits {
+    max_entries: 10_000,
+    max_entry_size: 256 * 1024 * 1024,
+    max_total_size: 1024 * 1024 * 1024,
+    max_compression_ratio: 100,
+    max_nesting: 100,
+};
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        DEFAULT_LIMITS
+    }
+}
+
+static LIMITS: RwLock<ArchiveLimits> = RwLock::new(DEFAULT_LIMITS);
+
+/// The limits applied to every archive read.
+pub fn limits() -> ArchiveLimits {
+    LIMITS.read().map(|limits| *limits).unwrap_or_default()
+}
+
+pub fn set_limits(limits: ArchiveLimits) -> Result<(), FlowError> {
+    let zero = limits.max_entries == 0
+        || limits.max_entry_size == 0
+        || limits.max_total_size == 0
+        || limits.max_compression_ratio == 0
+        || limits.max_nesting == 0;
+    if zero {
+        return Err(FlowError::invalid(
+            "Archive limits must be greater than zero",
+        ));
+    }
+    *LIMITS
+        .write()
+        .map_err(|_| FlowError::other("Archive limits are unavailable"))? = limits;
+    Ok(())
+}
+
+/// Check the entry count in the end of central directory of the ZIP data
+/// in `reader`, before the directory itself is read into memory.
+pub fn check_directory<R: Read + Seek>(path: &Path, reader: &mut R) -> Result<(), FlowError> {
+    let limits = limits();
+    let count = declared_entries(reader).map_err(|e| FlowError::io(path, e))?;
+    reader
+        .seek(SeekFrom::Start(0))
+        .map_err(|e| FlowError::io(path, e))?;
+    match count {
+        Some(count) if count > limits.max_entries => Err(FlowError::TooManyEntries {
+            path: path.to_path_buf(),
+            count,
+            limit: limits.max_entries,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Check every entry of an opened archive against the limits.
+pub fn check_archive<R: Read + Seek>(
+    path: &Path,
+    archive: &mut zip::ZipArchive<R>,
+) -> Result<(), FlowError> {
+    let limits = limits();
+    if archive.len() as u64 > limits.max_entries {
+        return Err(FlowError::TooManyEntries {
+            path: path.to_path_buf(),
+            count: archive.len() as u64,
+            limit: limits.max_entries,
+        });
+    }
+    let mut total: u64 = 0;
+    for index in 0..archive.len() {
+        let entry = archive
+            .by_index_raw(index)
+            .map_err(|e| FlowError::zip(path, e))?;
+        let name = entry.name();
+        if !is_safe_name(name) {
+            return Err(FlowError::UnsafeEntryName {
+                path: path.to_path_buf(),
+                entry: name.to_string(),
+            });
+        }
+        let size = entry.size();
+        if size > limits.max_entry_size {
+            return Err(FlowError::EntryTooLarge {
+                path: path.to_path_buf(),
+                entry: name.to_string(),
+                size,
+                limit: limits.max_entry_size,
+            });
+        }
+        let ratio = size / entry.compressed_size().max(1);
+        if size >= RATIO_MIN_SIZE && ratio > limits.max_compression_ratio {
+            return Err(FlowError::CompressionRatioTooHigh {
+                path: path.to_path_buf(),
+                entry: name.to_string(),
+                ratio,
+                limit: limits.max_compression_ratio,
+            });
+        }
+        total = total.saturating_add(size);
+        if total > limits.max_total_size {
+            return Err(FlowError::ArchiveTooLarge {
+                path: path.to_path_buf(),
+                size: total,
+                limit: limits.max_total_size,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Read the entry `name` of the archive at `path` from `reader`, failing
+/// once it decompresses to more than the entry size limit.
+pub fn read_entry<R: Read>(path: &Path, name: &str, reader: R) -> Result<Vec<u8>, FlowError> {
+    let limit = limits().max_entry_size;
+    let mut bytes = Vec::new();
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut bytes)
+        // Decompression errors and bad checksums surface as I/O errors here
+        .map_err(|e| FlowError::CorruptArchive {
+            path: path.to_path_buf(),
+            reason: format!("{}: {}", name, e),
+        })?;
+    if bytes.len() as u64 > limit {
+        return Err(FlowError::EntryTooLarge {
+            path: path.to_path_buf(),
+            entry: name.to_string(),
+            size: bytes.len() as u64,
+            limit,
+        });
+    }
+    Ok(bytes)
+}
+
+/// Check the nesting of the JSON entry `name` before it is parsed.
+pub fn check_nesting(path: &Path, name: &str, json: &str) -> Result<(), FlowError> {
+    let limit = limits().max_nesting;
+    let mut depth: u32 = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for byte in json.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > limit {
+                    return Err(FlowError::NestingTooDeep {
+                        path: path.to_path_buf(),
+                        entry: name.to_string(),
+                        limit,
+                    });
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether an entry name stays inside the directory it is extracted to.
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('/')
+        && !name.contains('\\')
+        && !name.contains('\0')
+        && !name
+            .split('/')
+            .next()
+            .is_some_and(|first| first.ends_with(':') || is_drive_relative(first))
+        && name.split('/').all(|part| part != "..")
+}
+
+/// Whether `part` starts with a Windows drive, as in `C:file`, which
+/// resolves against that drive's current directory when joined.
+fn is_drive_relative(part: &str) -> bool {
+    let bytes = part.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Number of entries recorded in the end of central directory, `None` if
+/// there is no such record.
+fn declared_entries<R: Read + Seek>(reader: &mut R) -> io::Result<Option<u64>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = len.min((EOCD_LEN + MAX_COMMENT_LEN) as u64);
+    reader.seek(SeekFrom::Start(len - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+
+    let Some(eocd) = tail
+        .windows(EOCD.len())
+        .rposition(|window| window == EOCD)
+        .filter(|&pos| pos + EOCD_LEN <= tail.len())
+    else {
+        return Ok(None);
+    };
+    let count = u16::from_le_bytes([tail[eocd + 10], tail[eocd + 11]]);
+    if count != u16::MAX || eocd < ZIP64_LOCATOR_LEN {
+        return Ok(Some(count as u64));
+    }
+
+    // ZIP64: the locator right before the record points at the real count
+    let locator = &tail[eocd - ZIP64_LOCATOR_LEN..eocd];
+    if &locator[..4] != ZIP64_LOCATOR {
+        return Ok(Some(count as u64));
+    }
+    let mut offset = [0u8; 8];
+    offset.copy_from_slice(&locator[8..16]);
+    let offset = u64::from_le_bytes(offset);
+    if offset.saturating_add(40) > len {
+        return Ok(Some(count as u64));
+    }
+    let mut record = [0u8; 40];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut record)?;
+    if &record[..4] != ZIP64_EOCD {
+        return Ok(Some(count as u64));
+    }
+    let mut count = [0u8; 8];
+    count.copy_from_slice(&record[32..40]);
+    Ok(Some(u64::from_le_bytes(count)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+    const MIB: u64 = 1024 * 1024;
+
+    /// Archive whose central directory declares `sizes` (uncompressed,
+    /// compressed) for its entries, whatever they actually hold.
+    fn declaring(sizes: &[(u64, u64)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for index in 0..sizes.len() {
+            zip.start_file(format!("assets/files/{}", index), options)
+                .unwrap();
+            zip.write_all(b"data").unwrap();
+        }
+        let mut bytes = zip.finish().unwrap().into_inner();
+        let headers: Vec<usize> = (0..bytes.len() - 4)
+            .filter(|&pos| &bytes[pos..pos + 4] == b"PK\x01\x02")
+            .collect();
+        for (&header, &(size, compressed)) in headers.iter().zip(sizes) {
+            bytes[header + 20..header + 24].copy_from_slice(&(compressed as u32).to_le_bytes());
+            bytes[header + 24..header + 28].copy_from_slice(&(size as u32).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn check(bytes: Vec<u8>) -> Result<(), FlowError> {
+        let path = Path::new("test.flm");
+        let mut reader = Cursor::new(bytes);
+        check_directory(path, &mut reader)?;
+        let mut archive = ZipArchive::new(reader).map_err(|e| FlowError::zip(path, e))?;
+        check_archive(path, &mut archive)
+    }
+
+    #[test]
+    fn unsafe_names() {
+        for name in [
+            "",
+            "../escape",
+            "assets/../../escape",
+            "..",
+            "/etc/passwd",
+            "C:/Windows/evil",
+            "C:evil",
+            "assets\\..\\evil",
+            "nul\0byte",
+        ] {
+            assert!(!is_safe_name(name), "{:?}", name);
+        }
+        for name in [
+            "document.json",
+            "assets/images/a.png",
+            "history/..json",
+            "a..b/c",
+        ] {
+            assert!(is_safe_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn unsafe_entry_name_is_rejected() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("../escape", FileOptions::default()).unwrap();
+        let bytes = zip.finish().unwrap().into_inner();
+        assert!(
+            matches!(check(bytes), Err(FlowError::UnsafeEntryName { entry, .. }) if entry == "../escape")
+        );
+    }
+
+    #[test]
+    fn declared_entry_count() {
+        let with_count = |count: u16| {
+            let mut bytes = declaring(&[(4, 4)]);
+            let eocd = bytes.len() - EOCD_LEN;
+            assert_eq!(&bytes[eocd..eocd + 4], EOCD);
+            bytes[eocd + 8..eocd + 10].copy_from_slice(&count.to_le_bytes());
+            bytes[eocd + 10..eocd + 12].copy_from_slice(&count.to_le_bytes());
+            bytes
+        };
+        let limit = DEFAULT_LIMITS.max_entries as u16;
+        let path = Path::new("test.flm");
+        assert!(check_directory(path, &mut Cursor::new(with_count(limit))).is_ok());
+        let result = check_directory(path, &mut Cursor::new(with_count(limit + 1)));
+        assert!(
+            matches!(result, Err(FlowError::TooManyEntries { count, .. }) if count == limit as u64 + 1)
+        );
+        // Not a ZIP at all is left to the ZIP reader
+        assert_eq!(
+            declared_entries(&mut Cursor::new(b"text".to_vec())).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn entry_size() {
+        let max = DEFAULT_LIMITS.max_entry_size;
+        assert!(check(declaring(&[(max, max)])).is_ok());
+        let result = check(declaring(&[(max + 1, max + 1)]));
+        assert!(matches!(result, Err(FlowError::EntryTooLarge { size, .. }) if size == max + 1));
+    }
+
+    #[test]
+    fn total_size() {
+        let max = DEFAULT_LIMITS.max_entry_size;
+        let count = (DEFAULT_LIMITS.max_total_size / max) as usize;
+        assert!(check(declaring(&vec![(max, max); count])).is_ok());
+        let result = check(declaring(&vec![(max, max); count + 1]));
+        assert!(matches!(result, Err(FlowError::ArchiveTooLarge { .. })));
+    }
+
+    #[test]
+    fn compression_ratio() {
+        let limit = DEFAULT_LIMITS.max_compression_ratio;
+        let compressed = 20_000;
+        // Integer ratios: just below the next one is still at the limit
+        let at_limit = limit * compressed + compressed - 1;
+        assert!(at_limit >= RATIO_MIN_SIZE);
+        assert!(check(declaring(&[(at_limit, compressed)])).is_ok());
+        let result = check(declaring(&[((limit + 1) * compressed, compressed)]));
+        assert!(
+            matches!(result, Err(FlowError::CompressionRatioTooHigh { ratio, .. }) if ratio == limit + 1)
+        );
+        // Small entries may compress as well as they like
+        assert!(check(declaring(&[(RATIO_MIN_SIZE - 1, 1)])).is_ok());
+        assert!(check(declaring(&[(MIB, 1)])).is_err());
+    }
+
+    #[test]
+    fn read_entry_is_capped() {
+        let path = Path::new("test.flm");
+        assert_eq!(read_entry(path, "a", &b"data"[..]).unwrap(), b"data");
+        let max = DEFAULT_LIMITS.max_entry_size;
+        let result = read_entry(path, "a", io::repeat(0).take(max + 10));
+        assert!(matches!(result, Err(FlowError::EntryTooLarge { size, .. }) if size == max + 1));
+    }
+
+    #[test]
+    fn nesting() {
+        let path = Path::new("test.flm");
+        let depth = DEFAULT_LIMITS.max_nesting as usize;
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(check_nesting(path, "document.json", &nested(depth)).is_ok());
+        let result = check_nesting(path, "document.json", &nested(depth + 1));
+        assert!(matches!(result, Err(FlowError::NestingTooDeep { .. })));
+        // Siblings don't add up, and brackets in strings don't count
+        let siblings = format!("[{},{}]", nested(depth - 1), nested(depth - 1));
+        assert!(check_nesting(path, "document.json", &siblings).is_ok());
+        let quoted = format!(
+            r#"{}"\"{}"{}"#,
+            "[".repeat(depth),
+            "[".repeat(depth),
+            "]".repeat(depth)
+        );
+        assert!(check_nesting(path, "document.json", &quoted).is_ok());
+    }
+}
@@ -0,0 +1,58 @@
+//! `manifest.json`: archive-level description of a .flm file.
+//!
+//! Lets readers check the format and version of an archive before parsing
+//! the document itself. Archives written before the manifest existed are
+//! still read; the document's own `version` field is used instead.
+
+use serde::{Deserialize, Serialize};
+
+use crate::document::{FlowmarkDocument, FORMAT};
+use crate::error::FlowError;
+use crate::flm::DOCUMENT_ENTRY;
+use crate::migrate;
+
+pub const MANIFEST_ENTRY: &str = "manifest.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub format: String,
+    pub version: String,
+    pub schema_version: String,
+    /// Entry holding the document content.
+    pub document: String,
+    /// Application that wrote the archive.
+    pub generator: String,
+}
+
+impl Manifest {
+    pub fn for_document(doc: &FlowmarkDocument) -> Self {
+        Manifest {
+            format: FORMAT.to_string(),
+            version: doc.version.clone(),
+            schema_version: doc.content.schema_version.clone(),
+            document: DOCUMENT_ENTRY.to_string(),
+            generator: format!("FlowMarker {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, FlowError> {
+        serde_json::from_str(json).map_err(|e| FlowError::json(MANIFEST_ENTRY, e))
+    }
+
+    pub fn to_json(&self) -> Result<String, FlowError> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| FlowError::other(format!("Failed to serialize manifest: {}", e)))
+    }
+
+    /// Refuse archives that are not FlowMark or come from a newer FlowMarker.
+    pub fn check(&self) -> Result<(), FlowError> {
+        if self.format != FORMAT {
+            return Err(FlowError::WrongFormat {
+                reason: "Invalid format: expected 'flowmark'".to_string(),
+            });
+        }
+        migrate::check_supported(&self.version, Some(&self.schema_version))?;
+        Ok(())
+    }
+}
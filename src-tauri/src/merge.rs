@@ -0,0 +1,513 @@
+//! Three-way merge of FlowMark documents.
+//!
+//! Top-level blocks are merged like lines in diff3: stretches changed on
+//! one side only take that side, identical changes are taken once, and
+//! stretches changed differently on both sides become a conflict. A
+//! conflict is written into the document between marker paragraphs
+//!
+//! ```text
+//! <<<<<<< ours
+//! …our blocks…
+//! =======
+//! …their blocks…
+//! >>>>>>> theirs
+//! ```
+//!
+//! so it shows up in the editor and can be resolved by deleting what isn't
+//! wanted. Notes are merged by `noteId` the same way, with conflicting note
+//! content marked up in the note text.
+
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use crate::assets;
+use crate::crypto;
+use crate::diff::lcs;
+use crate::document::{FlowmarkDocument, Node, NodeKind, Note};
+use crate::error::FlowError;
+use crate::flm;
+use crate::history;
+
+pub const OURS_MARKER: &str = "<<<<<<< ours";
+pub const SEPARATOR: &str = "=======";
+pub const THEIRS_MARKER: &str = ">>>>>>> theirs";
+
+/// Blocks changed differently on both sides.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockConflict {
+    /// Index of the opening marker among the merged document's blocks.
+    pub index: usize,
+    pub base: Vec<Node>,
+    pub ours: Vec<Node>,
+    pub theirs: Vec<Node>,
+}
+
+/// A note changed differently on both sides, or changed on one side and
+/// deleted on the other. `None` means the note doesn't exist on that side.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteConflict {
+    pub note_id: String,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub document: FlowmarkDocument,
+    pub conflicts: Vec<BlockConflict>,
+    pub note_conflicts: Vec<NoteConflict>,
+    /// Whether a common ancestor was available. Without one, the blocks
+    /// both sides share stand in for it: blocks only one side has are kept
+    /// and differing stretches between shared blocks are conflicts.
+    pub has_base: bool,
+}
+
+impl MergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty() && self.note_conflicts.is_empty()
+    }
+}
+
+/// Merge the .flm files at `ours` and `theirs`.
+///
+/// The common ancestor is read from `base` when given, and otherwise looked
+/// up in the revision history the two archives share. With `output` the
+/// result is written there, along with the assets it references from
+/// either archive.
+pub fn merge_files(
+    ours: &Path,
+    theirs: &Path,
+    base: Option<&Path>,
+    output: Option<&Path>,
+) -> Result<MergeResult, FlowError> {
+    let ours_doc = flm::read_document(ours)?;
+    let theirs_doc = flm::read_document(theirs)?;
+    let base_doc = match base {
+        Some(base) => Some(flm::read_document(base)?),
+        None => common_ancestor(ours, &ours_doc, theirs, &theirs_doc)?,
+    };
+    let result = merge(base_doc.as_ref(), &ours_doc, &theirs_doc);
+
+    if let Some(output) = output {
+        let present: HashSet<String> = assets::list_assets(output)
+            .map(|list| list.into_iter().map(|asset| asset.name).collect())
+            .unwrap_or_default();
+        let mut entries = Vec::new();
+        for name in assets::referenced(&result.document) {
+            if present.contains(&name) {
+                continue;
+            }
+            let bytes =
+                assets::read_asset(ours, &name).or_else(|_| assets::read_asset(theirs, &name))?;
+            entries.push((name, bytes));
+        }
+        // The result is as sensitive as either side
+        crypto::carry_key(ours, output)?;
+        crypto::carry_key(theirs, output)?;
+        flm::write_document_with(output, &result.document, entries)?;
+    }
+    Ok(result)
+}
+
+/// Merge `ours` and `theirs`, which both derive from `base`.
+pub fn merge(
+    base: Option<&FlowmarkDocument>,
+    ours: &FlowmarkDocument,
+    theirs: &FlowmarkDocument,
+) -> MergeResult {
+    let base_blocks = match base {
+        Some(base) => base.content.doc.content.clone(),
+        None => lcs(&ours.content.doc.content, &theirs.content.doc.content)
+            .into_iter()
+            .map(|(i, _)| ours.content.doc.content[i].clone())
+            .collect(),
+    };
+    let (blocks, conflicts) = merge_blocks(
+        &base_blocks,
+        &ours.content.doc.content,
+        &theirs.content.doc.content,
+    );
+
+    let mut document = ours.clone();
+    document.content.doc.content = blocks;
+    document.created_at = ours.created_at.clone().min(theirs.created_at.clone());
+    document.updated_at = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    let (notes, note_conflicts) = merge_notes(base.map(|b| &b.notes[..]), ours, theirs, &document);
+    document.notes = notes;
+    document.renumber_notes();
+
+    MergeResult {
+        document,
+        conflicts,
+        note_conflicts,
+        has_base: base.is_some(),
+    }
+}
+
+fn merge_blocks(base: &[Node], ours: &[Node], theirs: &[Node]) -> (Vec<Node>, Vec<BlockConflict>) {
+    let to_ours: HashMap<usize, usize> = lcs(base, ours).into_iter().collect();
+    let to_theirs: HashMap<usize, usize> = lcs(base, theirs).into_iter().collect();
+
+    let mut merged = Vec::new();
+    let mut conflicts = Vec::new();
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    loop {
+        // Next base block kept unchanged by both sides
+        let stable =
+            (b..base.len()).find_map(|i| Some((i, *to_ours.get(&i)?, *to_theirs.get(&i)?)));
+        let (b_end, o_end, t_end) = stable.unwrap_or((base.len(), ours.len(), theirs.len()));
+
+        let (base_chunk, ours_chunk, theirs_chunk) =
+            (&base[b..b_end], &ours[o..o_end], &theirs[t..t_end]);
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            merged.extend_from_slice(theirs_chunk);
+        } else if theirs_chunk == base_chunk {
+            merged.extend_from_slice(ours_chunk);
+        } else {
+            conflicts.push(BlockConflict {
+                index: merged.len(),
+                base: base_chunk.to_vec(),
+                ours: ours_chunk.to_vec(),
+                theirs: theirs_chunk.to_vec(),
+            });
+            merged.push(marker(OURS_MARKER));
+            merged.extend_from_slice(ours_chunk);
+            merged.push(marker(SEPARATOR));
+            merged.extend_from_slice(theirs_chunk);
+            merged.push(marker(THEIRS_MARKER));
+        }
+
+        match stable {
+            Some((b_end, o_end, t_end)) => {
+                merged.push(base[b_end].clone());
+                (b, o, t) = (b_end + 1, o_end + 1, t_end + 1);
+            }
+            None => break,
+        }
+    }
+    (merged, conflicts)
+}
+
+fn merge_notes(
+    base: Option<&[Note]>,
+    ours: &FlowmarkDocument,
+    theirs: &FlowmarkDocument,
+    merged: &FlowmarkDocument,
+) -> (Vec<Note>, Vec<NoteConflict>) {
+    let base = base.unwrap_or_default();
+    let content = |notes: &[Note], id: &str| {
+        notes
+            .iter()
+            .find(|note| note.note_id == id)
+            .map(|note| note.content.clone())
+    };
+    let referenced: HashSet<&str> = merged.content.doc.note_refs().into_iter().collect();
+
+    // Our notes first, then notes only they or the base have
+    let mut ids = Vec::new();
+    let mut seen = BTreeSet::new();
+    for note in ours.notes.iter().chain(&theirs.notes).chain(base) {
+        if seen.insert(note.note_id.as_str()) {
+            ids.push(note.note_id.as_str());
+        }
+    }
+
+    let mut notes = Vec::new();
+    let mut conflicts = Vec::new();
+    for id in ids {
+        let b = content(base, id);
+        let o = content(&ours.notes, id);
+        let t = content(&theirs.notes, id);
+        let resolved = if o == t || o == b {
+            t.clone()
+        } else if t == b {
+            o.clone()
+        } else {
+            conflicts.push(NoteConflict {
+                note_id: id.to_string(),
+                base: b.clone(),
+                ours: o.clone(),
+                theirs: t.clone(),
+            });
+            match (&o, &t) {
+                (Some(o), Some(t)) => Some(format!(
+                    "{}\n{}\n{}\n{}\n{}",
+                    OURS_MARKER, o, SEPARATOR, t, THEIRS_MARKER
+                )),
+                // Changed on one side, deleted on the other: keep the change
+                _ => o.clone().or_else(|| t.clone()),
+            }
+        };
+        // A note deleted on one side may still be referenced from blocks
+        // the other side changed
+        let resolved =
+            resolved.or_else(|| referenced.contains(id).then(|| o.or(t).or(b)).flatten());
+        if let Some(content) = resolved {
+            notes.push(Note {
+                note_id: id.to_string(),
+                content,
+                number: 0,
+            });
+        }
+    }
+    (notes, conflicts)
+}
+
+/// The newest revision both archives have in their history, or either
+/// document if the other one's history contains it.
+fn common_ancestor(
+    ours_path: &Path,
+    ours: &FlowmarkDocument,
+    theirs_path: &Path,
+    theirs: &FlowmarkDocument,
+) -> Result<Option<FlowmarkDocument>, FlowError> {
+    let same =
+        |a: &FlowmarkDocument, b: &FlowmarkDocument| a.content == b.content && a.notes == b.notes;
+    let ours_ids: Vec<String> = history::list(ours_path)?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+    let theirs_ids: HashSet<String> = history::list(theirs_path)?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
+
+    // Revisions are listed newest first; a copy made by a sync tool keeps
+    // the ids of the revisions saved before the split
+    for id in ours_ids.iter().filter(|id| theirs_ids.contains(*id)) {
+        let (Ok(a), Ok(b)) = (history::read(ours_path, id), history::read(theirs_path, id)) else {
+            continue;
+        };
+        if same(&a, &b) {
+            return Ok(Some(a));
+        }
+    }
+    // One side may simply be an older state of the other
+    for id in &ours_ids {
+        if let Ok(revision) = history::read(ours_path, id) {
+            if same(&revision, theirs) {
+                return Ok(Some(revision));
+            }
+        }
+    }
+    for id in &theirs_ids {
+        if let Ok(revision) = history::read(theirs_path, id) {
+            if same(&revision, ours) {
+                return Ok(Some(revision));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn marker(text: &str) -> Node {
+    Node::new(NodeKind::Paragraph).with_content(vec![Node::text(text)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(text: &str) -> Node {
+        Node::new(NodeKind::Paragraph).with_content(vec![Node::text(text)])
+    }
+
+    fn with_ref(text: &str, id: &str) -> Node {
+        Node::new(NodeKind::Paragraph).with_content(vec![
+            Node::text(text),
+            Node::new(NodeKind::NoteRef).with_attr("noteId", id),
+        ])
+    }
+
+    fn note(id: &str, content: &str) -> Note {
+        Note {
+            note_id: id.to_string(),
+            content: content.to_string(),
+            number: 1,
+        }
+    }
+
+    fn doc(blocks: Vec<Node>, notes: Vec<Note>) -> FlowmarkDocument {
+        FlowmarkDocument::new(Node::new(NodeKind::Doc).with_content(blocks), notes)
+    }
+
+    fn texts(doc: &FlowmarkDocument) -> Vec<String> {
+        doc.content
+            .doc
+            .content
+            .iter()
+            .map(Node::text_content)
+            .collect()
+    }
+
+    #[test]
+    fn one_sided_changes_are_taken() {
+        let base = doc(vec![p("a"), p("b"), p("c")], vec![]);
+        let ours = doc(vec![p("a"), p("B"), p("c")], vec![]);
+        let theirs = doc(vec![p("a"), p("b"), p("c"), p("d")], vec![]);
+        let result = merge(Some(&base), &ours, &theirs);
+        assert!(result.is_clean());
+        assert_eq!(texts(&result.document), ["a", "B", "c", "d"]);
+
+        // Deleting on one side is a change too
+        let theirs = doc(vec![p("a"), p("c")], vec![]);
+        let result = merge(Some(&base), &ours, &theirs);
+        assert!(!result.is_clean());
+        let ours = doc(vec![p("a"), p("b"), p("c"), p("d")], vec![]);
+        let result = merge(Some(&base), &ours, &theirs);
+        assert!(result.is_clean());
+        assert_eq!(texts(&result.document), ["a", "c", "d"]);
+    }
+
+    #[test]
+    fn identical_changes_are_taken_once() {
+        let base = doc(vec![p("a"), p("b")], vec![]);
+        let ours = doc(vec![p("a"), p("X"), p("Y")], vec![]);
+        let result = merge(Some(&base), &ours, &ours.clone());
+        assert!(result.is_clean());
+        assert_eq!(texts(&result.document), ["a", "X", "Y"]);
+    }
+
+    #[test]
+    fn conflicts_are_marked_where_they_are() {
+        let base = doc(vec![p("a"), p("b"), p("c"), p("d"), p("e")], vec![]);
+        let ours = doc(vec![p("a"), p("B1"), p("c"), p("D1"), p("e")], vec![]);
+        let theirs = doc(
+            vec![p("a"), p("B2"), p("c"), p("D2"), p("D3"), p("e")],
+            vec![],
+        );
+        let result = merge(Some(&base), &ours, &theirs);
+
+        assert_eq!(
+            texts(&result.document),
+            [
+                "a",
+                OURS_MARKER,
+                "B1",
+                SEPARATOR,
+                "B2",
+                THEIRS_MARKER,
+                "c",
+                OURS_MARKER,
+                "D1",
+                SEPARATOR,
+                "D2",
+                "D3",
+                THEIRS_MARKER,
+                "e"
+            ]
+        );
+        let indices: Vec<usize> = result.conflicts.iter().map(|c| c.index).collect();
+        assert_eq!(indices, [1, 7]);
+        for conflict in &result.conflicts {
+            assert_eq!(
+                result.document.content.doc.content[conflict.index].text_content(),
+                OURS_MARKER
+            );
+        }
+        let second = &result.conflicts[1];
+        assert_eq!(
+            (second.base.clone(), second.ours.clone()),
+            (vec![p("d")], vec![p("D1")])
+        );
+        assert_eq!(second.theirs, vec![p("D2"), p("D3")]);
+    }
+
+    #[test]
+    fn note_changes_merge_by_id() {
+        let base = doc(
+            vec![with_ref("a", "n1"), with_ref("b", "n2")],
+            vec![note("n1", "one"), note("n2", "two")],
+        );
+        let ours = doc(
+            base.content.doc.content.clone(),
+            vec![note("n1", "ONE"), note("n2", "two")],
+        );
+        let theirs = doc(
+            base.content.doc.content.clone(),
+            vec![note("n1", "one"), note("n2", "TWO")],
+        );
+        let result = merge(Some(&base), &ours, &theirs);
+        assert!(result.is_clean());
+        let contents: Vec<&str> = result
+            .document
+            .notes
+            .iter()
+            .map(|n| n.content.as_str())
+            .collect();
+        assert_eq!(contents, ["ONE", "TWO"]);
+
+        let theirs = doc(
+            base.content.doc.content.clone(),
+            vec![note("n1", "uno"), note("n2", "two")],
+        );
+        let result = merge(Some(&base), &ours, &theirs);
+        assert_eq!(result.note_conflicts.len(), 1);
+        assert_eq!(
+            result.document.notes[0].content,
+            format!(
+                "{}\nONE\n{}\nuno\n{}",
+                OURS_MARKER, SEPARATOR, THEIRS_MARKER
+            )
+        );
+    }
+
+    #[test]
+    fn note_deleted_on_one_side_and_edited_on_the_other_is_kept() {
+        let base = doc(vec![with_ref("a", "n1"), p("b")], vec![note("n1", "old")]);
+        let ours = doc(base.content.doc.content.clone(), vec![note("n1", "new")]);
+        let theirs = doc(vec![p("a"), p("b")], vec![]);
+        let result = merge(Some(&base), &ours, &theirs);
+
+        assert!(result.conflicts.is_empty());
+        let conflict = &result.note_conflicts[0];
+        assert_eq!(conflict.note_id, "n1");
+        assert_eq!(
+            (
+                conflict.base.as_deref(),
+                conflict.ours.as_deref(),
+                conflict.theirs.as_deref()
+            ),
+            (Some("old"), Some("new"), None)
+        );
+        assert_eq!(result.document.notes, vec![note("n1", "new")]);
+
+        // Deleted on one side and untouched on the other: gone
+        let ours = doc(base.content.doc.content.clone(), vec![note("n1", "old")]);
+        let result = merge(Some(&base), &ours, &theirs);
+        assert!(result.is_clean());
+        assert!(result.document.notes.is_empty());
+    }
+
+    #[test]
+    fn deleted_note_still_referenced_is_kept() {
+        let base = doc(vec![with_ref("a", "n1"), p("b")], vec![note("n1", "x")]);
+        // They drop the note but keep its reference in a block they changed
+        let ours = doc(vec![with_ref("a", "n1"), p("b")], vec![note("n1", "x")]);
+        let theirs = doc(vec![with_ref("a", "n1"), p("B")], vec![]);
+        let result = merge(Some(&base), &ours, &theirs);
+        assert!(result.is_clean());
+        assert_eq!(result.document.notes, vec![note("n1", "x")]);
+    }
+
+    #[test]
+    fn without_a_base_shared_blocks_stand_in_for_it() {
+        let ours = doc(vec![p("a"), p("b"), p("x")], vec![]);
+        let theirs = doc(vec![p("a"), p("y"), p("b")], vec![]);
+        let result = merge(None, &ours, &theirs);
+        assert!(!result.has_base);
+        assert!(result.is_clean());
+        assert_eq!(texts(&result.document), ["a", "y", "b", "x"]);
+
+        let theirs = doc(vec![p("a"), p("y"), p("b"), p("z")], vec![]);
+        let result = merge(None, &ours, &theirs);
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].index, 3);
+        assert!(result.conflicts[0].base.is_empty());
+    }
+}
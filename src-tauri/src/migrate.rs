@@ -0,0 +1,325 @@
+//! Format versioning and step-by-step upgrades of `document.json`.
+//!
+//! Migrations run on the raw JSON before it is parsed into the typed model,
+//! so they can handle documents that no longer match it. Each step upgrades
+//! from exactly one format version to the next.
+
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+use crate::document::{CONTENT_TYPE, FORMAT_VERSION, SCHEMA_VERSION};
+
+/// `major.minor` format version as written in the `version` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FormatVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FormatVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        FormatVersion { major, minor }
+    }
+
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.trim().splitn(2, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = match parts.next() {
+            Some(minor) => minor.parse().ok()?,
+            None => 0,
+        };
+        Some(FormatVersion { major, minor })
+    }
+
+    pub fn current() -> Self {
+        FormatVersion::parse(FORMAT_VERSION).expect("FORMAT_VERSION is valid")
+    }
+}
+
+impl fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationError {
+    /// The document was written by a newer FlowMarker.
+    UnsupportedVersion { found: String, supported: String },
+    /// The editor schema of the document is newer than this build knows.
+    UnsupportedSchema { found: String, supported: String },
+    /// The version field cannot be parsed.
+    InvalidVersion(String),
+    /// A migration step could not upgrade the document.
+    Failed { from: String, to: String, reason: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "This document uses FlowMark format {}, but this version of FlowMarker only supports up to {}. Please update FlowMarker to open it.",
+                found, supported
+            ),
+            MigrationError::UnsupportedSchema { found, supported } => write!(
+                f,
+                "This document uses editor schema {}, but this version of FlowMarker only supports up to {}. Please update FlowMarker to open it.",
+                found, supported
+            ),
+            MigrationError::InvalidVersion(version) => {
+                write!(f, "Invalid FlowMark version '{}'", version)
+            }
+            MigrationError::Failed { from, to, reason } => write!(
+                f,
+                "Failed to upgrade document from format {} to {}: {}",
+                from, to, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+struct Migration {
+    from: FormatVersion,
+    to: FormatVersion,
+    apply: fn(&mut Map<String, Value>) -> Result<(), String>,
+}
+
+/// Ordered upgrade steps; the last `to` must be the current format version.
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: FormatVersion::new(0, 0),
+    to: FormatVersion::new(0, 1),
+    apply: unversioned_to_0_1,
+}];
+
+/// Refuse versions this build cannot read.
+pub fn check_supported(version: &str, schema_version: Option<&str>) -> Result<(), MigrationError> {
+    let found = FormatVersion::parse(version)
+        .ok_or_else(|| MigrationError::InvalidVersion(version.to_string()))?;
+    if found > FormatVersion::current() {
+        return Err(MigrationError::UnsupportedVersion {
+            found: version.to_string(),
+            supported: FORMAT_VERSION.to_string(),
+        });
+    }
+    if let Some(schema_version) = schema_version {
+        let supported: u32 = SCHEMA_VERSION.parse().expect("SCHEMA_VERSION is valid");
+        let found: u32 = schema_version
+            .parse()
+            .map_err(|_| MigrationError::InvalidVersion(schema_version.to_string()))?;
+        if found > supported {
+            return Err(MigrationError::UnsupportedSchema {
+                found: schema_version.to_string(),
+                supported: SCHEMA_VERSION.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Upgrade a raw `document.json` value to the current format version.
+///
+/// Returns the versions that were migrated from, oldest first.
+pub fn upgrade(value: &mut Value) -> Result<Vec<FormatVersion>, MigrationError> {
+    let obj = value
+        .as_object_mut()
+        .ok_or_else(|| MigrationError::InvalidVersion("document is not an object".to_string()))?;
+
+    // Documents from before versioning carry no version field
+    let version = obj
+        .get("version")
+        .and_then(|v| v.as_str())
+        .unwrap_or("0.0")
+        .to_string();
+    let schema_version = obj
+        .get("content")
+        .and_then(|c| c.get("schemaVersion"))
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    check_supported(&version, schema_version.as_deref())?;
+
+    let mut current = FormatVersion::parse(&version)
+        .ok_or_else(|| MigrationError::InvalidVersion(version.clone()))?;
+    let mut applied = Vec::new();
+
+    for migration in MIGRATIONS {
+        if migration.from != current {
+            continue;
+        }
+        (migration.apply)(obj).map_err(|reason| MigrationError::Failed {
+            from: migration.from.to_string(),
+            to: migration.to.to_string(),
+            reason,
+        })?;
+        obj.insert("version".to_string(), json!(migration.to.to_string()));
+        applied.push(current);
+        current = migration.to;
+    }
+
+    if current != FormatVersion::current() {
+        return Err(MigrationError::Failed {
+            from: current.to_string(),
+            to: FORMAT_VERSION.to_string(),
+            reason: "no migration path".to_string(),
+        });
+    }
+
+    Ok(applied)
+}
+
+/// Documents written before `version` and `content.schemaVersion` existed.
+///
+/// These stored the ProseMirror doc directly under `content` and may lack
+/// timestamps or note numbers.
+fn unversioned_to_0_1(obj: &mut Map<String, Value>) -> Result<(), String> {
+    obj.entry("format").or_insert_with(|| json!("flowmark"));
+
+    let content = obj
+        .remove("content")
+        .ok_or_else(|| "missing content".to_string())?;
+    let content = if content.get("type").and_then(|t| t.as_str()) == Some("doc") {
+        json!({ "type": CONTENT_TYPE, "schemaVersion": "1", "doc": content })
+    } else {
+        let mut content = content;
+        let wrapper = content
+            .as_object_mut()
+            .ok_or_else(|| "content is not an object".to_string())?;
+        wrapper.entry("type").or_insert_with(|| json!(CONTENT_TYPE));
+        wrapper.entry("schemaVersion").or_insert_with(|| json!("1"));
+        content
+    };
+    obj.insert("content".to_string(), content);
+
+    let now = chrono::Utc::now()
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    let updated_at = obj
+        .get("updatedAt")
+        .or_else(|| obj.get("createdAt"))
+        .cloned()
+        .unwrap_or_else(|| json!(now));
+    obj.entry("createdAt").or_insert_with(|| updated_at.clone());
+    obj.entry("updatedAt").or_insert(updated_at);
+
+    if let Some(notes) = obj.get_mut("notes").and_then(|n| n.as_array_mut()) {
+        for (index, note) in notes.iter_mut().enumerate() {
+            if let Some(note) = note.as_object_mut() {
+                note.entry("number").or_insert_with(|| json!(index + 1));
+                note.entry("content").or_insert_with(|| json!(""));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::FlowmarkDocument;
+
+    #[test]
+    fn version_parsing_and_order() {
+        assert_eq!(FormatVersion::parse("0.1"), Some(FormatVersion::new(0, 1)));
+        assert_eq!(FormatVersion::parse(" 2 "), Some(FormatVersion::new(2, 0)));
+        assert_eq!(FormatVersion::parse("1.x"), None);
+        assert_eq!(FormatVersion::parse(""), None);
+        assert!(FormatVersion::new(0, 10) > FormatVersion::new(0, 9));
+        assert!(FormatVersion::new(1, 0) > FormatVersion::new(0, 99));
+        assert_eq!(FormatVersion::new(1, 2).to_string(), "1.2");
+    }
+
+    #[test]
+    fn unversioned_documents_are_upgraded() {
+        let mut value = json!({
+            "createdAt": "2023-01-01T00:00:00.000Z",
+            "content": {"type": "doc", "content": [{"type": "paragraph"}]},
+            "notes": [{"noteId": "a"}, {"noteId": "b", "content": "b", "number": 7}],
+        });
+        assert_eq!(upgrade(&mut value).unwrap(), [FormatVersion::new(0, 0)]);
+
+        assert_eq!(value["version"], FORMAT_VERSION);
+        assert_eq!(value["format"], "flowmark");
+        assert_eq!(value["content"]["type"], CONTENT_TYPE);
+        assert_eq!(value["content"]["schemaVersion"], "1");
+        assert_eq!(value["content"]["doc"]["type"], "doc");
+        assert_eq!(value["updatedAt"], "2023-01-01T00:00:00.000Z");
+        assert_eq!(value["notes"][0]["number"], 1);
+        assert_eq!(value["notes"][0]["content"], "");
+        assert_eq!(value["notes"][1]["number"], 7);
+        let doc: FlowmarkDocument = serde_json::from_value(value).unwrap();
+        doc.validate().unwrap();
+    }
+
+    #[test]
+    fn unversioned_wrapped_content_keeps_its_fields() {
+        let mut value = json!({
+            "format": "flowmark",
+            "content": {"doc": {"type": "doc", "content": [{"type": "paragraph"}]}},
+        });
+        upgrade(&mut value).unwrap();
+        assert_eq!(value["content"]["type"], CONTENT_TYPE);
+        assert_eq!(value["content"]["doc"]["content"][0]["type"], "paragraph");
+        assert!(value["createdAt"].is_string());
+
+        let mut broken = json!({"content": "text"});
+        assert!(matches!(
+            upgrade(&mut broken),
+            Err(MigrationError::Failed { .. })
+        ));
+        let mut missing = json!({"notes": []});
+        assert!(matches!(
+            upgrade(&mut missing),
+            Err(MigrationError::Failed { .. })
+        ));
+    }
+
+    #[test]
+    fn current_documents_are_left_alone() {
+        let mut value = json!({
+            "format": "flowmark",
+            "version": FORMAT_VERSION,
+            "createdAt": "2024-01-01T00:00:00.000Z",
+            "updatedAt": "2024-01-02T00:00:00.000Z",
+            "content": {"type": CONTENT_TYPE, "schemaVersion": SCHEMA_VERSION, "doc": {"type": "doc"}},
+        });
+        let before = value.clone();
+        assert!(upgrade(&mut value).unwrap().is_empty());
+        assert_eq!(value, before);
+    }
+
+    #[test]
+    fn newer_and_invalid_versions_are_refused() {
+        let mut newer = json!({"version": "99.0", "content": {}});
+        assert!(matches!(
+            upgrade(&mut newer),
+            Err(MigrationError::UnsupportedVersion { found, .. }) if found == "99.0"
+        ));
+        let mut newer_schema = json!({
+            "version": FORMAT_VERSION,
+            "content": {"type": CONTENT_TYPE, "schemaVersion": "99", "doc": {"type": "doc"}},
+        });
+        assert!(matches!(
+            upgrade(&mut newer_schema),
+            Err(MigrationError::UnsupportedSchema { .. })
+        ));
+        let mut invalid = json!({"version": "one", "content": {}});
+        assert_eq!(
+            upgrade(&mut invalid),
+            Err(MigrationError::InvalidVersion("one".to_string()))
+        );
+        assert!(upgrade(&mut json!([])).is_err());
+    }
+
+    #[test]
+    fn every_migration_leads_to_the_current_version() {
+        let mut version = MIGRATIONS[0].from;
+        for migration in MIGRATIONS {
+            assert_eq!(migration.from, version);
+            assert!(migration.to > migration.from);
+            version = migration.to;
+        }
+        assert_eq!(version, FormatVersion::current());
+    }
+}
@@ -0,0 +1,226 @@
+//! Consistency of the `notes` array with the `note_ref` nodes.
+//!
+//! The editor keeps both in step while a document is open, but files edited
+//! by hand or by other tools can reference notes that don't exist, carry
+//! notes nothing references, or number them out of order. Documents are
+//! reconciled when they are loaded and saved.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::document::{FlowmarkDocument, Node, NodeKind};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteReport {
+    /// Note ids referenced by `note_ref` nodes that have no note.
+    pub missing: Vec<String>,
+    /// Notes that no `note_ref` refers to.
+    pub orphaned: Vec<String>,
+    /// Note ids that occur more than once in `notes`.
+    pub duplicates: Vec<String>,
+    /// Whether the numbers didn't follow the order of the references.
+    pub renumbered: bool,
+    /// Whether the orphaned notes were removed.
+    pub orphans_removed: bool,
+}
+
+impl NoteReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty()
+            && self.orphaned.is_empty()
+            && self.duplicates.is_empty()
+            && !self.renumbered
+    }
+}
+
+/// Compare the notes of `doc` with its references, without changing it.
+pub fn check(doc: &FlowmarkDocument) -> NoteReport {
+    let refs = doc.content.doc.note_refs();
+    let referenced: HashSet<&str> = refs.iter().copied().collect();
+    let mut ids: HashSet<&str> = HashSet::new();
+    let mut report = NoteReport::default();
+    for note in &doc.notes {
+        let id = note.note_id.as_str();
+        if !ids.insert(id) {
+            if !report.duplicates.iter().any(|d| d == id) {
+                report.duplicates.push(id.to_string());
+            }
+        } else if !referenced.contains(id) {
+            report.orphaned.push(id.to_string());
+        }
+    }
+    for id in refs {
+        if !ids.contains(id) && !report.missing.iter().any(|m| m == id) {
+            report.missing.push(id.to_string());
+        }
+    }
+
+    let mut renumbered = doc.clone();
+    renumbered.renumber_notes();
+    report.renumbered = renumbered != *doc;
+    report
+}
+
+/// Make the notes of `doc` match its references: drop duplicate notes and
+/// references to missing notes, remove orphaned notes if asked to, and
+/// number everything by the first reference in document order.
+pub fn reconcile(doc: &mut FlowmarkDocument, remove_orphans: bool) -> NoteReport {
+    let mut report = check(doc);
+    if report.is_consistent() {
+        return report;
+    }
+
+    let mut ids: HashSet<String> = HashSet::new();
+    doc.notes.retain(|note| ids.insert(note.note_id.clone()));
+    if !report.missing.is_empty() {
+        drop_refs(&mut doc.content.doc, &ids);
+    }
+    if remove_orphans && !report.orphaned.is_empty() {
+        let orphaned: HashSet<&str> = report.orphaned.iter().map(String::as_str).collect();
+        doc.notes
+            .retain(|note| !orphaned.contains(note.note_id.as_str()));
+        report.orphans_removed = true;
+    }
+    doc.renumber_notes();
+    report
+}
+
+/// Remove `note_ref` nodes below `node` that don't refer to one of `ids`.
+fn drop_refs(node: &mut Node, ids: &HashSet<String>) {
+    node.content.retain(|child| {
+        child.kind != NodeKind::NoteRef
+            || child.attr_str("noteId").is_some_and(|id| ids.contains(id))
+    });
+    for child in &mut node.content {
+        drop_refs(child, ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Note;
+
+    fn note(id: &str, number: u32) -> Note {
+        Note {
+            note_id: id.to_string(),
+            content: format!("Note {}", id),
+            number,
+        }
+    }
+
+    fn note_ref(id: &str, number: u32) -> Node {
+        Node::new(NodeKind::NoteRef)
+            .with_attr("noteId", id)
+            .with_attr("number", number)
+    }
+
+    fn doc(refs: &[(&str, u32)], notes: Vec<Note>) -> FlowmarkDocument {
+        let blocks = refs
+            .iter()
+            .map(|&(id, number)| {
+                Node::new(NodeKind::Paragraph)
+                    .with_content(vec![Node::text("text"), note_ref(id, number)])
+            })
+            .collect();
+        FlowmarkDocument::new(Node::new(NodeKind::Doc).with_content(blocks), notes)
+    }
+
+    /// Note id and number of every reference, in document order.
+    fn ref_numbers(doc: &FlowmarkDocument) -> Vec<(&str, u64)> {
+        let mut refs = Vec::new();
+        doc.content.doc.walk(&mut |node| {
+            if node.kind == NodeKind::NoteRef {
+                let id = node.attr_str("noteId").unwrap_or_default();
+                refs.push((id, node.attr_u64("number").unwrap_or_default()));
+            }
+        });
+        refs
+    }
+
+    fn note_numbers(doc: &FlowmarkDocument) -> Vec<(&str, u32)> {
+        doc.notes
+            .iter()
+            .map(|note| (note.note_id.as_str(), note.number))
+            .collect()
+    }
+
+    #[test]
+    fn consistent_notes_are_left_alone() {
+        let mut consistent = doc(
+            &[("a", 1), ("b", 2), ("a", 1)],
+            vec![note("a", 1), note("b", 2)],
+        );
+        let before = consistent.clone();
+        let report = reconcile(&mut consistent, true);
+        assert!(report.is_consistent());
+        assert_eq!(report, NoteReport::default());
+        assert_eq!(consistent, before);
+    }
+
+    #[test]
+    fn notes_are_renumbered_in_document_order() {
+        let mut shuffled = doc(
+            &[("b", 2), ("a", 1), ("b", 2)],
+            vec![note("a", 1), note("b", 2)],
+        );
+        let report = check(&shuffled);
+        assert!(report.renumbered);
+        assert!(report.missing.is_empty() && report.orphaned.is_empty());
+
+        reconcile(&mut shuffled, false);
+        assert_eq!(ref_numbers(&shuffled), [("b", 1), ("a", 2), ("b", 1)]);
+        assert_eq!(note_numbers(&shuffled), [("b", 1), ("a", 2)]);
+        assert!(check(&shuffled).is_consistent());
+    }
+
+    #[test]
+    fn references_to_missing_notes_are_dropped() {
+        let mut dangling = doc(
+            &[("a", 1), ("gone", 2), ("b", 3), ("gone", 2)],
+            vec![note("a", 1), note("b", 3)],
+        );
+        let report = reconcile(&mut dangling, false);
+        assert_eq!(report.missing, ["gone"]);
+        assert_eq!(dangling.content.doc.note_refs(), ["a", "b"]);
+        // The paragraphs that held them stay
+        assert_eq!(dangling.content.doc.content.len(), 4);
+        assert_eq!(ref_numbers(&dangling), [("a", 1), ("b", 2)]);
+        assert_eq!(note_numbers(&dangling), [("a", 1), ("b", 2)]);
+    }
+
+    #[test]
+    fn orphaned_notes_are_only_removed_when_asked() {
+        let orphaned = doc(&[("a", 1)], vec![note("lonely", 1), note("a", 2)]);
+
+        let mut kept = orphaned.clone();
+        let report = reconcile(&mut kept, false);
+        assert_eq!(report.orphaned, ["lonely"]);
+        assert!(!report.orphans_removed);
+        // Unreferenced notes are numbered after the referenced ones
+        assert_eq!(note_numbers(&kept), [("a", 1), ("lonely", 2)]);
+
+        let mut removed = orphaned;
+        let report = reconcile(&mut removed, true);
+        assert!(report.orphans_removed);
+        assert_eq!(removed.notes, [note("a", 1)]);
+    }
+
+    #[test]
+    fn duplicate_notes_keep_the_first() {
+        let mut duplicated = doc(
+            &[("a", 1)],
+            vec![
+                note("a", 1),
+                Note {
+                    content: "second".to_string(),
+                    ..note("a", 1)
+                },
+            ],
+        );
+        let report = reconcile(&mut duplicated, false);
+        assert_eq!(report.duplicates, ["a"]);
+        assert_eq!(duplicated.notes, [note("a", 1)]);
+    }
+}
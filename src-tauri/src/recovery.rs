@@ -0,0 +1,223 @@
+//! Crash recovery journal kept in the app data directory.
+//!
+//! The frontend journals the open document, saved or not, while it is being
+//! edited. A `session.lock` marker exists for as long as the app runs; if it
+//! is still there on the next start the app did not shut down cleanly, and
+//! the journal left behind is moved to `recovered/` to be offered back.
+//!
+//! ```text
+//! recovery/
+//!   session.lock
+//!   journal/<id>.json
+//!   recovered/<id>.json
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::crypto;
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::export;
+use crate::flm;
+
+const LOCK_FILE: &str = "session.lock";
+const JOURNAL_DIR: &str = "journal";
+const RECOVERED_DIR: &str = "recovered";
+
+/// A journaled document as stored on disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    id: String,
+    /// The .flm file the document belongs to, if it has been saved before.
+    path: Option<String>,
+    saved_at: String,
+    /// Kept as raw JSON so older documents go through `from_json` upgrades.
+    document: serde_json::Value,
+}
+
+impl Entry {
+    fn document(&self) -> Result<FlowmarkDocument, FlowError> {
+        FlowmarkDocument::from_json(&self.document.to_string())
+    }
+}
+
+/// A document recovered from a session that did not shut down cleanly.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveredDocument {
+    pub id: String,
+    pub path: Option<String>,
+    pub title: Option<String>,
+    /// When the document was last journaled, as RFC 3339.
+    pub saved_at: String,
+}
+
+/// Recovery store rooted at a directory, shared by all commands.
+pub struct Journal {
+    dir: PathBuf,
+}
+
+impl Journal {
+    /// Open the store in `dir` and start a session.
+    ///
+    /// Documents journaled by a session that crashed are moved aside for
+    /// recovery, unless they match the file they were saved to.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, FlowError> {
+        let dir = dir.into();
+        let journal_dir = dir.join(JOURNAL_DIR);
+        let recovered_dir = dir.join(RECOVERED_DIR);
+        for dir in [&journal_dir, &recovered_dir] {
+            fs::create_dir_all(dir).map_err(|e| FlowError::io(dir, e))?;
+        }
+
+        let lock = dir.join(LOCK_FILE);
+        let unclean_shutdown = lock.exists();
+        for file in json_files(&journal_dir)? {
+            let entry = if unclean_shutdown {
+                read_entry(&file).ok()
+            } else {
+                None
+            };
+            match entry {
+                Some(entry) if !matches_saved_file(&entry) => {
+                    let target = recovered_dir.join(file.file_name().unwrap_or_default());
+                    fs::rename(&file, &target).map_err(|e| FlowError::io(&file, e))?;
+                }
+                _ => remove(&file),
+            }
+        }
+        if unclean_shutdown {
+            log::warn!("Previous session did not shut down cleanly");
+        }
+
+        let started_at = chrono::Utc::now().to_rfc3339();
+        atomic::write(&lock, started_at.as_bytes())?;
+        Ok(Journal { dir })
+    }
+
+    /// Journal the current state of the document `id`.
+    ///
+    /// Documents of encrypted files aren't journaled, since the journal is
+    /// stored in plain text.
+    pub fn record(
+        &self,
+        id: &str,
+        path: Option<String>,
+        document: &FlowmarkDocument,
+    ) -> Result<(), FlowError> {
+        if path.as_deref().is_some_and(crypto::is_encrypted) {
+            return self.discard(id);
+        }
+        let entry = Entry {
+            id: id.to_string(),
+            path,
+            saved_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            document: serde_json::to_value(document)
+                .map_err(|e| FlowError::other(format!("Failed to serialize document: {}", e)))?,
+        };
+        let json = serde_json::to_string(&entry)
+            .map_err(|e| FlowError::other(format!("Failed to serialize journal entry: {}", e)))?;
+        atomic::write(self.entry_path(JOURNAL_DIR, id)?, json.as_bytes())
+    }
+
+    /// Drop the journal of document `id`, e.g. once it was closed.
+    pub fn discard(&self, id: &str) -> Result<(), FlowError> {
+        remove(&self.entry_path(JOURNAL_DIR, id)?);
+        Ok(())
+    }
+
+    /// Documents recovered from crashed sessions, newest first.
+    pub fn recovered(&self) -> Result<Vec<RecoveredDocument>, FlowError> {
+        let mut documents = Vec::new();
+        for file in json_files(&self.dir.join(RECOVERED_DIR))? {
+            match read_entry(&file).and_then(|entry| Ok((entry.document()?, entry))) {
+                Ok((document, entry)) => documents.push(RecoveredDocument {
+                    title: export::html::document_title(&document),
+                    id: entry.id,
+                    path: entry.path,
+                    saved_at: entry.saved_at,
+                }),
+                Err(e) => log::warn!(
+                    "Skipping unreadable recovery file {}: {}",
+                    file.display(),
+                    e
+                ),
+            }
+        }
+        documents.sort_by(|a, b| b.saved_at.cmp(&a.saved_at));
+        Ok(documents)
+    }
+
+    /// Take the recovered document `id` out of the store.
+    pub fn restore(&self, id: &str) -> Result<FlowmarkDocument, FlowError> {
+        let file = self.entry_path(RECOVERED_DIR, id)?;
+        let document = read_entry(&file)?.document()?;
+        remove(&file);
+        Ok(document)
+    }
+
+    /// Throw away the recovered document `id`.
+    pub fn discard_recovered(&self, id: &str) -> Result<(), FlowError> {
+        remove(&self.entry_path(RECOVERED_DIR, id)?);
+        Ok(())
+    }
+
+    /// End the session cleanly: the journal is no longer needed.
+    pub fn close(&self) {
+        if let Ok(files) = json_files(&self.dir.join(JOURNAL_DIR)) {
+            files.iter().for_each(|file| remove(file));
+        }
+        remove(&self.dir.join(LOCK_FILE));
+    }
+
+    fn entry_path(&self, folder: &str, id: &str) -> Result<PathBuf, FlowError> {
+        // Ids become file names, so keep them to a safe alphabet
+        let valid = !id.is_empty()
+            && id.len() <= 64
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(FlowError::invalid(format!("Invalid recovery id: {}", id)));
+        }
+        Ok(self.dir.join(folder).join(format!("{}.json", id)))
+    }
+}
+
+fn read_entry(file: &Path) -> Result<Entry, FlowError> {
+    let json = fs::read_to_string(file).map_err(|e| FlowError::io(file, e))?;
+    serde_json::from_str(&json).map_err(|e| FlowError::json(file.display().to_string(), e))
+}
+
+/// Whether the journaled document is identical to the file it belongs to.
+fn matches_saved_file(entry: &Entry) -> bool {
+    let Some(path) = &entry.path else {
+        return false;
+    };
+    match (flm::read_document(path), entry.document()) {
+        (Ok(saved), Ok(journaled)) => {
+            saved.content == journaled.content && saved.notes == journaled.notes
+        }
+        _ => false,
+    }
+}
+
+fn json_files(dir: &Path) -> Result<Vec<PathBuf>, FlowError> {
+    let entries = fs::read_dir(dir).map_err(|e| FlowError::io(dir, e))?;
+    Ok(entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect())
+}
+
+fn remove(file: &Path) {
+    if let Err(e) = fs::remove_file(file) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Failed to remove {}: {}", file.display(), e);
+        }
+    }
+}
@@ -0,0 +1,669 @@
+//! Salvaging damaged .flm archives.
+//!
+//! A damaged archive usually can't be opened at all, since the central
+//! directory at its end is the first thing to go when a file is cut short.
+//! Instead the file is scanned for the local header in front of every
+//! entry, and each entry that decompresses with a matching checksum is
+//! kept. A truncated `document.json` is cut back to its last complete
+//! top-level block and closed; without one the newest readable revision
+//! from the history takes its place. What could be saved is written to a
+//! new archive, leaving the damaged file untouched.
+
+use flate2::read::DeflateDecoder;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::assets;
+use crate::crypto;
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::flm::{self, DOCUMENT_ENTRY};
+use crate::history::HISTORY_DIR;
+use crate::limits::{self, is_safe_name};
+use crate::manifest::MANIFEST_ENTRY;
+
+const LOCAL_HEADER: &[u8; 4] = b"PK\x03\x04";
+const LOCAL_HEADER_LEN: usize = 30;
+const DATA_DESCRIPTOR: &[u8; 4] = b"PK\x07\x08";
+/// General purpose flag: sizes and checksum follow the data.
+const FLAG_DATA_DESCRIPTOR: u16 = 0x08;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// Where the document of the salvaged copy comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DocumentSource {
+    /// `document.json` was undamaged.
+    Intact,
+    /// `document.json` was damaged and cut back to its complete blocks.
+    Partial,
+    /// `document.json` was lost; the newest readable revision is used.
+    Revision,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LostEntry {
+    pub name: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairReport {
+    /// The salvaged copy.
+    pub output: PathBuf,
+    pub document: DocumentSource,
+    /// Revision id, for [`DocumentSource::Revision`].
+    pub revision: Option<String>,
+    /// Top-level blocks and notes in the salvaged document.
+    pub blocks: usize,
+    pub notes: usize,
+    /// Bytes at the end of a partial `document.json` that were dropped, if
+    /// its original size is known.
+    pub lost_bytes: Option<u64>,
+    /// Entries copied to the salvaged archive.
+    pub recovered: Vec<String>,
+    /// Entries found damaged.
+    pub lost: Vec<LostEntry>,
+    /// Assets the document references that couldn't be recovered.
+    pub missing_assets: Vec<String>,
+}
+
+/// An entry found by scanning for local headers.
+struct Scanned {
+    data: Vec<u8>,
+    /// Size stored in the header, if it was there.
+    size: Option<u64>,
+    /// Why the data is incomplete or can't be trusted.
+    damage: Option<String>,
+}
+
+/// Salvage what can be read from the archive at `path` into `output`,
+/// which defaults to `<name>.repaired.flm` next to it.
+pub fn repair(path: &Path, output: Option<&Path>) -> Result<RepairReport, FlowError> {
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => default_output(path),
+    };
+    if output == path {
+        return Err(FlowError::invalid(format!(
+            "Refusing to overwrite {} with its repaired copy",
+            path.display()
+        )));
+    }
+    if crypto::is_encrypted(path) {
+        // Any damage to the ciphertext fails authentication as a whole
+        return Err(FlowError::invalid(format!(
+            "{} is encrypted; damaged encrypted files can't be repaired",
+            path.display()
+        )));
+    }
+    let bytes = fs::read(path).map_err(|e| FlowError::io(path, e))?;
+
+    let mut lost = Vec::new();
+    let mut complete = BTreeMap::new();
+    let mut damaged_document = None;
+    for (name, entry) in scan(path, &bytes)? {
+        match entry.damage {
+            None => {
+                complete.insert(name, entry.data);
+            }
+            Some(reason) => {
+                if name == DOCUMENT_ENTRY {
+                    damaged_document = Some((entry.data, entry.size));
+                }
+                lost.push(LostEntry { name, reason });
+            }
+        }
+    }
+
+    let mut lost_bytes = None;
+    let mut revision = None;
+    let intact = complete
+        .get(DOCUMENT_ENTRY)
+        .and_then(|data| parse(data).ok());
+    let partial = damaged_document.and_then(|(data, size)| {
+        let (doc, kept) = recover_document(&data)?;
+        Some((doc, size.map(|size| size.saturating_sub(kept as u64))))
+    });
+    let (doc, source) = if let Some(doc) = intact {
+        (doc, DocumentSource::Intact)
+    } else if let Some((doc, dropped)) = partial {
+        lost_bytes = dropped;
+        (doc, DocumentSource::Partial)
+    } else if let Some((id, doc)) = newest_revision(&complete) {
+        revision = Some(id);
+        (doc, DocumentSource::Revision)
+    } else {
+        return Err(FlowError::CorruptArchive {
+            path: path.to_path_buf(),
+            reason: "no document could be recovered".to_string(),
+        });
+    };
+    if source != DocumentSource::Intact && !lost.iter().any(|l| l.name == DOCUMENT_ENTRY) {
+        let reason = match complete.contains_key(DOCUMENT_ENTRY) {
+            true => "not a valid document",
+            false => "not found",
+        };
+        lost.push(LostEntry {
+            name: DOCUMENT_ENTRY.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    // The salvaged archive gets a new document and manifest
+    complete.remove(DOCUMENT_ENTRY);
+    complete.remove(MANIFEST_ENTRY);
+    let mut missing_assets: Vec<String> = assets::referenced(&doc)
+        .into_iter()
+        .filter(|name| !complete.contains_key(name))
+        .collect();
+    missing_assets.sort();
+    let recovered: Vec<String> = complete.keys().cloned().collect();
+    flm::write_archive(&output, &doc, |_| false, complete.into_iter().collect())?;
+
+    Ok(RepairReport {
+        output,
+        document: source,
+        revision,
+        blocks: doc.content.doc.content.len(),
+        notes: doc.notes.len(),
+        lost_bytes,
+        recovered,
+        lost,
+        missing_assets,
+    })
+}
+
+fn default_output(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.repaired.flm", stem))
+}
+
+/// Every entry with a local header in `bytes`, by name. An undamaged copy
+/// of an entry wins over a damaged one.
+fn scan(path: &Path, bytes: &[u8]) -> Result<BTreeMap<String, Scanned>, FlowError> {
+    let limits = limits::limits();
+    let mut entries: BTreeMap<String, Scanned> = BTreeMap::new();
+    let mut total: u64 = 0;
+    let mut pos = 0;
+    while let Some(found) = find(bytes, LOCAL_HEADER, pos) {
+        pos = found + 1;
+        let Some((name, entry, end)) = read_entry(bytes, found, limits.max_entry_size) else {
+            continue;
+        };
+        // Damaged data may hide the next header
+        if entry.damage.is_none() {
+            pos = pos.max(end);
+        }
+        let keep_known = entries
+            .get(&name)
+            .is_some_and(|known| known.damage.is_none() || entry.damage.is_some());
+        if keep_known {
+            continue;
+        }
+        total = total.saturating_add(entry.data.len() as u64);
+        entries.insert(name, entry);
+        if entries.len() as u64 > limits.max_entries {
+            return Err(FlowError::TooManyEntries {
+                path: path.to_path_buf(),
+                count: entries.len() as u64,
+                limit: limits.max_entries,
+            });
+        }
+        if total > limits.max_total_size {
+            return Err(FlowError::ArchiveTooLarge {
+                path: path.to_path_buf(),
+                size: total,
+                limit: limits.max_total_size,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The entry whose local header is at `start`, and where its data ends.
+/// Data beyond `max_size` is not decompressed.
+fn read_entry(bytes: &[u8], start: usize, max_size: u64) -> Option<(String, Scanned, usize)> {
+    let header = bytes.get(start..start + LOCAL_HEADER_LEN)?;
+    let u16_at = |offset: usize| u16::from_le_bytes([header[offset], header[offset + 1]]);
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            header[offset],
+            header[offset + 1],
+            header[offset + 2],
+            header[offset + 3],
+        ])
+    };
+    let flags = u16_at(6);
+    let method = u16_at(8);
+    let mut crc = u32_at(14);
+    let compressed_size = u32_at(18);
+    let size = u32_at(22);
+    let name_len = u16_at(26) as usize;
+    let extra_len = u16_at(28) as usize;
+
+    let name_start = start + LOCAL_HEADER_LEN;
+    let name = std::str::from_utf8(bytes.get(name_start..name_start + name_len)?).ok()?;
+    if !is_safe_name(name) {
+        return None;
+    }
+    let data_start = name_start + name_len + extra_len;
+    if data_start > bytes.len() {
+        return None;
+    }
+    // Sizes are only known up front without a data descriptor and ZIP64
+    let sized = flags & FLAG_DATA_DESCRIPTOR == 0 && compressed_size != u32::MAX;
+    let available = &bytes[data_start..];
+    let (stored, declared) = match sized {
+        true => (
+            &available[..available.len().min(compressed_size as usize)],
+            Some(size as u64),
+        ),
+        false => (available, None),
+    };
+
+    let (data, consumed, mut damage) = match method {
+        METHOD_STORED if sized && stored.len() as u64 > max_size => {
+            (Vec::new(), stored.len(), Some(too_large(max_size)))
+        }
+        METHOD_STORED if sized => (stored.to_vec(), stored.len(), None),
+        METHOD_DEFLATED => inflate(stored, max_size),
+        METHOD_STORED => (Vec::new(), 0, Some("stored without its size".to_string())),
+        method => (
+            Vec::new(),
+            stored.len(),
+            Some(format!("unsupported compression method {}", method)),
+        ),
+    };
+    let mut end = data_start + consumed;
+    if !sized {
+        // The checksum is in the descriptor after the data
+        let descriptor = match bytes.get(end..end + 4) {
+            Some(signature) if signature == DATA_DESCRIPTOR => end + 4,
+            _ => end,
+        };
+        crc = bytes
+            .get(descriptor..descriptor + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .unwrap_or(0);
+        end = descriptor;
+    }
+    if damage.is_none() {
+        if sized && stored.len() < compressed_size as usize {
+            damage = Some("truncated".to_string());
+        } else if declared.is_some_and(|declared| declared != data.len() as u64) {
+            damage = Some("size mismatch".to_string());
+        } else if crc32fast::hash(&data) != crc {
+            damage = Some("checksum mismatch".to_string());
+        }
+    }
+    Some((
+        name.to_string(),
+        Scanned {
+            data,
+            size: declared,
+            damage,
+        },
+        end,
+    ))
+}
+
+/// Decompress as much of `data` as possible, up to `max_size` bytes: the
+/// output, the input used and what went wrong, if anything.
+fn inflate(data: &[u8], max_size: u64) -> (Vec<u8>, usize, Option<String>) {
+    let mut decoder = DeflateDecoder::new(data);
+    let mut out = Vec::new();
+    let mut buf = [0u8; 16 * 1024];
+    let damage = loop {
+        match decoder.read(&mut buf) {
+            Ok(0) => break None,
+            Ok(n) if (out.len() + n) as u64 > max_size => break Some(too_large(max_size)),
+            Ok(n) => out.extend_from_slice(&buf[..n]),
+            Err(e) => break Some(format!("decompression failed: {}", e)),
+        }
+    };
+    (out, decoder.total_in() as usize, damage)
+}
+
+fn too_large(max_size: u64) -> String {
+    format!("larger than the limit of {} bytes", max_size)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|offset| from + offset)
+}
+
+fn parse(data: &[u8]) -> Result<FlowmarkDocument, FlowError> {
+    let json = std::str::from_utf8(data)
+        .map_err(|e| FlowError::invalid(format!("{}: {}", DOCUMENT_ENTRY, e)))?;
+    FlowmarkDocument::from_json(json)
+}
+
+/// The document in the damaged `document.json` text `data`, and how many
+/// of its bytes were kept.
+fn recover_document(data: &[u8]) -> Option<(FlowmarkDocument, usize)> {
+    // A cut through a multi-byte character leaves a valid prefix
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => std::str::from_utf8(&data[..e.valid_up_to()]).ok()?,
+    };
+    let (json, cut) = close_json(text)?;
+    let doc = FlowmarkDocument::from_json(&json).ok()?;
+    Some((doc, cut))
+}
+
+/// A container being parsed by [`close_json`].
+struct Frame {
+    closer: char,
+    /// Key of the container in its parent object.
+    key: Option<String>,
+    /// In an object: whether the next string is a key, and the last key.
+    expect_key: bool,
+    last_key: Option<String>,
+}
+
+/// Key paths of the containers whose complete children are kept: the
+/// document object, `content`, `content.doc`, the top-level blocks and the
+/// notes. A child of anything deeper is only kept whole.
+const KEPT_CONTAINERS: &[&[&str]] = &[
+    &[],
+    &["content"],
+    &["content", "doc"],
+    &["content", "doc", "content"],
+    &["notes"],
+];
+
+/// Cut `text`, the start of a JSON document, back to the end of the last
+/// child completed in one of [`KEPT_CONTAINERS`] and close whatever is
+/// still open there. Returns the JSON and the length of `text` kept.
+fn close_json(text: &str) -> Option<(String, usize)> {
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut cut: Option<(usize, String)> = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string_start = 0;
+
+    let kept = |stack: &[Frame]| {
+        let path: Vec<&str> = stack
+            .iter()
+            .skip(1)
+            .map(|frame| frame.key.as_deref().unwrap_or(""))
+            .collect();
+        !stack.is_empty() && KEPT_CONTAINERS.contains(&path.as_slice())
+    };
+    let closers =
+        |stack: &[Frame]| -> String { stack.iter().rev().map(|frame| frame.closer).collect() };
+
+    for (index, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+                if let Some(frame) = stack.last_mut() {
+                    if frame.closer == '}' && frame.expect_key {
+                        frame.last_key = Some(text[string_start..index].to_string());
+                    }
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                string_start = index + 1;
+            }
+            '{' | '[' => {
+                if stack.is_empty() && c != '{' {
+                    return None;
+                }
+                let key = stack.last_mut().and_then(|parent| match parent.closer {
+                    '}' => parent.last_key.take(),
+                    _ => None,
+                });
+                stack.push(Frame {
+                    closer: if c == '{' { '}' } else { ']' },
+                    key,
+                    expect_key: c == '{',
+                    last_key: None,
+                });
+            }
+            '}' | ']' => {
+                stack.pop()?;
+                if stack.is_empty() {
+                    // The text was complete after all
+                    return Some((text[..=index].to_string(), index + 1));
+                }
+                if kept(&stack) {
+                    cut = Some((index + 1, closers(&stack)));
+                }
+            }
+            ':' => {
+                if let Some(frame) = stack.last_mut() {
+                    frame.expect_key = false;
+                }
+            }
+            ',' => {
+                if kept(&stack) {
+                    cut = Some((index, closers(&stack)));
+                }
+                if let Some(frame) = stack.last_mut() {
+                    frame.expect_key = frame.closer == '}';
+                }
+            }
+            c if stack.is_empty() && !c.is_whitespace() => return None,
+            _ => {}
+        }
+    }
+    let (end, closers) = cut?;
+    Some((format!("{}{}", &text[..end], closers), end))
+}
+
+/// The newest revision in the history entries that is a valid document.
+fn newest_revision(entries: &BTreeMap<String, Vec<u8>>) -> Option<(String, FlowmarkDocument)> {
+    // Revision ids sort by time
+    entries.iter().rev().find_map(|(name, data)| {
+        let id = name.strip_prefix(HISTORY_DIR)?.strip_suffix(".json")?;
+        Some((id.to_string(), parse(data).ok()?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{Node, NodeKind};
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    const BLOCKS: &str = r#"{"content":{"doc":{"type":"doc","content":[{"type":"paragraph"},"#;
+
+    fn archive(entries: &[(&str, &[u8], CompressionMethod)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data, method) in entries {
+            let options = FileOptions::default().compression_method(*method);
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn paragraphs(count: usize) -> FlowmarkDocument {
+        let blocks = (0..count)
+            .map(|i| {
+                Node::new(NodeKind::Paragraph)
+                    .with_content(vec![Node::text(format!("Paragraph {}", i))])
+            })
+            .collect();
+        FlowmarkDocument::new(Node::new(NodeKind::Doc).with_content(blocks), Vec::new())
+    }
+
+    #[test]
+    fn complete_json_is_kept_as_is() {
+        let json = r#"{"a":[1,{"b":"}]"}]}"#;
+        assert_eq!(close_json(json), Some((json.to_string(), json.len())));
+    }
+
+    #[test]
+    fn truncation_inside_a_string_keeps_complete_blocks() {
+        let text = format!(
+            r#"{}{{"type":"paragraph","content":[{{"type":"text","text":"hel"#,
+            BLOCKS
+        );
+        let (json, cut) = close_json(&text).unwrap();
+        assert_eq!(
+            json,
+            r#"{"content":{"doc":{"type":"doc","content":[{"type":"paragraph"}]}}}"#
+        );
+        assert_eq!(cut, BLOCKS.len() - 1);
+    }
+
+    #[test]
+    fn truncated_escape_sequence_is_not_taken_for_a_quote() {
+        let text = format!(r#"{}{{"type":"text","text":"a\"#, BLOCKS);
+        let (json, _) = close_json(&text).unwrap();
+        assert_eq!(
+            json,
+            r#"{"content":{"doc":{"type":"doc","content":[{"type":"paragraph"}]}}}"#
+        );
+
+        // Escaped quotes and brackets inside strings don't end anything
+        let block = r#"{"type":"code_block","content":[{"type":"text","text":"\"]}\\"}]}"#;
+        let text = format!(r#"{}{},{{"type":"para"#, BLOCKS, block);
+        let (json, _) = close_json(&text).unwrap();
+        assert_eq!(json, format!("{}{}]}}}}}}", BLOCKS, block));
+    }
+
+    #[test]
+    fn nested_blocks_are_kept_whole_or_not_at_all() {
+        let list = r#"{"type":"bullet_list","content":[{"type":"list_item","content":[{"type":"paragraph"}]}]}"#;
+        // Cut after a complete item of a nested list
+        let text = format!(
+            r#"{}{},{{"type":"blockquote","content":[{{"type":"paragraph"}},{{"type":"bullet_list","content":[{{"type":"list_item"}},"#,
+            BLOCKS, list
+        );
+        let (json, _) = close_json(&text).unwrap();
+        assert_eq!(json, format!("{}{}]}}}}}}", BLOCKS, list));
+        assert_eq!(
+            FlowmarkDocument::from_json(&json)
+                .unwrap()
+                .content
+                .doc
+                .content
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn trailing_partial_block_is_dropped_and_notes_kept() {
+        let text = r#"{"notes":[{"noteId":"a","number":1,"content":[]},{"noteId":"b","num"#;
+        let (json, _) = close_json(text).unwrap();
+        assert_eq!(
+            json,
+            r#"{"notes":[{"noteId":"a","number":1,"content":[]}]}"#
+        );
+
+        // Without a complete block the cut falls back to the last member
+        assert_eq!(
+            close_json(r#"{"content":{"doc":{"type":"doc","content":[{"ty"#).map(|(json, _)| json),
+            Some(r#"{"content":{"doc":{"type":"doc"}}}"#.to_string())
+        );
+        assert_eq!(close_json("[1,2"), None);
+        assert_eq!(close_json("x{}"), None);
+    }
+
+    #[test]
+    fn recovers_the_complete_blocks_of_a_cut_document() {
+        let json = paragraphs(3).to_json().unwrap();
+        let cut = json.find("Paragraph 2").unwrap();
+        let (doc, kept) = recover_document(&json.as_bytes()[..cut]).unwrap();
+        assert_eq!(doc.content.doc.content, paragraphs(2).content.doc.content);
+        assert!(kept < cut);
+    }
+
+    #[test]
+    fn scan_keeps_entries_before_a_truncation() {
+        let document = paragraphs(3).to_json().unwrap();
+        let bytes = archive(&[
+            ("assets/files/a", b"first", CompressionMethod::Deflated),
+            (
+                DOCUMENT_ENTRY,
+                document.as_bytes(),
+                CompressionMethod::Stored,
+            ),
+            ("assets/files/b", b"last", CompressionMethod::Stored),
+        ]);
+        // Cut inside the data of document.json
+        let cut = find(&bytes, b"Paragraph 2", 0).unwrap();
+        let entries = scan(Path::new("cut.flm"), &bytes[..cut]).unwrap();
+
+        let names: Vec<&str> = entries.keys().map(String::as_str).collect();
+        assert_eq!(names, ["assets/files/a", DOCUMENT_ENTRY]);
+        let first = &entries["assets/files/a"];
+        assert_eq!(
+            (first.data.as_slice(), first.damage.as_deref()),
+            (&b"first"[..], None)
+        );
+        let document_entry = &entries[DOCUMENT_ENTRY];
+        assert_eq!(document_entry.damage.as_deref(), Some("truncated"));
+        assert_eq!(document_entry.size, Some(document.len() as u64));
+        assert!(document.as_bytes().starts_with(&document_entry.data));
+    }
+
+    #[test]
+    fn scan_reports_damaged_data() {
+        let mut bytes = archive(&[("assets/files/a", b"data", CompressionMethod::Stored)]);
+        let at = find(&bytes, b"data", 0).unwrap();
+        bytes[at] = b'D';
+        let entries = scan(Path::new("bad.flm"), &bytes).unwrap();
+        assert_eq!(
+            entries["assets/files/a"].damage.as_deref(),
+            Some("checksum mismatch")
+        );
+    }
+
+    #[test]
+    fn repair_writes_what_could_be_saved() {
+        let dir = std::env::temp_dir().join(format!("flowmark-repair-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cut.flm");
+        let document = paragraphs(3).to_json().unwrap();
+        let bytes = archive(&[
+            ("assets/files/a", b"first", CompressionMethod::Stored),
+            (
+                DOCUMENT_ENTRY,
+                document.as_bytes(),
+                CompressionMethod::Stored,
+            ),
+        ]);
+        let cut = find(&bytes, b"Paragraph 2", 0).unwrap();
+        fs::write(&path, &bytes[..cut]).unwrap();
+
+        let report = repair(&path, None).unwrap();
+        assert_eq!(report.output, dir.join("cut.repaired.flm"));
+        assert_eq!(report.document, DocumentSource::Partial);
+        assert_eq!(report.blocks, 2);
+        assert_eq!(report.recovered, ["assets/files/a"]);
+        assert_eq!(report.lost[0].name, DOCUMENT_ENTRY);
+        let doc = flm::read_document(&report.output).unwrap();
+        assert_eq!(doc.content.doc.content, paragraphs(2).content.doc.content);
+        assert_eq!(fs::read(&path).unwrap(), &bytes[..cut]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,691 @@
+//! Validation of the document tree against the editor schema.
+//!
+//! Mirrors the node and mark specs combined in `frontend/src/editor/schema.ts`
+//! (prosemirror-schema-basic, prosemirror-schema-list, prosemirror-tables and
+//! `note_ref`), so a document that passes can be loaded by ProseMirror.
+//! Violations are reported with their JSON path in `document.json`, and
+//! `fix` rewrites the tree until there are none left. Whether referenced
+//! notes exist is checked by [`notes`](crate::notes).
+
+use serde::Serialize;
+use std::path::Path;
+
+use crate::document::{FlowmarkDocument, MarkKind, Node, NodeKind};
+use crate::error::FlowError;
+
+/// JSON path of the root node.
+const ROOT: &str = "$.content.doc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ViolationKind {
+    /// A node type the schema doesn't define.
+    UnknownNode,
+    /// A mark type the schema doesn't define.
+    UnknownMark,
+    /// A node where its parent doesn't allow it.
+    InvalidContent,
+    /// A mark where its node doesn't allow it, or a repeated one.
+    InvalidMark,
+    /// A node without the children it requires.
+    MissingContent,
+    /// A text node without text.
+    InvalidText,
+    /// A required attribute that is missing or out of range.
+    InvalidAttribute,
+}
+
+impl ViolationKind {
+    /// Whether fixing it changes the structure of the document or drops
+    /// content the user may want to keep, instead of only tidying it up.
+    pub fn is_structural(self) -> bool {
+        matches!(
+            self,
+            ViolationKind::UnknownNode | ViolationKind::UnknownMark | ViolationKind::InvalidContent
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Violation {
+    /// JSON path in `document.json`, e.g. `$.content.doc.content[2]`.
+    pub path: String,
+    pub kind: ViolationKind,
+    pub message: String,
+}
+
+/// Content expression of a node type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Content {
+    /// `block+`
+    Blocks,
+    /// `inline*`
+    Inline,
+    /// `text*`, without marks
+    Text,
+    /// `list_item+`
+    Items,
+    /// `paragraph block*`
+    ListItem,
+    /// `table_row+`
+    Rows,
+    /// `(table_cell | table_header)*`
+    Cells,
+    /// Leaf node
+    Empty,
+}
+
+const NO_WRAPPER: &[NodeKind] = &[];
+const PARAGRAPH: &[NodeKind] = &[NodeKind::Paragraph];
+const BULLET_LIST: &[NodeKind] = &[NodeKind::BulletList];
+const LIST_ITEM: &[NodeKind] = &[NodeKind::ListItem];
+const TABLE: &[NodeKind] = &[NodeKind::Table];
+const TABLE_ROW: &[NodeKind] = &[NodeKind::TableRow];
+const TABLE_CELL: &[NodeKind] = &[NodeKind::TableCell];
+const TABLE_WITH_ROW: &[NodeKind] = &[NodeKind::Table, NodeKind::TableRow];
+const ROW_WITH_CELL: &[NodeKind] = &[NodeKind::TableRow, NodeKind::TableCell];
+
+impl Content {
+    /// `None` for node types the schema doesn't define.
+    fn of(kind: &NodeKind) -> Option<Content> {
+        Some(match kind {
+            NodeKind::Doc | NodeKind::Blockquote | NodeKind::TableCell | NodeKind::TableHeader => {
+                Content::Blocks
+            }
+            NodeKind::Paragraph | NodeKind::Heading => Content::Inline,
+            NodeKind::CodeBlock => Content::Text,
+            NodeKind::OrderedList | NodeKind::BulletList => Content::Items,
+            NodeKind::ListItem => Content::ListItem,
+            NodeKind::Table => Content::Rows,
+            NodeKind::TableRow => Content::Cells,
+            NodeKind::HorizontalRule
+            | NodeKind::Text
+            | NodeKind::Image
+            | NodeKind::HardBreak
+            | NodeKind::NoteRef => Content::Empty,
+            NodeKind::Other(_) => return None,
+        })
+    }
+
+    fn allows(self, kind: &NodeKind, index: usize) -> bool {
+        match self {
+            Content::Blocks => is_block(kind),
+            Content::Inline => is_inline(kind),
+            Content::Text => *kind == NodeKind::Text,
+            Content::Items => *kind == NodeKind::ListItem,
+            Content::ListItem if index == 0 => *kind == NodeKind::Paragraph,
+            Content::ListItem => is_block(kind),
+            Content::Rows => *kind == NodeKind::TableRow,
+            Content::Cells => matches!(kind, NodeKind::TableCell | NodeKind::TableHeader),
+            Content::Empty => false,
+        }
+    }
+
+    fn is_required(self) -> bool {
+        matches!(
+            self,
+            Content::Blocks | Content::Items | Content::ListItem | Content::Rows
+        )
+    }
+
+    fn is_textblock(self) -> bool {
+        matches!(self, Content::Inline | Content::Text)
+    }
+
+    /// Nodes to wrap a child of `kind` in, outermost first, so it fits this
+    /// content. Only used for content that isn't inline.
+    fn wrappers(self, kind: &NodeKind) -> &'static [NodeKind] {
+        match self {
+            Content::Blocks | Content::ListItem => match kind {
+                kind if is_inline(kind) => PARAGRAPH,
+                NodeKind::ListItem => BULLET_LIST,
+                NodeKind::TableRow => TABLE,
+                NodeKind::TableCell | NodeKind::TableHeader => TABLE_WITH_ROW,
+                _ => NO_WRAPPER,
+            },
+            Content::Items => match kind {
+                NodeKind::ListItem => NO_WRAPPER,
+                _ => LIST_ITEM,
+            },
+            Content::Rows => match kind {
+                NodeKind::TableRow => NO_WRAPPER,
+                NodeKind::TableCell | NodeKind::TableHeader => TABLE_ROW,
+                _ => ROW_WITH_CELL,
+            },
+            Content::Cells => match kind {
+                NodeKind::TableCell | NodeKind::TableHeader => NO_WRAPPER,
+                _ => TABLE_CELL,
+            },
+            Content::Inline | Content::Text | Content::Empty => NO_WRAPPER,
+        }
+    }
+
+    /// Smallest valid content, for nodes that require some.
+    fn fill(self) -> Vec<Node> {
+        let paragraph = || Node::new(NodeKind::Paragraph);
+        match self {
+            Content::Blocks | Content::ListItem => vec![paragraph()],
+            Content::Items => vec![Node::new(NodeKind::ListItem).with_content(vec![paragraph()])],
+            Content::Rows => vec![Node::new(NodeKind::TableRow)
+                .with_content(vec![
+                    Node::new(NodeKind::TableCell).with_content(vec![paragraph()])
+                ])],
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn is_block(kind: &NodeKind) -> bool {
+    matches!(
+        kind,
+        NodeKind::Paragraph
+            | NodeKind::Blockquote
+            | NodeKind::HorizontalRule
+            | NodeKind::Heading
+            | NodeKind::CodeBlock
+            | NodeKind::OrderedList
+            | NodeKind::BulletList
+            | NodeKind::Table
+    )
+}
+
+fn is_inline(kind: &NodeKind) -> bool {
+    matches!(
+        kind,
+        NodeKind::Text | NodeKind::Image | NodeKind::HardBreak | NodeKind::NoteRef
+    )
+}
+
+/// All violations of the editor schema in `doc`, in document order.
+pub fn check(doc: &FlowmarkDocument) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    check_node(&doc.content.doc, ROOT, &mut violations);
+    violations
+}
+
+/// Rewrite `doc` to match the editor schema, returning the violations it
+/// had. Unknown nodes are replaced by their content, stray inline content
+/// and list items, rows and cells are wrapped in the nodes they belong in,
+/// and note references without a note id, images without a source and
+/// unknown marks are dropped.
+pub fn fix(doc: &mut FlowmarkDocument) -> Vec<Violation> {
+    let violations = check(doc);
+    if !violations.is_empty() {
+        let root = &mut doc.content.doc;
+        root.marks.clear();
+        fix_node(root);
+    }
+    violations
+}
+
+/// Check the document read from `path` and fix it, returning the
+/// violations it had. Structural violations (see
+/// [`ViolationKind::is_structural`]) are only fixed when `fix` is set, and
+/// fail with [`FlowError::InvalidSchema`] otherwise.
+pub fn conform(
+    path: &Path,
+    doc: &mut FlowmarkDocument,
+    fix: bool,
+) -> Result<Vec<Violation>, FlowError> {
+    let violations = check(doc);
+    if !fix && violations.iter().any(|v| v.kind.is_structural()) {
+        return Err(FlowError::InvalidSchema {
+            path: path.to_path_buf(),
+            violations,
+        });
+    }
+    Ok(self::fix(doc))
+}
+
+fn violation(path: &str, kind: ViolationKind, message: String) -> Violation {
+    Violation {
+        path: path.to_string(),
+        kind,
+        message,
+    }
+}
+
+fn check_node(node: &Node, path: &str, out: &mut Vec<Violation>) {
+    let Some(content) = Content::of(&node.kind) else {
+        out.push(violation(
+            path,
+            ViolationKind::UnknownNode,
+            format!("Unknown node type '{}'", node.kind.as_str()),
+        ));
+        return;
+    };
+    check_attrs(node, path, out);
+    if content.is_required() && node.content.is_empty() {
+        out.push(violation(
+            path,
+            ViolationKind::MissingContent,
+            format!("'{}' must not be empty", node.kind.as_str()),
+        ));
+    }
+    for (index, child) in node.content.iter().enumerate() {
+        let child_path = format!("{}.content[{}]", path, index);
+        if Content::of(&child.kind).is_some() && !content.allows(&child.kind, index) {
+            out.push(violation(
+                &child_path,
+                ViolationKind::InvalidContent,
+                format!(
+                    "'{}' is not allowed here in '{}'",
+                    child.kind.as_str(),
+                    node.kind.as_str()
+                ),
+            ));
+        }
+        check_marks(child, &child_path, content == Content::Inline, out);
+        check_node(child, &child_path, out);
+    }
+}
+
+fn check_attrs(node: &Node, path: &str, out: &mut Vec<Violation>) {
+    match node.kind {
+        NodeKind::Text if node.text.as_deref().unwrap_or("").is_empty() => {
+            out.push(violation(
+                path,
+                ViolationKind::InvalidText,
+                "Text nodes must not be empty".to_string(),
+            ));
+        }
+        NodeKind::Heading if !valid_level(node) => out.push(violation(
+            &format!("{}.attrs.level", path),
+            ViolationKind::InvalidAttribute,
+            "Heading level must be a number from 1 to 6".to_string(),
+        )),
+        NodeKind::Image if node.attr_str("src").is_none() => out.push(violation(
+            &format!("{}.attrs.src", path),
+            ViolationKind::InvalidAttribute,
+            "Images need a source".to_string(),
+        )),
+        // Whether the note exists is up to `notes`
+        NodeKind::NoteRef if node.attr_str("noteId").is_none() => out.push(violation(
+            &format!("{}.attrs.noteId", path),
+            ViolationKind::InvalidAttribute,
+            "Note reference without a note id".to_string(),
+        )),
+        _ => {}
+    }
+}
+
+/// The level may be left out, it defaults to 1.
+fn valid_level(node: &Node) -> bool {
+    match node.attrs.get("level") {
+        Some(level) => level.as_u64().is_some_and(|l| (1..=6).contains(&l)),
+        None => true,
+    }
+}
+
+/// Marks are only allowed on the children of nodes with inline content,
+/// except code blocks.
+fn check_marks(node: &Node, path: &str, allowed: bool, out: &mut Vec<Violation>) {
+    let mut seen: Vec<&MarkKind> = Vec::new();
+    for (index, mark) in node.marks.iter().enumerate() {
+        let mark_path = format!("{}.marks[{}]", path, index);
+        let name = mark.kind.as_str();
+        if let MarkKind::Other(_) = mark.kind {
+            out.push(violation(
+                &mark_path,
+                ViolationKind::UnknownMark,
+                format!("Unknown mark type '{}'", name),
+            ));
+        } else if !allowed {
+            out.push(violation(
+                &mark_path,
+                ViolationKind::InvalidMark,
+                format!("'{}' can't have marks here", node.kind.as_str()),
+            ));
+        } else if seen.contains(&&mark.kind) {
+            out.push(violation(
+                &mark_path,
+                ViolationKind::InvalidMark,
+                format!("Duplicate '{}' mark", name),
+            ));
+        } else if mark.kind == MarkKind::Link
+            && mark.attrs.get("href").and_then(|v| v.as_str()).is_none()
+        {
+            out.push(violation(
+                &format!("{}.attrs.href", mark_path),
+                ViolationKind::InvalidAttribute,
+                "Links need an href".to_string(),
+            ));
+        }
+        seen.push(&mark.kind);
+    }
+}
+
+fn fix_node(node: &mut Node) {
+    // Only called for known node types
+    let Some(content) = Content::of(&node.kind) else {
+        return;
+    };
+    if node.kind == NodeKind::Heading && !valid_level(node) {
+        let level = node.level();
+        node.attrs.insert("level".to_string(), level.into());
+    }
+    if content == Content::Empty {
+        node.content.clear();
+        return;
+    }
+
+    let mut fitted = Vec::new();
+    for child in std::mem::take(&mut node.content) {
+        fit(child, content, &mut fitted);
+    }
+    node.content = if content.is_textblock() {
+        fitted
+    } else {
+        wrap_stray(content, fitted)
+    };
+    if content == Content::ListItem
+        && node
+            .content
+            .first()
+            .is_some_and(|first| first.kind != NodeKind::Paragraph)
+    {
+        node.content.insert(0, Node::new(NodeKind::Paragraph));
+    }
+    if content.is_required() && node.content.is_empty() {
+        node.content = content.fill();
+    }
+
+    for child in &mut node.content {
+        fix_marks(child, content == Content::Inline);
+        fix_node(child);
+    }
+}
+
+/// Add `node` to the children of a node with `content`, replacing it by its
+/// own children or dropping it if it can't be kept.
+fn fit(node: Node, content: Content, out: &mut Vec<Node>) {
+    let keep = match &node.kind {
+        NodeKind::Text => !node.text.as_deref().unwrap_or("").is_empty(),
+        NodeKind::NoteRef => node.attr_str("noteId").is_some(),
+        NodeKind::Image => node.attr_str("src").is_some(),
+        NodeKind::Other(_) | NodeKind::Doc => {
+            // Keep what's inside unknown nodes, including text-like ones
+            if let Some(text) = node.text.filter(|text| !text.is_empty()) {
+                out.push(Node {
+                    marks: node.marks,
+                    ..Node::text(text)
+                });
+            }
+            for child in node.content {
+                fit(child, content, out);
+            }
+            return;
+        }
+        kind if content.is_textblock() && !is_inline(kind) => {
+            // Blocks inside a textblock are flattened into it
+            for child in node.content {
+                fit(child, content, out);
+            }
+            return;
+        }
+        _ => true,
+    };
+    if !keep {
+        return;
+    }
+    match node.kind {
+        NodeKind::HardBreak if content == Content::Text => out.push(Node::text("\n")),
+        NodeKind::Image | NodeKind::NoteRef if content == Content::Text => {}
+        _ => out.push(node),
+    }
+}
+
+/// Wrap runs of children that don't fit `content` in the nodes they belong
+/// in, e.g. inline content in a paragraph or table rows in a table.
+fn wrap_stray(content: Content, children: Vec<Node>) -> Vec<Node> {
+    let mut out = Vec::new();
+    let mut run: Vec<Node> = Vec::new();
+    let mut run_wrappers = NO_WRAPPER;
+    for child in children {
+        let wrappers = content.wrappers(&child.kind);
+        if wrappers != run_wrappers && !run.is_empty() {
+            out.push(wrap(run_wrappers, std::mem::take(&mut run)));
+        }
+        run_wrappers = wrappers;
+        if wrappers.is_empty() {
+            out.push(child);
+        } else {
+            run.push(child);
+        }
+    }
+    if !run.is_empty() {
+        out.push(wrap(run_wrappers, run));
+    }
+    out
+}
+
+fn wrap(wrappers: &[NodeKind], mut content: Vec<Node>) -> Node {
+    for kind in wrappers.iter().rev() {
+        content = vec![Node::new(kind.clone()).with_content(content)];
+    }
+    content.remove(0)
+}
+
+fn fix_marks(node: &mut Node, allowed: bool) {
+    if !allowed {
+        node.marks.clear();
+        return;
+    }
+    let mut seen: Vec<MarkKind> = Vec::new();
+    node.marks.retain(|mark| {
+        let keep = !matches!(mark.kind, MarkKind::Other(_))
+            && !seen.contains(&mark.kind)
+            && (mark.kind != MarkKind::Link
+                || mark.attrs.get("href").and_then(|v| v.as_str()).is_some());
+        seen.push(mark.kind.clone());
+        keep
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn node(value: Value) -> Node {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn doc(content: Value) -> FlowmarkDocument {
+        FlowmarkDocument::new(node(json!({"type": "doc", "content": content})), Vec::new())
+    }
+
+    fn kinds(violations: &[Violation]) -> Vec<(&str, ViolationKind)> {
+        violations
+            .iter()
+            .map(|v| (v.path.as_str(), v.kind))
+            .collect()
+    }
+
+    fn paragraph(text: &str) -> Value {
+        json!({"type": "paragraph", "content": [{"type": "text", "text": text}]})
+    }
+
+    #[test]
+    fn valid_document_is_left_alone() {
+        let mut valid = doc(json!([
+            {"type": "heading", "attrs": {"level": 2}, "content": [{"type": "text", "text": "Title"}]},
+            {"type": "paragraph", "content": [
+                {"type": "text", "text": "link", "marks": [{"type": "em"}, {"type": "link", "attrs": {"href": "x"}}]},
+                {"type": "note_ref", "attrs": {"noteId": "n1"}},
+            ]},
+            {"type": "bullet_list", "content": [
+                {"type": "list_item", "content": [paragraph("a"), {"type": "code_block"}]},
+            ]},
+            {"type": "table", "content": [
+                {"type": "table_row", "content": [{"type": "table_header", "content": [paragraph("h")]}]},
+            ]},
+            {"type": "paragraph"},
+        ]));
+        let before = valid.clone();
+        assert_eq!(check(&valid), []);
+        assert_eq!(conform(Path::new("a.flm"), &mut valid, false).unwrap(), []);
+        assert_eq!(valid, before);
+    }
+
+    #[test]
+    fn stray_inline_content_is_wrapped() {
+        let mut stray = doc(json!([
+            {"type": "text", "text": "a"},
+            {"type": "hard_break"},
+            paragraph("b"),
+            {"type": "text", "text": "c"},
+        ]));
+        let violations = check(&stray);
+        assert_eq!(
+            kinds(&violations),
+            [
+                ("$.content.doc.content[0]", ViolationKind::InvalidContent),
+                ("$.content.doc.content[1]", ViolationKind::InvalidContent),
+                ("$.content.doc.content[3]", ViolationKind::InvalidContent),
+            ]
+        );
+        assert_eq!(fix(&mut stray), violations);
+        assert_eq!(
+            stray.content.doc,
+            node(json!({"type": "doc", "content": [
+                {"type": "paragraph", "content": [{"type": "text", "text": "a"}, {"type": "hard_break"}]},
+                paragraph("b"),
+                paragraph("c"),
+            ]}))
+        );
+        assert_eq!(check(&stray), []);
+    }
+
+    #[test]
+    fn stray_list_items_and_cells_get_their_parents() {
+        let mut stray = doc(json!([
+            {"type": "list_item", "content": [paragraph("a")]},
+            {"type": "table_cell", "content": [paragraph("b")]},
+            {"type": "bullet_list", "content": [paragraph("c")]},
+        ]));
+        fix(&mut stray);
+        assert_eq!(
+            stray.content.doc,
+            node(json!({"type": "doc", "content": [
+                {"type": "bullet_list", "content": [{"type": "list_item", "content": [paragraph("a")]}]},
+                {"type": "table", "content": [{"type": "table_row", "content": [
+                    {"type": "table_cell", "content": [paragraph("b")]},
+                ]}]},
+                {"type": "bullet_list", "content": [{"type": "list_item", "content": [paragraph("c")]}]},
+            ]}))
+        );
+        assert_eq!(check(&stray), []);
+    }
+
+    #[test]
+    fn unknown_nodes_are_replaced_by_their_content() {
+        let mut unknown = doc(json!([
+            {"type": "callout", "content": [paragraph("kept")]},
+            {"type": "paragraph", "content": [{"type": "mention", "text": "@me"}]},
+        ]));
+        assert_eq!(
+            kinds(&check(&unknown)),
+            [
+                ("$.content.doc.content[0]", ViolationKind::UnknownNode),
+                (
+                    "$.content.doc.content[1].content[0]",
+                    ViolationKind::UnknownNode
+                ),
+            ]
+        );
+        fix(&mut unknown);
+        assert_eq!(
+            unknown.content.doc.content,
+            [node(paragraph("kept")), node(paragraph("@me"))]
+        );
+    }
+
+    #[test]
+    fn structural_violations_need_fix_to_load() {
+        let path = Path::new("a.flm");
+        for content in [
+            json!([{"type": "callout"}]),
+            json!([{"type": "text", "text": "stray"}]),
+            json!([{"type": "paragraph", "content": [{"type": "text", "text": "a", "marks": [{"type": "glow"}]}]}]),
+        ] {
+            let mut structural = doc(content);
+            let before = structural.clone();
+            let violations = check(&structural);
+            assert!(violations.iter().any(|v| v.kind.is_structural()));
+            match conform(path, &mut structural, false) {
+                Err(FlowError::InvalidSchema {
+                    violations: reported,
+                    ..
+                }) => {
+                    assert_eq!(reported, violations)
+                }
+                other => panic!("expected InvalidSchema, got {:?}", other),
+            }
+            assert_eq!(structural, before);
+            assert_eq!(conform(path, &mut structural, true).unwrap(), violations);
+            assert_eq!(check(&structural), []);
+        }
+    }
+
+    #[test]
+    fn other_violations_are_fixed_on_load() {
+        let mut untidy = doc(json!([
+            {"type": "heading", "attrs": {"level": 9}, "content": [{"type": "text", "text": "h"}]},
+            {"type": "paragraph", "content": [
+                {"type": "text", "text": ""},
+                {"type": "text", "text": "a", "marks": [{"type": "em"}, {"type": "em"}]},
+                {"type": "text", "text": "b", "marks": [{"type": "link"}]},
+                {"type": "note_ref"},
+            ]},
+            {"type": "code_block", "content": [{"type": "text", "text": "c", "marks": [{"type": "strong"}]}]},
+            {"type": "bullet_list"},
+        ]));
+        let violations = conform(Path::new("a.flm"), &mut untidy, false).unwrap();
+        assert_eq!(
+            kinds(&violations),
+            [
+                (
+                    "$.content.doc.content[0].attrs.level",
+                    ViolationKind::InvalidAttribute
+                ),
+                (
+                    "$.content.doc.content[1].content[0]",
+                    ViolationKind::InvalidText
+                ),
+                (
+                    "$.content.doc.content[1].content[1].marks[1]",
+                    ViolationKind::InvalidMark
+                ),
+                (
+                    "$.content.doc.content[1].content[2].marks[0].attrs.href",
+                    ViolationKind::InvalidAttribute
+                ),
+                (
+                    "$.content.doc.content[1].content[3].attrs.noteId",
+                    ViolationKind::InvalidAttribute
+                ),
+                (
+                    "$.content.doc.content[2].content[0].marks[0]",
+                    ViolationKind::InvalidMark
+                ),
+                ("$.content.doc.content[3]", ViolationKind::MissingContent),
+            ]
+        );
+        assert!(violations.iter().all(|v| !v.kind.is_structural()));
+        assert_eq!(
+            untidy.content.doc,
+            node(json!({"type": "doc", "content": [
+                {"type": "heading", "attrs": {"level": 6}, "content": [{"type": "text", "text": "h"}]},
+                {"type": "paragraph", "content": [
+                    {"type": "text", "text": "a", "marks": [{"type": "em"}]},
+                    {"type": "text", "text": "b"},
+                ]},
+                {"type": "code_block", "content": [{"type": "text", "text": "c"}]},
+                {"type": "bullet_list", "content": [{"type": "list_item", "content": [{"type": "paragraph"}]}]},
+            ]}))
+        );
+        assert_eq!(check(&untidy), []);
+    }
+}
@@ -0,0 +1,741 @@
+//! Full-text search over folders of .flm documents.
+//!
+//! The text of every block and note is split into lowercase terms (CJK
+//! ideographs and kana one character per term, since they aren't separated
+//! by spaces) and stored in an inverted index with term positions, which is
+//! what phrase queries match against. The index is a single JSON file in the
+//! app data directory. It is brought up to date before every search: only
+//! files whose size or modification time changed are read again.
+//!
+//! Queries are a list of clauses that must all match: plain terms,
+//! `"quoted phrases"` and `prefix*` terms.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::atomic;
+use crate::crypto;
+use crate::document::FlowmarkDocument;
+use crate::error::FlowError;
+use crate::export;
+use crate::flm;
+
+/// Bumped whenever the stored layout or the tokenizer changes.
+const INDEX_VERSION: u32 = 1;
+/// Characters of context shown on each side of the first match.
+const SNIPPET_CONTEXT: usize = 60;
+/// BM25 parameters.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Summary of an indexing run.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexStats {
+    /// Documents in the index afterwards.
+    pub files: usize,
+    /// Documents read because they were new or changed.
+    pub updated: usize,
+    /// Documents dropped because they were deleted or can't be read.
+    pub removed: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub score: f64,
+    /// HTML-escaped context around the first match, matches in `<mark>`.
+    pub snippet: String,
+    /// Set when the snippet comes from a note rather than the body.
+    pub note_id: Option<String>,
+}
+
+/// Search index shared by all commands.
+pub struct SearchIndex {
+    file: PathBuf,
+    index: Mutex<Index>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    version: u32,
+    folders: BTreeSet<PathBuf>,
+    next_id: u32,
+    documents: BTreeMap<u32, Record>,
+    /// Term -> document id -> positions of the term in that document.
+    postings: BTreeMap<String, BTreeMap<u32, Vec<u32>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    path: PathBuf,
+    modified: u64,
+    size: u64,
+    title: Option<String>,
+    /// Number of terms, for length normalisation.
+    length: u32,
+    segments: Vec<Segment>,
+}
+
+/// A text block or note. Positions are counted across the whole document,
+/// with a gap between segments so phrases never span two of them.
+#[derive(Debug, Serialize, Deserialize)]
+struct Segment {
+    text: String,
+    note_id: Option<String>,
+    /// Position of the segment's first term.
+    start: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Clause {
+    Term(String),
+    Prefix(String),
+    Phrase(Vec<String>),
+}
+
+struct Token {
+    term: String,
+    start: usize,
+    end: usize,
+}
+
+impl SearchIndex {
+    /// Load the index stored at `file`. A missing, unreadable or outdated
+    /// index starts out empty.
+    pub fn open(file: impl Into<PathBuf>) -> Self {
+        let file = file.into();
+        let index = match fs::read(&file) {
+            Ok(bytes) => match serde_json::from_slice::<Index>(&bytes) {
+                Ok(index) if index.version == INDEX_VERSION => index,
+                Ok(_) => Index::default(),
+                Err(e) => {
+                    log::warn!(
+                        "Discarding unreadable search index {}: {}",
+                        file.display(),
+                        e
+                    );
+                    Index::default()
+                }
+            },
+            Err(_) => Index::default(),
+        };
+        SearchIndex {
+            file,
+            index: Mutex::new(index),
+        }
+    }
+
+    /// Add `folder` to the indexed folders and bring it up to date.
+    pub fn index_folder(&self, folder: &Path) -> Result<IndexStats, FlowError> {
+        let folder = folder
+            .canonicalize()
+            .map_err(|e| FlowError::io(folder, e))?;
+        if !folder.is_dir() {
+            return Err(FlowError::invalid(format!(
+                "Not a folder: {}",
+                folder.display()
+            )));
+        }
+        let mut index = self.lock()?;
+        let mut changed = index.folders.insert(folder.clone());
+        let mut stats = index.refresh(&folder)?;
+        changed |= stats.updated + stats.removed > 0;
+        stats.files = index.documents.len();
+        if changed {
+            self.save(&index)?;
+        }
+        Ok(stats)
+    }
+
+    /// Stop indexing `folder` and drop its documents.
+    pub fn remove_folder(&self, folder: &Path) -> Result<(), FlowError> {
+        let folder = folder
+            .canonicalize()
+            .unwrap_or_else(|_| folder.to_path_buf());
+        let mut index = self.lock()?;
+        if !index.folders.remove(&folder) {
+            return Ok(());
+        }
+        let ids: Vec<u32> = index
+            .documents
+            .iter()
+            .filter(|(_, record)| index.is_stale(&record.path))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in ids {
+            index.remove(id);
+        }
+        self.save(&index)
+    }
+
+    /// Re-index whatever changed in the indexed folders.
+    pub fn refresh(&self) -> Result<IndexStats, FlowError> {
+        let mut index = self.lock()?;
+        let mut stats = IndexStats::default();
+        for folder in index.folders.clone() {
+            let folder_stats = index.refresh(&folder)?;
+            stats.updated += folder_stats.updated;
+            stats.removed += folder_stats.removed;
+        }
+        stats.files = index.documents.len();
+        if stats.updated + stats.removed > 0 {
+            self.save(&index)?;
+        }
+        Ok(stats)
+    }
+
+    /// The `limit` best matches for `query`, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, FlowError> {
+        self.refresh()?;
+        let clauses = parse_query(query);
+        if clauses.is_empty() {
+            return Ok(Vec::new());
+        }
+        let index = self.lock()?;
+        Ok(index.search(&clauses, limit))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Index>, FlowError> {
+        self.index
+            .lock()
+            .map_err(|_| FlowError::other("Search index is unavailable"))
+    }
+
+    fn save(&self, index: &Index) -> Result<(), FlowError> {
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir).map_err(|e| FlowError::io(dir, e))?;
+        }
+        let json = serde_json::to_vec(index)
+            .map_err(|e| FlowError::other(format!("Failed to serialize search index: {}", e)))?;
+        atomic::write(&self.file, &json)
+    }
+}
+
+impl Index {
+    /// Bring the documents under `folder` up to date with the file system.
+    fn refresh(&mut self, folder: &Path) -> Result<IndexStats, FlowError> {
+        self.version = INDEX_VERSION;
+        let mut stats = IndexStats::default();
+
+        let mut files = HashMap::new();
+        if folder.is_dir() {
+            collect_files(folder, &mut files);
+        }
+
+        let known: HashMap<PathBuf, u32> = self
+            .documents
+            .iter()
+            .filter(|(_, record)| record.path.starts_with(folder))
+            .map(|(id, record)| (record.path.clone(), *id))
+            .collect();
+        for (path, id) in &known {
+            if !files.contains_key(path) {
+                self.remove(*id);
+                stats.removed += 1;
+            }
+        }
+
+        for (path, (modified, size)) in files {
+            let existing = known.get(&path).copied();
+            if let Some(id) = existing {
+                let record = &self.documents[&id];
+                if record.modified == modified && record.size == size {
+                    continue;
+                }
+                self.remove(id);
+            }
+            // The index is stored in plain text
+            if crypto::is_encrypted(&path) {
+                if existing.is_some() {
+                    stats.removed += 1;
+                }
+                continue;
+            }
+            match flm::read_document(&path) {
+                Ok(doc) => {
+                    self.add(path, modified, size, &doc);
+                    stats.updated += 1;
+                }
+                Err(e) => {
+                    log::warn!("Not indexing {}: {}", path.display(), e);
+                    if existing.is_some() {
+                        stats.removed += 1;
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    fn add(&mut self, path: PathBuf, modified: u64, size: u64, doc: &FlowmarkDocument) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let texts = doc.content.doc.textblocks();
+        let notes = doc
+            .notes
+            .iter()
+            .map(|note| (note.content.clone(), Some(note.note_id.clone())));
+        let texts = texts.into_iter().map(|text| (text, None)).chain(notes);
+
+        let mut position = 0u32;
+        let mut segments = Vec::new();
+        for (text, note_id) in texts {
+            let tokens = tokenize(&text);
+            if tokens.is_empty() {
+                continue;
+            }
+            segments.push(Segment {
+                text: text.clone(),
+                note_id,
+                start: position,
+            });
+            for token in tokens {
+                self.postings
+                    .entry(token.term)
+                    .or_default()
+                    .entry(id)
+                    .or_default()
+                    .push(position);
+                position += 1;
+            }
+            position += 1;
+        }
+
+        self.documents.insert(
+            id,
+            Record {
+                path,
+                modified,
+                size,
+                title: export::html::document_title(doc),
+                length: position,
+                segments,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: u32) {
+        let Some(record) = self.documents.remove(&id) else {
+            return;
+        };
+        let terms: HashSet<String> = record
+            .segments
+            .iter()
+            .flat_map(|segment| tokenize(&segment.text))
+            .map(|token| token.term)
+            .collect();
+        for term in terms {
+            if let Some(documents) = self.postings.get_mut(&term) {
+                documents.remove(&id);
+                if documents.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Whether `path` is no longer inside any indexed folder.
+    fn is_stale(&self, path: &Path) -> bool {
+        !self.folders.iter().any(|folder| path.starts_with(folder))
+    }
+
+    fn search(&self, clauses: &[Clause], limit: usize) -> Vec<SearchHit> {
+        let total = self.documents.len() as f64;
+        let average_length = self
+            .documents
+            .values()
+            .map(|record| record.length as f64)
+            .sum::<f64>()
+            / total.max(1.0);
+
+        // Every clause must match; positions are kept for the snippets
+        let mut matches: Option<HashMap<u32, Vec<(u32, u32)>>> = None;
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+        for clause in clauses {
+            let (span, found) = self.matches(clause);
+            let idf = (1.0 + (total - found.len() as f64 + 0.5) / (found.len() as f64 + 0.5)).ln();
+            for (id, positions) in &found {
+                let tf = positions.len() as f64;
+                let length = self.documents[id].length as f64;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(*id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+            matches = Some(match matches {
+                None => found
+                    .into_iter()
+                    .map(|(id, positions)| (id, positions.into_iter().map(|p| (p, span)).collect()))
+                    .collect(),
+                Some(mut previous) => {
+                    previous.retain(|id, _| found.contains_key(id));
+                    for (id, spans) in previous.iter_mut() {
+                        spans.extend(found[id].iter().map(|p| (*p, span)));
+                    }
+                    previous
+                }
+            });
+        }
+
+        let mut hits: Vec<SearchHit> = matches
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, mut spans)| {
+                spans.sort_unstable();
+                let record = &self.documents[&id];
+                let (snippet, note_id) = snippet(record, &spans);
+                SearchHit {
+                    path: record.path.clone(),
+                    title: record.title.clone(),
+                    score: scores[&id],
+                    snippet,
+                    note_id,
+                }
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        hits.truncate(limit);
+        hits
+    }
+
+    /// Documents matching `clause` with the start positions of each match,
+    /// along with the number of terms a match spans.
+    fn matches(&self, clause: &Clause) -> (u32, HashMap<u32, Vec<u32>>) {
+        let mut found: HashMap<u32, Vec<u32>> = HashMap::new();
+        match clause {
+            Clause::Term(term) => {
+                if let Some(documents) = self.postings.get(term) {
+                    found.extend(documents.iter().map(|(id, p)| (*id, p.clone())));
+                }
+                (1, found)
+            }
+            Clause::Prefix(prefix) => {
+                let terms = self
+                    .postings
+                    .range(prefix.clone()..)
+                    .take_while(|(term, _)| term.starts_with(prefix.as_str()));
+                for (_, documents) in terms {
+                    for (id, positions) in documents {
+                        found.entry(*id).or_default().extend(positions);
+                    }
+                }
+                for positions in found.values_mut() {
+                    positions.sort_unstable();
+                }
+                (1, found)
+            }
+            Clause::Phrase(terms) => {
+                let postings: Option<Vec<_>> = terms.iter().map(|t| self.postings.get(t)).collect();
+                let Some(postings) = postings else {
+                    return (terms.len() as u32, found);
+                };
+                for (id, first) in postings[0] {
+                    let starts: Vec<u32> = first
+                        .iter()
+                        .copied()
+                        .filter(|start| {
+                            postings[1..].iter().enumerate().all(|(offset, documents)| {
+                                documents.get(id).is_some_and(|positions| {
+                                    positions
+                                        .binary_search(&(start + offset as u32 + 1))
+                                        .is_ok()
+                                })
+                            })
+                        })
+                        .collect();
+                    if !starts.is_empty() {
+                        found.insert(*id, starts);
+                    }
+                }
+                (terms.len() as u32, found)
+            }
+        }
+    }
+}
+
+/// Context around the first match in `spans` (start position, term count),
+/// with every match in the same segment highlighted.
+fn snippet(record: &Record, spans: &[(u32, u32)]) -> (String, Option<String>) {
+    let Some(&(first, _)) = spans.first() else {
+        return (String::new(), None);
+    };
+    let index = record
+        .segments
+        .partition_point(|segment| segment.start <= first)
+        .saturating_sub(1);
+    let Some(segment) = record.segments.get(index) else {
+        return (String::new(), None);
+    };
+    let tokens = tokenize(&segment.text);
+    let end = segment.start + tokens.len() as u32;
+
+    // Byte ranges of the matches in this segment
+    let ranges: Vec<(usize, usize)> = spans
+        .iter()
+        .filter(|(start, _)| (segment.start..end).contains(start))
+        .filter_map(|(start, length)| {
+            let first = (start - segment.start) as usize;
+            let last = (first + *length as usize - 1).min(tokens.len() - 1);
+            Some((tokens.get(first)?.start, tokens[last].end))
+        })
+        .collect();
+    let text = &segment.text;
+    let (match_start, match_end) = ranges.first().copied().unwrap_or((0, 0));
+
+    let window_start = text[..match_start]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let window_end = text[match_end..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT)
+        .map(|(i, _)| match_end + i)
+        .unwrap_or(text.len());
+
+    let mut html = String::new();
+    if window_start > 0 {
+        html.push('…');
+    }
+    let mut cursor = window_start;
+    for (start, end) in ranges {
+        if start < cursor || end > window_end {
+            continue;
+        }
+        html.push_str(&export::html::escape(&text[cursor..start]));
+        html.push_str("<mark>");
+        html.push_str(&export::html::escape(&text[start..end]));
+        html.push_str("</mark>");
+        cursor = end;
+    }
+    html.push_str(&export::html::escape(&text[cursor..window_end]));
+    if window_end < text.len() {
+        html.push('…');
+    }
+    (html, segment.note_id.clone())
+}
+
+/// Split a query into clauses. Quoted text is a phrase, a trailing `*`
+/// makes a prefix, and words that split into several terms (such as CJK
+/// runs) are matched as phrases.
+fn parse_query(query: &str) -> Vec<Clause> {
+    let mut clauses = Vec::new();
+    let mut rest = query;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let (word, quoted) = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or_default();
+            (&quoted[..end], true)
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+            (word, false)
+        };
+
+        let mut terms: Vec<String> = tokenize(word).into_iter().map(|t| t.term).collect();
+        match terms.len() {
+            0 => {}
+            1 if !quoted && word.ends_with('*') => clauses.push(Clause::Prefix(terms.remove(0))),
+            1 => clauses.push(Clause::Term(terms.remove(0))),
+            _ => clauses.push(Clause::Phrase(terms)),
+        }
+    }
+    clauses
+}
+
+/// Lowercase terms of `text` with their byte ranges.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    for (i, c) in text.char_indices() {
+        let end = i + c.len_utf8();
+        if is_cjk(c) {
+            tokens.extend(current.take());
+            tokens.push(Token {
+                term: c.to_string(),
+                start: i,
+                end,
+            });
+        } else if c.is_alphanumeric() {
+            let token = current.get_or_insert_with(|| Token {
+                term: String::new(),
+                start: i,
+                end,
+            });
+            token.term.extend(c.to_lowercase());
+            token.end = end;
+        } else {
+            tokens.extend(current.take());
+        }
+    }
+    tokens.extend(current);
+    tokens
+}
+
+/// Scripts written without spaces between words.
+pub(crate) fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF       // Hiragana, Katakana
+        | 0x3400..=0x4DBF     // CJK Extension A
+        | 0x4E00..=0x9FFF     // CJK Unified Ideographs
+        | 0xF900..=0xFAFF     // CJK Compatibility Ideographs
+        | 0x20000..=0x2FFFF) // CJK Extensions B and later
+}
+
+/// `.flm` files under `folder` with their modification time and size,
+/// skipping hidden directories.
+fn collect_files(folder: &Path, files: &mut HashMap<PathBuf, (u64, u64)>) {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if meta.is_dir() {
+            if !hidden {
+                collect_files(&path, files);
+            }
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("flm"))
+        {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_millis() as u64);
+            files.insert(path, (modified, meta.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{Node, NodeKind};
+
+    fn terms(text: &str) -> Vec<String> {
+        tokenize(text).into_iter().map(|token| token.term).collect()
+    }
+
+    fn term(text: &str) -> String {
+        text.to_string()
+    }
+
+    #[test]
+    fn tokenize_lowercases_words_with_their_ranges() {
+        let tokens = tokenize("Hello, Wörld 42!");
+        let found: Vec<(&str, usize, usize)> = tokens
+            .iter()
+            .map(|token| (token.term.as_str(), token.start, token.end))
+            .collect();
+        assert_eq!(found, [("hello", 0, 5), ("wörld", 7, 13), ("42", 14, 16)]);
+        assert_eq!(terms("ΣΊΣΥΦΟΣ don't"), ["σίσυφοσ", "don", "t"]);
+        assert!(terms(" \t—…!").is_empty());
+    }
+
+    #[test]
+    fn tokenize_splits_cjk_into_characters() {
+        assert_eq!(terms("東京タワーへ"), ["東", "京", "タ", "ワ", "ー", "へ"]);
+        assert_eq!(terms("Rust语言v2"), ["rust", "语", "言", "v2"]);
+        let tokens = tokenize("a東");
+        assert_eq!((tokens[1].start, tokens[1].end), (1, 4));
+        // Hangul is written with spaces
+        assert_eq!(terms("한국어 문장"), ["한국어", "문장"]);
+    }
+
+    #[test]
+    fn parse_query_clauses() {
+        assert_eq!(
+            parse_query(r#"  Rust "Safe Code" async* "#),
+            [
+                Clause::Term(term("rust")),
+                Clause::Phrase(vec![term("safe"), term("code")]),
+                Clause::Prefix(term("async")),
+            ]
+        );
+        // Quoted single words and prefixes are plain terms
+        assert_eq!(parse_query(r#""word*""#), [Clause::Term(term("word"))]);
+        // Words that split into several terms are phrases
+        assert_eq!(
+            parse_query("東京"),
+            [Clause::Phrase(vec![term("東"), term("京")])]
+        );
+        assert_eq!(
+            parse_query("e-mail"),
+            [Clause::Phrase(vec![term("e"), term("mail")])]
+        );
+        // An unclosed quote runs to the end
+        assert_eq!(
+            parse_query(r#"a "b c"#),
+            [
+                Clause::Term(term("a")),
+                Clause::Phrase(vec![term("b"), term("c")])
+            ]
+        );
+        assert!(parse_query(r#"* "" -- "#).is_empty());
+    }
+
+    #[test]
+    fn search_finds_terms_phrases_and_prefixes() {
+        let dir = std::env::temp_dir().join(format!("flowmark-search-{}", std::process::id()));
+        let folder = dir.join("docs");
+        fs::create_dir_all(&folder).unwrap();
+        let write = |name: &str, texts: &[&str]| {
+            let blocks = texts
+                .iter()
+                .map(|text| Node::new(NodeKind::Paragraph).with_content(vec![Node::text(*text)]))
+                .collect();
+            let doc = FlowmarkDocument::new(Node::new(NodeKind::Doc).with_content(blocks), vec![]);
+            flm::write_document(folder.join(name), &doc).unwrap();
+        };
+        write(
+            "a.flm",
+            &["The quick brown fox", "jumps over <the> lazy dog"],
+        );
+        write("b.flm", &["Brown bread", "A quick note on 東京"]);
+
+        let index = SearchIndex::open(dir.join("index.json"));
+        assert_eq!(index.index_folder(&folder).unwrap().files, 2);
+        let found = |query: &str| -> Vec<String> {
+            let mut names: Vec<String> = index
+                .search(query, 10)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.path.file_name().unwrap().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(found("quick"), ["a.flm", "b.flm"]);
+        // Every clause must match, anywhere in the document
+        assert_eq!(found("quick brown"), ["a.flm", "b.flm"]);
+        assert_eq!(found("quick fox"), ["a.flm"]);
+        assert_eq!(found(r#""brown fox""#), ["a.flm"]);
+        assert_eq!(found(r#""fox brown""#), Vec::<String>::new());
+        // Phrases don't span blocks
+        assert_eq!(found(r#""fox jumps""#), Vec::<String>::new());
+        assert_eq!(found("bre*"), ["b.flm"]);
+        assert_eq!(found("京"), ["b.flm"]);
+
+        let hit = &index.search("lazy", 10).unwrap()[0];
+        assert_eq!(hit.snippet, "jumps over &lt;the&gt; <mark>lazy</mark> dog");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
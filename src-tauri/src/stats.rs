@@ -0,0 +1,240 @@
+//! Length statistics of a document, for manuscript targets.
+//!
+//! Words are runs of letters and digits, joined by apostrophes and hyphens
+//! ("don't", "well-known"). Scripts written without spaces are counted the
+//! way word processors do: every CJK character is a word. Notes are counted
+//! separately and left out of the body totals and the reading time.
+
+use serde::Serialize;
+
+use crate::document::{FlowmarkDocument, Node, NodeKind};
+use crate::search::is_cjk;
+
+/// Reading speed for text written with spaces between words.
+const WORDS_PER_MINUTE: f64 = 230.0;
+/// Reading speed for CJK text, in characters.
+const CJK_CHARACTERS_PER_MINUTE: f64 = 500.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Counts {
+    pub words: usize,
+    /// Characters including whitespace; hard breaks count as one.
+    pub characters: usize,
+    pub characters_no_spaces: usize,
+    /// Non-empty paragraphs, including those in lists and quotes.
+    pub paragraphs: usize,
+    /// Estimated reading time in seconds.
+    pub reading_time: u64,
+    /// Words that are CJK characters, part of `words`.
+    #[serde(skip)]
+    cjk_words: usize,
+}
+
+/// A heading and the blocks up to the next heading of any level.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SectionStats {
+    /// Heading text, `None` for the blocks before the first heading.
+    pub heading: Option<String>,
+    pub level: Option<u8>,
+    /// Index of the heading among the document's top-level blocks.
+    pub index: usize,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentStats {
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub headings: usize,
+    pub notes: usize,
+    pub note_words: usize,
+    pub sections: Vec<SectionStats>,
+}
+
+/// Statistics of `doc`.
+pub fn document_stats(doc: &FlowmarkDocument) -> DocumentStats {
+    let mut sections: Vec<SectionStats> = Vec::new();
+    let mut headings = 0;
+    for (index, block) in doc.content.doc.content.iter().enumerate() {
+        if block.kind == NodeKind::Heading {
+            headings += 1;
+            sections.push(SectionStats {
+                heading: Some(block.text_content()),
+                level: Some(block.level()),
+                index,
+                counts: Counts::default(),
+            });
+        }
+        if sections.is_empty() {
+            sections.push(SectionStats {
+                heading: None,
+                level: None,
+                index,
+                counts: Counts::default(),
+            });
+        }
+        let section = sections.last_mut().expect("a section was just added");
+        section.counts.add_block(block);
+    }
+
+    let mut counts = Counts::default();
+    for section in &mut sections {
+        section.counts.finish();
+        counts.add(&section.counts);
+    }
+    counts.finish();
+
+    DocumentStats {
+        counts,
+        headings,
+        notes: doc.notes.len(),
+        note_words: doc
+            .notes
+            .iter()
+            .map(|note| count_words(&note.content).0)
+            .sum(),
+        sections,
+    }
+}
+
+impl Counts {
+    fn add_block(&mut self, block: &Node) {
+        for text in block.textblocks() {
+            let (words, cjk_words) = count_words(&text);
+            self.words += words;
+            self.cjk_words += cjk_words;
+            self.characters += text.chars().count();
+            self.characters_no_spaces += text.chars().filter(|c| !c.is_whitespace()).count();
+        }
+        block.walk(&mut |node| {
+            if node.kind == NodeKind::Paragraph && !node.text_content().trim().is_empty() {
+                self.paragraphs += 1;
+            }
+        });
+    }
+
+    fn add(&mut self, other: &Counts) {
+        self.words += other.words;
+        self.cjk_words += other.cjk_words;
+        self.characters += other.characters;
+        self.characters_no_spaces += other.characters_no_spaces;
+        self.paragraphs += other.paragraphs;
+    }
+
+    fn finish(&mut self) {
+        let minutes = (self.words - self.cjk_words) as f64 / WORDS_PER_MINUTE
+            + self.cjk_words as f64 / CJK_CHARACTERS_PER_MINUTE;
+        self.reading_time = (minutes * 60.0).round() as u64;
+    }
+}
+
+/// Words in `text`, and how many of them are CJK characters.
+fn count_words(text: &str) -> (usize, usize) {
+    let (mut words, mut cjk) = (0, 0);
+    let mut in_word = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if is_cjk(c) {
+            words += 1;
+            cjk += 1;
+            in_word = false;
+        } else if c.is_alphanumeric() {
+            if !in_word {
+                words += 1;
+                in_word = true;
+            }
+        } else if in_word && matches!(c, '\'' | '’' | '-' | '‐') {
+            // Joins only when a letter or digit follows
+            in_word = chars
+                .peek()
+                .is_some_and(|next| next.is_alphanumeric() && !is_cjk(*next));
+        } else {
+            in_word = false;
+        }
+    }
+    (words, cjk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Note;
+
+    fn paragraph(text: &str) -> Node {
+        Node::new(NodeKind::Paragraph).with_content(vec![Node::text(text)])
+    }
+
+    fn heading(text: &str, level: u8) -> Node {
+        Node::new(NodeKind::Heading)
+            .with_attr("level", level)
+            .with_content(vec![Node::text(text)])
+    }
+
+    #[test]
+    fn words_join_over_apostrophes_and_hyphens() {
+        assert_eq!(count_words("Don't stop the well-known show"), (5, 0));
+        assert_eq!(count_words("it’s a state‐of‐the‐art 2-step"), (4, 0));
+        // Only between letters or digits
+        assert_eq!(count_words("end - start, trailing- 'quoted'"), (4, 0));
+        assert_eq!(count_words("  \n\t "), (0, 0));
+        assert_eq!(count_words("naïve café 42"), (3, 0));
+    }
+
+    #[test]
+    fn every_cjk_character_is_a_word() {
+        assert_eq!(count_words("日本語"), (3, 3));
+        assert_eq!(count_words("我爱Rust语言。"), (5, 4));
+        assert_eq!(count_words("ひらがなとカタカナ"), (9, 9));
+        // A hyphen doesn't join a CJK character to a word
+        assert_eq!(count_words("top-東京"), (3, 2));
+        // Hangul is written with spaces
+        assert_eq!(count_words("한국어 문장"), (2, 0));
+    }
+
+    #[test]
+    fn sections_notes_and_reading_time() {
+        let blocks = vec![
+            paragraph("Before the first heading."),
+            heading("One", 1),
+            paragraph("Two words"),
+            paragraph(""),
+            heading("Two", 2),
+            Node::new(NodeKind::Blockquote).with_content(vec![paragraph("日本語")]),
+        ];
+        let notes = vec![Note {
+            note_id: "n1".to_string(),
+            content: "Three note words".to_string(),
+            number: 1,
+        }];
+        let stats = document_stats(&FlowmarkDocument::new(
+            Node::new(NodeKind::Doc).with_content(blocks),
+            notes,
+        ));
+
+        let sections: Vec<(Option<&str>, Option<u8>, usize, usize)> = stats
+            .sections
+            .iter()
+            .map(|s| (s.heading.as_deref(), s.level, s.index, s.counts.words))
+            .collect();
+        assert_eq!(
+            sections,
+            [
+                (None, None, 0, 4),
+                (Some("One"), Some(1), 1, 3),
+                (Some("Two"), Some(2), 4, 4),
+            ]
+        );
+        assert_eq!(stats.headings, 2);
+        assert_eq!((stats.notes, stats.note_words), (1, 3));
+        assert_eq!(stats.counts.words, 11);
+        assert_eq!(stats.counts.paragraphs, 3);
+        assert_eq!(stats.counts.characters, 25 + 3 + 9 + 3 + 3);
+        // 8 words at 230 a minute and 3 characters at 500
+        let seconds = (8.0 / WORDS_PER_MINUTE + 3.0 / CJK_CHARACTERS_PER_MINUTE) * 60.0;
+        assert_eq!(stats.counts.reading_time, seconds.round() as u64);
+    }
+}
@@ -0,0 +1,189 @@
+//! Detection of changes made to open documents by other programs.
+//!
+//! Each open file is tracked with the SHA-256 of its content as last loaded
+//! or saved by the app. Writes go through [`OpenFiles::write`], which
+//! refuses to overwrite a file whose content no longer matches, so a sync
+//! client's or another editor's changes aren't silently lost. The parent
+//! directories are watched (the files themselves are replaced on every
+//! save) and a change is reported once per new content.
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error::FlowError;
+use crate::files::{normalize, sha256_hex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileChangeKind {
+    Modified,
+    Deleted,
+}
+
+/// An open file was changed on disk by someone else.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: FileChangeKind,
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    /// Hash of the content the app last loaded or wrote, `None` if missing.
+    known: Option<String>,
+    /// Hash of the last content reported as a change, to report it once.
+    reported: Option<Option<String>>,
+}
+
+type Files = Arc<Mutex<HashMap<PathBuf, Tracked>>>;
+
+/// Open files shared by all commands.
+pub struct OpenFiles {
+    files: Files,
+    watcher: Mutex<RecommendedWatcher>,
+}
+
+impl OpenFiles {
+    /// Start watching, calling `on_change` for external changes to tracked files.
+    pub fn new<F>(on_change: F) -> Result<Self, FlowError>
+    where
+        F: Fn(FileChange) + Send + 'static,
+    {
+        let files: Files = Arc::default();
+        let watched = files.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log::warn!("File watcher error: {}", e);
+                    return;
+                }
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            let Ok(mut files) = watched.lock() else {
+                return;
+            };
+            for path in event.paths {
+                let Some(tracked) = files.get_mut(&path) else {
+                    continue;
+                };
+                let current = hash_file(&path);
+                if current == tracked.known || tracked.reported.as_ref() == Some(&current) {
+                    continue;
+                }
+                let kind = match current {
+                    Some(_) => FileChangeKind::Modified,
+                    None => FileChangeKind::Deleted,
+                };
+                tracked.reported = Some(current);
+                on_change(FileChange { path, kind });
+            }
+        })
+        .map_err(|e| FlowError::other(format!("Failed to start file watcher: {}", e)))?;
+
+        Ok(OpenFiles {
+            files,
+            watcher: Mutex::new(watcher),
+        })
+    }
+
+    /// Run `load`, which reads the file at `path`, and track the file if it
+    /// succeeds. The content is hashed before it is read, so a change made
+    /// in between is reported as a conflict by the next write.
+    pub fn load<T, F>(&self, path: &Path, load: F) -> Result<T, FlowError>
+    where
+        F: FnOnce() -> Result<T, FlowError>,
+    {
+        let path = normalize(path);
+        let known = hash_file(&path);
+        let result = load()?;
+        let tracked = Tracked {
+            known,
+            reported: None,
+        };
+        let added = self.lock()?.insert(path.clone(), tracked).is_none();
+        if added {
+            self.watch_parent(&path);
+        }
+        Ok(result)
+    }
+
+    /// Stop tracking `path`, e.g. when its document was closed.
+    pub fn untrack(&self, path: &Path) -> Result<(), FlowError> {
+        let path = normalize(path);
+        let (removed, siblings) = {
+            let mut files = self.lock()?;
+            let removed = files.remove(&path).is_some();
+            let siblings = files.keys().any(|other| other.parent() == path.parent());
+            (removed, siblings)
+        };
+        if let (true, false, Some(parent)) = (removed, siblings, path.parent()) {
+            if let Ok(mut watcher) = self.watcher.lock() {
+                let _ = watcher.unwatch(parent);
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `write`, which replaces the file at `path`, unless the file was
+    /// changed by someone else since the app last loaded or wrote it. With
+    /// `force` the check is skipped. Afterwards the file is tracked with its
+    /// new content.
+    pub fn write<T, F>(&self, path: &Path, force: bool, write: F) -> Result<T, FlowError>
+    where
+        F: FnOnce() -> Result<T, FlowError>,
+    {
+        let path = normalize(path);
+        // Hold the lock across the write so the watcher doesn't take our
+        // own write for an external one
+        let (result, added) = {
+            let mut files = self.lock()?;
+            if let Some(tracked) = files.get(&path) {
+                let current = hash_file(&path);
+                if !force && current.is_some() && current != tracked.known {
+                    return Err(FlowError::ExternalChange { path });
+                }
+            }
+            let result = write()?;
+            let tracked = Tracked {
+                known: hash_file(&path),
+                reported: None,
+            };
+            (result, files.insert(path.clone(), tracked).is_none())
+        };
+        if added {
+            self.watch_parent(&path);
+        }
+        Ok(result)
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<PathBuf, Tracked>>, FlowError> {
+        self.files
+            .lock()
+            .map_err(|_| FlowError::other("File tracking is unavailable"))
+    }
+
+    /// Must not be called with `files` locked: the watcher may be waiting
+    /// on it while delivering an event.
+    fn watch_parent(&self, path: &Path) {
+        let Some(parent) = path.parent() else {
+            return;
+        };
+        if let Ok(mut watcher) = self.watcher.lock() {
+            if let Err(e) = watcher.watch(parent, RecursiveMode::NonRecursive) {
+                log::warn!("Failed to watch {}: {}", parent.display(), e);
+            }
+        }
+    }
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    fs::read(path).ok().map(|bytes| sha256_hex(&bytes))
+}
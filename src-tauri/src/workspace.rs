@@ -0,0 +1,377 @@
+//! Project folders ("workspaces") holding many documents.
+//!
+//! A workspace is a folder of .flm and Markdown files, e.g. the chapters of
+//! a book. Project settings live in `.flowmarker/settings.json` inside it,
+//! and the folder is watched so the frontend can follow files being added,
+//! changed or removed outside the app.
+
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::atomic;
+use crate::error::FlowError;
+use crate::export;
+use crate::flm;
+
+pub const SETTINGS_DIR: &str = ".flowmarker";
+const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSettings {
+    /// Display name; defaults to the folder name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Paths relative to the workspace root in reading order. Listed entries
+    /// come first in the tree, the rest follow by name.
+    #[serde(default)]
+    pub order: Vec<String>,
+    /// Other settings stored by the frontend, kept as they are.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FileFormat {
+    Flm,
+    Markdown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TreeEntry {
+    Folder {
+        name: String,
+        path: PathBuf,
+        children: Vec<TreeEntry>,
+    },
+    File {
+        name: String,
+        path: PathBuf,
+        format: FileFormat,
+        /// Text of the first heading.
+        title: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceInfo {
+    pub root: PathBuf,
+    pub name: String,
+    pub settings: WorkspaceSettings,
+    pub tree: Vec<TreeEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+    Renamed,
+}
+
+/// Files or folders of the workspace changed on disk.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceChange {
+    pub kind: ChangeKind,
+    pub paths: Vec<PathBuf>,
+}
+
+/// An open workspace. Changes are reported until it is dropped.
+pub struct Workspace {
+    root: PathBuf,
+    _watcher: RecommendedWatcher,
+}
+
+impl Workspace {
+    /// Open the folder at `root`, calling `on_change` for every relevant
+    /// change below it.
+    pub fn open<F>(root: &Path, on_change: F) -> Result<Self, FlowError>
+    where
+        F: Fn(WorkspaceChange) + Send + 'static,
+    {
+        let root = root.canonicalize().map_err(|e| FlowError::io(root, e))?;
+        if !root.is_dir() {
+            return Err(FlowError::invalid(format!(
+                "Not a folder: {}",
+                root.display()
+            )));
+        }
+
+        let watched_root = root.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        log::warn!("Workspace watcher error: {}", e);
+                        return;
+                    }
+                };
+                let kind = match event.kind {
+                    EventKind::Create(_) => ChangeKind::Created,
+                    EventKind::Modify(ModifyKind::Name(_)) => ChangeKind::Renamed,
+                    EventKind::Modify(ModifyKind::Metadata(_)) => return,
+                    EventKind::Modify(_) => ChangeKind::Modified,
+                    EventKind::Remove(_) => ChangeKind::Removed,
+                    _ => return,
+                };
+                let paths: Vec<PathBuf> = event
+                    .paths
+                    .into_iter()
+                    .filter(|path| is_relevant(&watched_root, path))
+                    .collect();
+                if !paths.is_empty() {
+                    on_change(WorkspaceChange { kind, paths });
+                }
+            })
+            .map_err(|e| FlowError::other(format!("Failed to watch {}: {}", root.display(), e)))?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| FlowError::other(format!("Failed to watch {}: {}", root.display(), e)))?;
+
+        Ok(Workspace {
+            root,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Settings and current file tree of the workspace.
+    pub fn info(&self) -> Result<WorkspaceInfo, FlowError> {
+        let settings = read_settings(&self.root)?;
+        let name = settings.name.clone().unwrap_or_else(|| {
+            self.root
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        Ok(WorkspaceInfo {
+            tree: scan(&self.root, &settings),
+            root: self.root.clone(),
+            name,
+            settings,
+        })
+    }
+
+    pub fn save_settings(&self, settings: &WorkspaceSettings) -> Result<(), FlowError> {
+        write_settings(&self.root, settings)
+    }
+}
+
+/// Settings stored in the workspace at `root`, or the defaults.
+pub fn read_settings(root: &Path) -> Result<WorkspaceSettings, FlowError> {
+    let path = root.join(SETTINGS_DIR).join(SETTINGS_FILE);
+    match fs::read_to_string(&path) {
+        Ok(json) => {
+            serde_json::from_str(&json).map_err(|e| FlowError::json(path.display().to_string(), e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(WorkspaceSettings::default()),
+        Err(e) => Err(FlowError::io(path, e)),
+    }
+}
+
+pub fn write_settings(root: &Path, settings: &WorkspaceSettings) -> Result<(), FlowError> {
+    let dir = root.join(SETTINGS_DIR);
+    fs::create_dir_all(&dir).map_err(|e| FlowError::io(&dir, e))?;
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| FlowError::other(format!("Failed to serialize settings: {}", e)))?;
+    atomic::write(dir.join(SETTINGS_FILE), json.as_bytes())
+}
+
+/// Documents and the folders containing them below `root`, in the order
+/// given by `settings`. Hidden entries and empty folders are left out.
+pub fn scan(root: &Path, settings: &WorkspaceSettings) -> Vec<TreeEntry> {
+    scan_folder(root, root, settings)
+}
+
+fn scan_folder(root: &Path, folder: &Path, settings: &WorkspaceSettings) -> Vec<TreeEntry> {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return Vec::new();
+    };
+    let mut tree = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            let children = scan_folder(root, &path, settings);
+            if !children.is_empty() {
+                tree.push(TreeEntry::Folder {
+                    name,
+                    path,
+                    children,
+                });
+            }
+        } else if let Some(format) = file_format(&path) {
+            tree.push(TreeEntry::File {
+                title: read_title(&path, format),
+                name,
+                path,
+                format,
+            });
+        }
+    }
+
+    let rank = |entry: &TreeEntry| {
+        let relative = entry.path().strip_prefix(root).ok().map(slash_path);
+        relative
+            .and_then(|relative| settings.order.iter().position(|p| *p == relative))
+            .unwrap_or(usize::MAX)
+    };
+    tree.sort_by(|a, b| {
+        rank(a)
+            .cmp(&rank(b))
+            .then_with(|| b.is_folder().cmp(&a.is_folder()))
+            .then_with(|| natural_cmp(a.name(), b.name()))
+    });
+    tree
+}
+
+impl TreeEntry {
+    pub fn path(&self) -> &Path {
+        match self {
+            TreeEntry::Folder { path, .. } | TreeEntry::File { path, .. } => path,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            TreeEntry::Folder { name, .. } | TreeEntry::File { name, .. } => name,
+        }
+    }
+
+    fn is_folder(&self) -> bool {
+        matches!(self, TreeEntry::Folder { .. })
+    }
+}
+
+fn file_format(path: &Path) -> Option<FileFormat> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "flm" => Some(FileFormat::Flm),
+        "md" | "markdown" => Some(FileFormat::Markdown),
+        _ => None,
+    }
+}
+
+/// Text of the first heading of the document at `path`.
+fn read_title(path: &Path, format: FileFormat) -> Option<String> {
+    match format {
+        FileFormat::Flm => flm::read_document(path)
+            .ok()
+            .and_then(|doc| export::html::document_title(&doc)),
+        FileFormat::Markdown => markdown_title(&fs::read_to_string(path).ok()?),
+    }
+}
+
+/// First ATX heading outside front matter and fenced code.
+fn markdown_title(markdown: &str) -> Option<String> {
+    let mut lines = markdown.lines().peekable();
+    if lines.peek().map(|line| line.trim_end()) == Some("---") {
+        lines.next();
+        lines
+            .by_ref()
+            .find(|line| matches!(line.trim_end(), "---" | "..."));
+    }
+    let mut fence: Option<&str> = None;
+    for line in lines {
+        let trimmed = line.trim_start();
+        if let Some(open) = fence {
+            if trimmed.starts_with(open) {
+                fence = None;
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+            continue;
+        }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if !(1..=6).contains(&level) {
+            continue;
+        }
+        let rest = &trimmed[level..];
+        if !rest.is_empty() && !rest.starts_with([' ', '\t']) {
+            continue;
+        }
+        // Strip an optional closing sequence of #s
+        let title = rest.trim();
+        let title = match title.trim_end_matches('#') {
+            stripped if stripped.is_empty() || stripped.ends_with([' ', '\t']) => stripped.trim(),
+            _ => title,
+        };
+        if !title.is_empty() {
+            return Some(title.to_string());
+        }
+    }
+    None
+}
+
+/// Whether a change to `path` matters to the workspace tree.
+fn is_relevant(root: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let hidden = relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+    // Removed folders can't be told apart from files without an extension
+    !hidden && (file_format(path).is_some() || path.extension().is_none())
+}
+
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Case-insensitive comparison that orders digit runs by value, so
+/// "Chapter 2" sorts before "Chapter 10".
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x: String = std::iter::from_fn(|| a.next_if(char::is_ascii_digit)).collect();
+                let y: String = std::iter::from_fn(|| b.next_if(char::is_ascii_digit)).collect();
+                let x = x.trim_start_matches('0');
+                let y = y.trim_start_matches('0');
+                let order = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_lowercase().cmp(y.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}